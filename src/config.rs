@@ -1,16 +1,76 @@
-use super::dns_provider::dynv6_com::ConfigDynv6Com;
-use super::dns_provider::name_com::ConfigNameCom;
+use super::control::ConfigControl;
+use super::dns_provider::ConfigDnsTarget;
+use super::hooks::ConfigHooks;
+use super::http::{ConfigBind, ConfigHttp};
+use super::logger::LogFormat;
+use super::metrics::ConfigMetrics;
+use super::mqtt::ConfigMqtt;
+use super::notify::ConfigNotify;
+use super::policy::ConfigPolicy;
+use super::prefix_delegation::ConfigPrefixDelegation;
+use super::round_robin::ConfigRoundRobin;
 use serde::{Deserialize, Serialize};
 use std::{error::Error, fs, path::Path};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
-    pub dns_provider: Vec<String>,
+    #[serde(default)]
     pub ip_provider: Vec<String>,
     pub interval: u32,
+    /// Allow publishing private, CGNAT, loopback and other non-global addresses, it is off by default.
+    #[serde(default)]
+    pub allow_non_global: bool,
+    /// The policy to decide whether a detected IP should be published.
+    #[serde(default)]
+    pub policy: ConfigPolicy,
+    /// Publish addresses of LAN hosts under the delegated IPv6 prefix.
+    pub prefix_delegation: Option<ConfigPrefixDelegation>,
+    /// Detect the public IP of each uplink and publish it independently.
+    #[serde(default)]
+    pub wan: Vec<ConfigWan>,
+    /// Publish the IPs of all healthy WANs to one hostname as multi-value records.
+    #[serde(default)]
+    pub round_robin: Vec<ConfigRoundRobin>,
 
-    pub name_com: Option<ConfigNameCom>,
-    pub dynv6_com: Option<ConfigDynv6Com>,
+    /// The format of logs, `text` or `json`.
+    pub log_format: Option<LogFormat>,
+    /// Save the last published IPs to this file on exit, so they are not published again after restart.
+    pub state_file: Option<String>,
+    /// How many seconds to wait for in-flight updates on exit, 10 by default.
+    pub shutdown_timeout: Option<u64>,
+    /// Expose Prometheus metrics over HTTP.
+    pub metrics: Option<ConfigMetrics>,
+    /// Serve the HTTP API to control DWD at runtime.
+    pub control: Option<ConfigControl>,
+    /// Send notifications when the IP changes or updates fail.
+    #[serde(default)]
+    pub notify: ConfigNotify,
+    /// Publish the state to an MQTT broker, with Home Assistant discovery.
+    pub mqtt: Option<ConfigMqtt>,
+    /// Run commands when the IP changes or DNS updates finish.
+    #[serde(default)]
+    pub hooks: ConfigHooks,
+
+    /// The timeouts, retries, proxy and user agent of HTTP requests.
+    #[serde(default)]
+    pub http: ConfigHttp,
+
+    #[serde(flatten)]
+    pub bind: ConfigBind,
+    #[serde(flatten)]
+    pub dns: ConfigDnsTarget,
+}
+
+/// The config of an uplink, the bind address or interface is used for both IP detection and DNS updates.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigWan {
+    pub name: String,
+    pub ip_provider: Vec<String>,
+
+    #[serde(flatten)]
+    pub bind: ConfigBind,
+    #[serde(flatten)]
+    pub dns: ConfigDnsTarget,
 }
 
 pub fn load_config(config_path: &str) -> Result<Config, Box<dyn Error>> {
@@ -23,7 +83,7 @@ pub fn load_config(config_path: &str) -> Result<Config, Box<dyn Error>> {
                 config_path
                     .to_str()
                     .expect("The file path should be valid utf-8 string."),
-                e.to_string()
+                e
             )
             .into())
         }
@@ -33,19 +93,19 @@ pub fn load_config(config_path: &str) -> Result<Config, Box<dyn Error>> {
         Some(Some("json")) => match serde_json::from_str(&config_str) {
             Ok(config) => config,
             Err(err) => {
-                return Err(format!("[config::load_config] Parse json failed. (error: {})", err.to_string()).into());
+                return Err(format!("[config::load_config] Parse json failed. (error: {})", err).into());
             }
         },
         Some(Some("toml")) => match toml::from_str(&config_str) {
             Ok(config) => config,
             Err(err) => {
-                return Err(format!("[config::load_config] Parse toml failed. (error: {})", err.to_string()).into());
+                return Err(format!("[config::load_config] Parse toml failed. (error: {})", err).into());
             }
         },
         Some(Some("yaml")) => match serde_yaml::from_str(&config_str) {
             Ok(config) => config,
             Err(err) => {
-                return Err(format!("[config::load_config] Parse yaml failed. (error: {})", err.to_string()).into());
+                return Err(format!("[config::load_config] Parse yaml failed. (error: {})", err).into());
             }
         },
         _ => return Err("[config::load_config] Only .json, .toml and .yaml format is supported.".into()),
@@ -53,3 +113,48 @@ pub fn load_config(config_path: &str) -> Result<Config, Box<dyn Error>> {
 
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_config_should_support_all_formats() {
+        for path in [
+            "config/config_exampl.json",
+            "config/config_exampl.toml",
+            "config/config_exampl.yaml",
+        ] {
+            let ret = load_config(path);
+            assert!(ret.is_ok(), "{}: {}", path, ret.unwrap_err());
+
+            let config = ret.unwrap();
+            assert!(!config.dns.dns_provider.is_empty());
+            assert!(config.dns.name_com.is_some());
+        }
+    }
+
+    #[test]
+    fn config_should_support_multi_wan() {
+        let config: Config = toml::from_str(
+            r#"
+            ip_provider = ["myip.la"]
+            interval = 300
+            bind_interface = "eth0"
+
+            [[wan]]
+            name = "wan2"
+            ip_provider = ["vnet.one"]
+            bind_address = "192.168.2.2"
+            dns_provider = ["dynv6.com"]
+            dynv6_com = { zone = "wan2.dynv6.net" }
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.dns.dns_provider.is_empty());
+        assert_eq!(config.bind.bind_interface, Some(String::from("eth0")));
+        assert_eq!(config.wan[0].bind.bind_address, "192.168.2.2".parse().ok());
+        assert_eq!(config.wan[0].dns.dynv6_com.as_ref().unwrap().zone, "wan2.dynv6.net");
+    }
+}
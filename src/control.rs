@@ -0,0 +1,315 @@
+//! The HTTP API to control DWD at runtime, e.g. from home automation.
+//!
+//! All endpoints except `/healthz` require the header `Authorization: Bearer <token>`.
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    env,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+use tokio::{net::TcpListener, sync::watch};
+
+use super::error::DwdError;
+use super::http_server::{self, Request, Response};
+use super::shutdown;
+use super::state;
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref STATUS: Mutex<Status> = Mutex::new(Status::default());
+    /// Wake up the loops to run a cycle immediately.
+    static ref TRIGGER: watch::Sender<Trigger> = watch::channel(Trigger::default()).0;
+}
+
+#[cfg(test)]
+lazy_static! {
+    /// The tests which touch the global trigger or pause state run one by one.
+    pub static ref TEST_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigControl {
+    /// The address to serve the API on, e.g. `127.0.0.1:9899`.
+    pub listen: String,
+    /// The bearer token of the API, or set it through env variable DWD_CONTROL_TOKEN.
+    pub token: Option<String>,
+}
+
+impl ConfigControl {
+    pub fn token(&self) -> Result<String, DwdError> {
+        match self.token.as_ref() {
+            Some(val) if !val.is_empty() => Ok(val.to_owned()),
+            _ => env::var("DWD_CONTROL_TOKEN")
+                .ok()
+                .filter(|val| !val.is_empty())
+                .ok_or_else(|| DwdError::config("Please set control.token or env variable DWD_CONTROL_TOKEN.")),
+        }
+    }
+}
+
+/// The requests to run a cycle, `forced` counts the ones which publish the IP even if it is not changed.
+#[derive(Debug, Clone, Copy, Default)]
+struct Trigger {
+    forced: u64,
+}
+
+/// Receive the requests to run a cycle, a forced request is kept even if a normal one follows before it is received.
+pub struct TriggerReceiver {
+    receiver: watch::Receiver<Trigger>,
+    forced: u64,
+}
+
+impl TriggerReceiver {
+    /// Wait for the next request, return whether any request since the last one is forced.
+    pub async fn changed(&mut self) -> Option<bool> {
+        self.receiver.changed().await.ok()?;
+        let forced = self.receiver.borrow_and_update().forced;
+        let force = forced > self.forced;
+        self.forced = forced;
+        Some(force)
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Status {
+    jobs: BTreeMap<String, JobStatus>,
+    ip_providers: BTreeMap<String, ProviderHealth>,
+    dns_providers: BTreeMap<String, ProviderHealth>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct JobStatus {
+    /// The last IP detected by the job.
+    ip: Option<IpAddr>,
+    healthy: bool,
+    checked_at: Option<DateTime<Utc>>,
+    published_ip: Option<IpAddr>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct ProviderHealth {
+    healthy: bool,
+    /// The credentials are rejected, the provider is not updated until restart.
+    disabled: bool,
+    last_error: Option<String>,
+    checked_at: Option<DateTime<Utc>>,
+}
+
+impl ProviderHealth {
+    fn report(&mut self, error: Option<String>) {
+        self.healthy = error.is_none();
+        self.last_error = error.or_else(|| self.last_error.take());
+        self.checked_at = Some(Utc::now());
+    }
+}
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+/// Receive the requests to run a cycle immediately.
+pub fn subscribe() -> TriggerReceiver {
+    let receiver = TRIGGER.subscribe();
+    let forced = receiver.borrow().forced;
+    TriggerReceiver { receiver, forced }
+}
+
+/// Ask the loops to run a cycle immediately.
+pub fn trigger(force: bool) {
+    TRIGGER.send_modify(|trigger| trigger.forced += force as u64);
+}
+
+/// Report the IP of a job, `None` means the detection failed or the IP is rejected.
+pub fn report_job(key: &str, ip: Option<IpAddr>) {
+    let mut status = STATUS.lock().expect("The lock of status should not be poisoned.");
+    let job = status.jobs.entry(key.to_owned()).or_default();
+    job.ip = ip.or(job.ip);
+    job.healthy = ip.is_some();
+    job.checked_at = Some(Utc::now());
+}
+
+pub fn report_ip_provider(name: &str, error: Option<String>) {
+    let mut status = STATUS.lock().expect("The lock of status should not be poisoned.");
+    status.ip_providers.entry(name.to_owned()).or_default().report(error);
+}
+
+pub fn report_dns_provider(name: &str, error: Option<String>) {
+    let mut status = STATUS.lock().expect("The lock of status should not be poisoned.");
+    status.dns_providers.entry(name.to_owned()).or_default().report(error);
+}
+
+pub fn report_dns_provider_disabled(name: &str) {
+    let mut status = STATUS.lock().expect("The lock of status should not be poisoned.");
+    status.dns_providers.entry(name.to_owned()).or_default().disabled = true;
+}
+
+fn status() -> serde_json::Value {
+    let mut status = STATUS.lock().expect("The lock of status should not be poisoned.");
+    for (key, job) in status.jobs.iter_mut() {
+        if let Some((ip, updated_at)) = state::published_at(key) {
+            job.published_ip = Some(ip);
+            job.published_at = Some(updated_at);
+        }
+    }
+
+    json!({
+        "paused": is_paused(),
+        "jobs": status.jobs,
+        "ip_providers": status.ip_providers,
+        "dns_providers": status.dns_providers,
+    })
+}
+
+/// Compare the tokens in constant time, so the token could not be guessed by timing.
+fn is_authorized(request: &Request, token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+    let actual = request
+        .headers
+        .get("authorization")
+        .map(String::as_str)
+        .unwrap_or_default();
+    actual.len() == expected.len()
+        && actual
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Whether the update is forced by `?force=true` or the body `{"force": true}`.
+fn is_forced(request: &Request) -> bool {
+    let by_query = matches!(request.query.get("force").map(String::as_str), Some("true") | Some("1"));
+    let by_body = serde_json::from_str::<serde_json::Value>(&request.body)
+        .ok()
+        .and_then(|val| val.get("force").and_then(|val| val.as_bool()))
+        .unwrap_or(false);
+    by_query || by_body
+}
+
+fn handle(request: Request, token: &str) -> Response {
+    if request.path == "/healthz" {
+        return match (request.method.as_str(), shutdown::is_shutting_down()) {
+            ("GET", false) => Response::text(200, "ok"),
+            ("GET", true) => Response::text(503, "shutting down"),
+            _ => Response::text(405, "Method not allowed"),
+        };
+    }
+
+    if !is_authorized(&request, token) {
+        return Response::json(401, json!({ "error": "unauthorized" }));
+    }
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/status") => Response::json(200, status()),
+        ("POST", "/update") => {
+            if is_paused() {
+                return Response::json(409, json!({ "error": "updates are paused" }));
+            }
+            let force = is_forced(&request);
+            info!(event = "update_triggered", force = force; "An update is triggered through the control API.");
+            trigger(force);
+            Response::json(202, json!({ "triggered": true, "force": force }))
+        }
+        ("POST", "/pause") => {
+            if !PAUSED.swap(true, Ordering::SeqCst) {
+                info!(event = "paused"; "Updates are paused through the control API.");
+            }
+            Response::json(200, json!({ "paused": true }))
+        }
+        ("POST", "/resume") => {
+            if PAUSED.swap(false, Ordering::SeqCst) {
+                info!(event = "resumed"; "Updates are resumed through the control API.");
+            }
+            Response::json(200, json!({ "paused": false }))
+        }
+        (_, "/status") | (_, "/update") | (_, "/pause") | (_, "/resume") => {
+            Response::json(405, json!({ "error": "method not allowed" }))
+        }
+        _ => Response::json(404, json!({ "error": "not found" })),
+    }
+}
+
+/// Serve the API on the listener.
+pub async fn serve(listener: TcpListener, token: String) {
+    http_server::serve(listener, move |request: Request| {
+        let response = handle(request, &token);
+        async move { response }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str, token: Option<&str>) -> Request {
+        let mut request = Request {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            ..Default::default()
+        };
+        if let Some(token) = token {
+            request
+                .headers
+                .insert(String::from("authorization"), format!("Bearer {}", token));
+        }
+        request
+    }
+
+    #[test]
+    fn handle_should_require_token() {
+        assert_eq!(handle(request("GET", "/healthz", None), "secret").status, 200);
+        assert_eq!(handle(request("GET", "/status", None), "secret").status, 401);
+        assert_eq!(handle(request("GET", "/status", Some("secreT")), "secret").status, 401);
+        assert_eq!(handle(request("GET", "/status", Some("secret")), "secret").status, 200);
+        assert_eq!(handle(request("GET", "/update", Some("secret")), "secret").status, 405);
+        assert_eq!(handle(request("GET", "/unknown", Some("secret")), "secret").status, 404);
+    }
+
+    /// Resume updates when a test ends, so the other tests are not paused whatever the order is.
+    struct ResumeOnDrop;
+
+    impl Drop for ResumeOnDrop {
+        fn drop(&mut self) {
+            PAUSED.store(false, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_should_pause_resume_and_trigger() {
+        let _serial = TEST_LOCK.lock().await;
+        let _resume = ResumeOnDrop;
+        let mut receiver = subscribe();
+
+        assert_eq!(handle(request("POST", "/pause", Some("secret")), "secret").status, 200);
+        assert!(is_paused());
+        assert_eq!(handle(request("POST", "/update", Some("secret")), "secret").status, 409);
+        assert!(!receiver.receiver.has_changed().unwrap());
+
+        assert_eq!(handle(request("POST", "/resume", Some("secret")), "secret").status, 200);
+        assert!(!is_paused());
+        let mut update = request("POST", "/update", Some("secret"));
+        update.query.insert(String::from("force"), String::from("true"));
+        assert_eq!(handle(update, "secret").status, 202);
+        assert!(receiver.receiver.has_changed().unwrap());
+        assert_eq!(receiver.changed().await, Some(true));
+
+        // A normal request does not overwrite the pending forced one.
+        trigger(true);
+        trigger(false);
+        assert_eq!(receiver.changed().await, Some(true));
+        trigger(false);
+        assert_eq!(receiver.changed().await, Some(false));
+    }
+}
@@ -0,0 +1,428 @@
+use chrono::Utc;
+use log::trace;
+use openssl::{base64, hash::MessageDigest, rand::rand_bytes};
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::env;
+use std::net::IpAddr;
+
+use super::super::error::{DwdError, ErrorKind};
+use super::super::http::HttpClient;
+use super::super::util::{error_style, info_style};
+use super::signature::{hex, hmac, uri_encode};
+use super::{ips_of_type, plan_record_set};
+
+const DEFAULT_ENDPOINT: &str = "https://alidns.aliyuncs.com/";
+const API_VERSION: &str = "2015-01-09";
+/// The minimum TTL of the free edition.
+const DEFAULT_TTL: u32 = 600;
+/// The records of one host never fill a page, so only the first page is read.
+const PAGE_SIZE: &str = "500";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigAlidns {
+    pub domain: String,
+    /// The host record (RR), `@` for the domain itself.
+    pub record_host: Option<String>,
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// The AccessKey, or set them through env variables ALIDNS_ACCESS_KEY_ID and ALIDNS_ACCESS_KEY_SECRET.
+    pub access_key_id: Option<String>,
+    pub access_key_secret: Option<String>,
+    /// The API endpoint, `https://alidns.aliyuncs.com/` by default.
+    pub endpoint: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiError {
+    code: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DescribeDomainRecords {
+    domain_records: DomainRecords,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DomainRecords {
+    record: Vec<Record>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Record {
+    record_id: String,
+    #[serde(rename = "RR")]
+    rr: String,
+    #[serde(rename = "Type")]
+    record_type: String,
+    value: String,
+}
+
+/// The response of the actions changing records.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RecordId {
+    record_id: String,
+}
+
+/// Update DNS record on Alibaba Cloud DNS
+///
+/// The document of Alidns API: https://help.aliyun.com/document_detail/29739.html
+///
+/// # Example:
+/// ```rust
+/// alidns::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigAlidns, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    update_inner(conf, client, ip)
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+async fn update_inner(conf: &ConfigAlidns, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+    let records = find_records(conf, client, record_type).await?;
+    let value = ip.to_string();
+
+    match records.first() {
+        // Updating a record with the same value is rejected as `DomainRecordDuplicate`.
+        Some(record) if record.value == value => trace!("Record {} is up to date", record.record_id),
+        Some(record) => {
+            let ret: RecordId = call(
+                conf,
+                client,
+                "UpdateDomainRecord",
+                &[
+                    ("RecordId", record.record_id.to_owned()),
+                    ("RR", record_host(conf).to_owned()),
+                    ("Type", record_type.to_owned()),
+                    ("Value", value),
+                    ("TTL", conf.record_ttl.unwrap_or(DEFAULT_TTL).to_string()),
+                ],
+            )
+            .await?;
+            trace!("Update record {} to: {}", ret.record_id, ip);
+        }
+        None => {
+            let ret = add_record(conf, client, record_type, ip).await?;
+            trace!("Create record {} with: {}", ret.record_id, ip);
+        }
+    }
+
+    Ok(())
+}
+
+/// Make the records of the host contain exactly the given IPs, one record per IP.
+///
+/// Only the IPs matching the configured `record_type` are published, so A and AAAA records need separated configs.
+pub async fn update_set(conf: &ConfigAlidns, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    update_set_inner(conf, client, ips)
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+async fn update_set_inner(conf: &ConfigAlidns, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let ips = match ips_of_type(record_type, ips)? {
+        Some(val) => val,
+        None => return Ok(()),
+    };
+
+    let records = find_records(conf, client, record_type)
+        .await?
+        .into_iter()
+        .map(|record| (record.record_id, record.value))
+        .collect();
+    let (stale, missing) = plan_record_set(records, &ips);
+
+    for ip in missing {
+        add_record(conf, client, record_type, &ip).await?;
+    }
+    for record_id in stale {
+        let _: RecordId = call(conf, client, "DeleteDomainRecord", &[("RecordId", record_id)]).await?;
+    }
+
+    Ok(())
+}
+
+pub fn record_name(conf: &ConfigAlidns) -> String {
+    match record_host(conf) {
+        "@" => conf.domain.to_owned(),
+        host => format!("{}.{}", host, conf.domain),
+    }
+}
+
+fn record_host(conf: &ConfigAlidns) -> &str {
+    match conf.record_host.as_deref() {
+        Some(host) if !host.is_empty() => host,
+        _ => "@",
+    }
+}
+
+/// Find the records of the host and type, `RRKeyWord` matches fuzzily so the host is compared again.
+async fn find_records(conf: &ConfigAlidns, client: &HttpClient, record_type: &str) -> Result<Vec<Record>, DwdError> {
+    let ret: DescribeDomainRecords = call(
+        conf,
+        client,
+        "DescribeDomainRecords",
+        &[
+            ("DomainName", conf.domain.to_owned()),
+            ("RRKeyWord", record_host(conf).to_owned()),
+            ("Type", record_type.to_owned()),
+            ("PageSize", PAGE_SIZE.to_owned()),
+        ],
+    )
+    .await?;
+    let records = ret
+        .domain_records
+        .record
+        .into_iter()
+        .filter(|record| record.rr == record_host(conf) && record.record_type == record_type)
+        .collect::<Vec<_>>();
+
+    trace!("Find records from Alidns: {:?}", records);
+
+    Ok(records)
+}
+
+async fn add_record(
+    conf: &ConfigAlidns,
+    client: &HttpClient,
+    record_type: &str,
+    ip: &IpAddr,
+) -> Result<RecordId, DwdError> {
+    call(
+        conf,
+        client,
+        "AddDomainRecord",
+        &[
+            ("DomainName", conf.domain.to_owned()),
+            ("RR", record_host(conf).to_owned()),
+            ("Type", record_type.to_owned()),
+            ("Value", ip.to_string()),
+            ("TTL", conf.record_ttl.unwrap_or(DEFAULT_TTL).to_string()),
+        ],
+    )
+    .await
+}
+
+/// Call an action of the RPC API, the request is signed in the query.
+async fn call<T: DeserializeOwned>(
+    conf: &ConfigAlidns,
+    client: &HttpClient,
+    action: &str,
+    params: &[(&str, String)],
+) -> Result<T, DwdError> {
+    let (access_key_id, access_key_secret) = credentials(conf)?;
+    let mut nonce = [0; 16];
+    rand_bytes(&mut nonce).map_err(|e| DwdError::config(format!("Generate the nonce failed: {}", e)))?;
+
+    let mut query = vec![
+        (String::from("Action"), action.to_owned()),
+        (String::from("Format"), String::from("JSON")),
+        (String::from("Version"), String::from(API_VERSION)),
+        (String::from("AccessKeyId"), access_key_id),
+        (String::from("SignatureMethod"), String::from("HMAC-SHA1")),
+        (String::from("SignatureVersion"), String::from("1.0")),
+        (String::from("SignatureNonce"), hex(&nonce)),
+        (
+            String::from("Timestamp"),
+            Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        ),
+    ];
+    query.extend(params.iter().map(|(key, val)| (key.to_string(), val.to_owned())));
+    sign(&mut query, &access_key_secret)?;
+    let mut url = Url::parse(conf.endpoint.as_deref().unwrap_or(DEFAULT_ENDPOINT))?;
+    url.query_pairs_mut().extend_pairs(query);
+
+    let response = client.send(client.get(url)).await?;
+    let status = response.status();
+    trace!("GET {} {}", action, status);
+
+    let text = response.text().await?;
+    trace!("Response: {}", text);
+    if !status.is_success() {
+        let err = serde_json::from_str::<ApiError>(&text).map_err(|_| {
+            DwdError::from_status(
+                status,
+                format!("API response error: {} {}", error_style(status), error_style(&text)),
+            )
+        })?;
+        let message = format!(
+            "API response error: {} {}: {}",
+            error_style(status),
+            err.code,
+            err.message
+        );
+        return Err(match err.code.as_str() {
+            "InvalidAccessKeyId.NotFound"
+            | "InvalidAccessKeyId.Inactive"
+            | "SignatureDoesNotMatch"
+            | "IncompleteSignature"
+            | "Forbidden.RAM" => DwdError::new(ErrorKind::Auth, message),
+            code if code.starts_with("Throttling") => DwdError::new(ErrorKind::RateLimit, message),
+            "InvalidDomainName.NoExist" => DwdError::new(ErrorKind::NotFound, message),
+            _ => DwdError::from_status(status, message),
+        });
+    }
+
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Sort the parameters and append the signature of them.
+///
+/// The document of the signature: https://help.aliyun.com/document_detail/29747.html
+fn sign(query: &mut Vec<(String, String)>, access_key_secret: &str) -> Result<(), DwdError> {
+    query.sort();
+    let canonical_query = query
+        .iter()
+        .map(|(key, val)| format!("{}={}", uri_encode(key, false), uri_encode(val, false)))
+        .collect::<Vec<_>>()
+        .join("&");
+    let string_to_sign = format!("GET&%2F&{}", uri_encode(&canonical_query, false));
+    trace!("String to sign: {}", string_to_sign);
+
+    let signature = hmac(
+        MessageDigest::sha1(),
+        format!("{}&", access_key_secret).as_bytes(),
+        string_to_sign.as_bytes(),
+    )?;
+    query.push((String::from("Signature"), base64::encode_block(&signature)));
+
+    Ok(())
+}
+
+fn credentials(conf: &ConfigAlidns) -> Result<(String, String), DwdError> {
+    let access_key_id = match conf.access_key_id.as_ref() {
+        Some(val) => val.to_owned(),
+        None => env::var("ALIDNS_ACCESS_KEY_ID")
+            .map_err(|_| DwdError::config("Please set env variable ALIDNS_ACCESS_KEY_ID."))?,
+    };
+    let access_key_secret = match conf.access_key_secret.as_ref() {
+        Some(val) => val.to_owned(),
+        None => env::var("ALIDNS_ACCESS_KEY_SECRET")
+            .map_err(|_| DwdError::config("Please set env variable ALIDNS_ACCESS_KEY_SECRET."))?,
+    };
+
+    trace!("AccessKey ID: {:?}", info_style(&access_key_id));
+
+    Ok((access_key_id, access_key_secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn conf(server: &MockServer) -> ConfigAlidns {
+        ConfigAlidns {
+            domain: String::from("example.com"),
+            record_host: Some(String::from("home")),
+            record_ttl: None,
+            record_type: None,
+            access_key_id: Some(String::from("testid")),
+            access_key_secret: Some(String::from("testsecret")),
+            endpoint: Some(server.url.clone()),
+        }
+    }
+
+    #[test]
+    fn sign_should_match_document_example() {
+        let mut query = [
+            ("Format", "XML"),
+            ("AccessKeyId", "testid"),
+            ("Action", "DescribeDomainRecords"),
+            ("SignatureMethod", "HMAC-SHA1"),
+            ("DomainName", "example.com"),
+            ("SignatureNonce", "f59ed6a9-83fc-473b-9cc6-99c95df3856e"),
+            ("SignatureVersion", "1.0"),
+            ("Version", "2015-01-09"),
+            ("Timestamp", "2016-03-24T16:41:54Z"),
+        ]
+        .iter()
+        .map(|(key, val)| (key.to_string(), val.to_string()))
+        .collect::<Vec<_>>();
+        sign(&mut query, "testsecret").unwrap();
+
+        assert_eq!(
+            query.last().unwrap(),
+            &(String::from("Signature"), String::from("uRpHwaSEt3J+6KQD//svCh/x+pI="))
+        );
+    }
+
+    #[tokio::test]
+    async fn update_should_update_record_of_host() {
+        let server = MockServer::start(|request| {
+            if request.path.contains("Action=DescribeDomainRecords") {
+                MockResponse::json(
+                    200,
+                    serde_json::json!({
+                        "TotalCount": 2,
+                        "DomainRecords": {"Record": [
+                            {"RecordId": "1", "RR": "home2", "Type": "A", "Value": "1.1.1.1", "TTL": 600},
+                            {"RecordId": "2", "RR": "home", "Type": "A", "Value": "1.1.1.1", "TTL": 600}
+                        ]}
+                    }),
+                )
+            } else {
+                MockResponse::json(200, serde_json::json!({"RecordId": "2"}))
+            }
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update(&conf(&server), &client, &"2.2.2.2".parse().unwrap())
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        let query = Url::parse(&format!("http://localhost{}", requests[1].path)).unwrap();
+        let query = query.query_pairs().into_owned().collect::<Vec<_>>();
+        for pair in [
+            ("Action", "UpdateDomainRecord"),
+            ("RecordId", "2"),
+            ("RR", "home"),
+            ("Value", "2.2.2.2"),
+        ] {
+            assert!(query.contains(&(pair.0.to_owned(), pair.1.to_owned())), "{:?}", pair);
+        }
+        assert!(query.iter().any(|(key, _)| key == "Signature"));
+    }
+
+    #[tokio::test]
+    async fn update_should_classify_errors() {
+        let server = MockServer::start(|_| {
+            MockResponse::json(
+                404,
+                serde_json::json!({"RequestId": "x", "Code": "InvalidAccessKeyId.NotFound", "Message": "Oops"}),
+            )
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        let err = update(&conf(&server), &client, &"1.1.1.1".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Auth);
+        assert_eq!(err.record.as_deref(), Some("home.example.com"));
+    }
+
+    #[tokio::test]
+    async fn update_set_should_skip_empty_set() {
+        let server = MockServer::start(|_| MockResponse::json(200, serde_json::json!({"RequestId": "x"}))).await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update_set(&conf(&server), &client, &["2001:db8::1".parse().unwrap()])
+            .await
+            .unwrap();
+        assert!(server.requests().is_empty());
+    }
+}
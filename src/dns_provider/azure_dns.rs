@@ -0,0 +1,295 @@
+use log::trace;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{env, net::IpAddr};
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::super::util::{error_style, info_style};
+use super::{ips_of_type, oauth};
+
+const DEFAULT_AUTHORITY: &str = "https://login.microsoftonline.com";
+const DEFAULT_API_BASE: &str = "https://management.azure.com";
+const API_VERSION: &str = "2018-05-01";
+const DEFAULT_TTL: u32 = 300;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigAzureDns {
+    /// The directory (tenant) ID of the service principal.
+    pub tenant_id: String,
+    /// The application (client) ID of the service principal.
+    pub client_id: String,
+    /// The client secret, or set it through env variable AZURE_CLIENT_SECRET.
+    pub client_secret: Option<String>,
+    pub subscription_id: String,
+    pub resource_group: String,
+    /// The name of the DNS zone, e.g. `example.com`.
+    pub zone: String,
+    /// The name of the record relative to the zone, `@` for the zone itself.
+    pub record_host: Option<String>,
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// The Microsoft identity platform, `https://login.microsoftonline.com` by default.
+    pub authority: Option<String>,
+    /// The Azure Resource Manager endpoint, `https://management.azure.com` by default.
+    pub api_base: Option<String>,
+}
+
+/// Update DNS record on Azure DNS
+///
+/// The document of Azure DNS API: https://learn.microsoft.com/en-us/rest/api/dns/record-sets/create-or-update
+///
+/// # Example:
+/// ```rust
+/// azure_dns::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigAzureDns, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+    put_record_set(conf, client, record_type, &[*ip])
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+/// Replace the record set with the IPs of `record_type`, the IPs of the other family are ignored.
+pub async fn update_set(conf: &ConfigAzureDns, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let update_set_inner = async {
+        match ips_of_type(record_type, ips)? {
+            Some(ips) => put_record_set(conf, client, record_type, &ips).await,
+            None => Ok(()),
+        }
+    };
+    update_set_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+pub fn record_name(conf: &ConfigAzureDns) -> String {
+    match record_host(conf) {
+        "@" => conf.zone.to_owned(),
+        host => format!("{}.{}", host, conf.zone),
+    }
+}
+
+fn record_host(conf: &ConfigAzureDns) -> &str {
+    match conf.record_host.as_deref() {
+        Some(host) if !host.is_empty() => host,
+        _ => "@",
+    }
+}
+
+/// Create or replace the record set, the PUT is idempotent so it is sent without looking up the record first.
+async fn put_record_set(
+    conf: &ConfigAzureDns,
+    client: &HttpClient,
+    record_type: &str,
+    ips: &[IpAddr],
+) -> Result<(), DwdError> {
+    let token = access_token(conf, client).await?;
+    let url = format!(
+        "{}/subscriptions/{}/resourceGroups/{}/providers/Microsoft.Network/dnsZones/{}/{}/{}",
+        api_base(conf),
+        conf.subscription_id,
+        conf.resource_group,
+        conf.zone,
+        record_type,
+        record_host(conf)
+    );
+    let body = match record_type {
+        "A" => json!({"properties": {
+            "TTL": conf.record_ttl.unwrap_or(DEFAULT_TTL),
+            "ARecords": ips.iter().map(|ip| json!({"ipv4Address": ip})).collect::<Vec<_>>(),
+        }}),
+        "AAAA" => json!({"properties": {
+            "TTL": conf.record_ttl.unwrap_or(DEFAULT_TTL),
+            "AAAARecords": ips.iter().map(|ip| json!({"ipv6Address": ip})).collect::<Vec<_>>(),
+        }}),
+        val => {
+            return Err(DwdError::config(format!(
+                "Invalid config.azure_dns.record_type {:?}, it should be A or AAAA.",
+                val
+            )))
+        }
+    };
+    trace!("Request body: {}", body);
+
+    let response = client
+        .send(
+            client
+                .put(&url)
+                .query(&[("api-version", API_VERSION)])
+                .json(&body)
+                .bearer_auth(&token),
+        )
+        .await?;
+    let status = response.status();
+    trace!("PUT {} {}", url, status);
+
+    if !status.is_success() {
+        if status == StatusCode::UNAUTHORIZED {
+            oauth::invalidate(&token_key(conf));
+        }
+        let (code, message) = match response.json::<Value>().await {
+            Ok(val) => (
+                val["error"]["code"].as_str().unwrap_or_default().to_owned(),
+                val["error"]["message"].as_str().unwrap_or_default().to_owned(),
+            ),
+            Err(e) => (String::new(), e.to_string()),
+        };
+        return Err(DwdError::from_status(
+            status,
+            format!("API response error: {} {}: {}", error_style(status), code, message),
+        ));
+    }
+
+    trace!("Update {} to: {:?}", record_name(conf), ips);
+
+    Ok(())
+}
+
+fn api_base(conf: &ConfigAzureDns) -> &str {
+    conf.api_base
+        .as_deref()
+        .unwrap_or(DEFAULT_API_BASE)
+        .trim_end_matches('/')
+}
+
+fn token_key(conf: &ConfigAzureDns) -> String {
+    format!(
+        "{}/{}/{}",
+        conf.authority.as_deref().unwrap_or(DEFAULT_AUTHORITY),
+        conf.tenant_id,
+        conf.client_id
+    )
+}
+
+/// Request an access token of Azure Resource Manager with the client credentials flow.
+///
+/// The document of the flow: https://learn.microsoft.com/en-us/entra/identity-platform/v2-oauth2-client-creds-grant-flow
+async fn access_token(conf: &ConfigAzureDns, client: &HttpClient) -> Result<String, DwdError> {
+    let client_secret = match conf.client_secret.as_ref() {
+        Some(val) => val.to_owned(),
+        None => env::var("AZURE_CLIENT_SECRET")
+            .map_err(|_| DwdError::config("Please set env variable AZURE_CLIENT_SECRET."))?,
+    };
+    trace!("Client ID: {:?}", info_style(&conf.client_id));
+
+    let url = format!(
+        "{}/{}/oauth2/v2.0/token",
+        conf.authority
+            .as_deref()
+            .unwrap_or(DEFAULT_AUTHORITY)
+            .trim_end_matches('/'),
+        conf.tenant_id
+    );
+    oauth::token(client, &token_key(conf), &url, || {
+        Ok(vec![
+            ("grant_type", String::from("client_credentials")),
+            ("client_id", conf.client_id.to_owned()),
+            ("client_secret", client_secret),
+            // The scope follows the endpoint, so the sovereign clouds work as well.
+            ("scope", format!("{}/.default", api_base(conf))),
+        ])
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn conf(server: &MockServer, tenant_id: &str) -> ConfigAzureDns {
+        ConfigAzureDns {
+            tenant_id: String::from(tenant_id),
+            client_id: String::from("client"),
+            client_secret: Some(String::from("secret")),
+            subscription_id: String::from("sub"),
+            resource_group: String::from("rg"),
+            zone: String::from("example.com"),
+            record_host: Some(String::from("home")),
+            record_ttl: Some(60),
+            record_type: Some(String::from("AAAA")),
+            authority: Some(server.url.clone()),
+            api_base: Some(server.url.clone()),
+        }
+    }
+
+    #[tokio::test]
+    async fn update_set_should_put_record_set() {
+        let server = MockServer::start(|request| {
+            if request.path.ends_with("/oauth2/v2.0/token") {
+                MockResponse::json(
+                    200,
+                    json!({"access_token": "t", "expires_in": 3599, "token_type": "Bearer"}),
+                )
+            } else {
+                MockResponse::json(200, json!({"name": "home"}))
+            }
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update_set(
+            &conf(&server, "tenant"),
+            &client,
+            &["1.1.1.1".parse().unwrap(), "2001:db8::1".parse().unwrap()],
+        )
+        .await
+        .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/tenant/oauth2/v2.0/token");
+        assert!(requests[0].body.contains("grant_type=client_credentials"));
+        assert!(requests[0].body.contains("client_secret=secret"));
+        assert_eq!(
+            requests[1].path,
+            "/subscriptions/sub/resourceGroups/rg/providers/Microsoft.Network/dnsZones/example.com/AAAA/home\
+             ?api-version=2018-05-01"
+        );
+        assert_eq!(requests[1].method, "PUT");
+        assert_eq!(requests[1].headers["authorization"], "Bearer t");
+        assert_eq!(
+            serde_json::from_str::<Value>(&requests[1].body).unwrap(),
+            json!({"properties": {"TTL": 60, "AAAARecords": [{"ipv6Address": "2001:db8::1"}]}})
+        );
+    }
+
+    #[tokio::test]
+    async fn update_set_should_skip_empty_set_and_refuse_other_types() {
+        let server = MockServer::start(|_| MockResponse::json(200, json!({"name": "home"}))).await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update_set(&conf(&server, "tenant"), &client, &["1.1.1.1".parse().unwrap()])
+            .await
+            .unwrap();
+
+        let mut conf = conf(&server, "tenant");
+        conf.record_type = Some(String::from("CNAME"));
+        let err = update_set(&conf, &client, &["1.1.1.1".parse().unwrap()])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Config);
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_should_classify_errors() {
+        let server = MockServer::start(|_| {
+            MockResponse::json(
+                401,
+                json!({"error": "invalid_client", "error_description": "AADSTS7000215: Invalid client secret."}),
+            )
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        let err = update(&conf(&server, "other"), &client, &"1.1.1.1".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Auth);
+        assert_eq!(err.record.as_deref(), Some("home.example.com"));
+    }
+}
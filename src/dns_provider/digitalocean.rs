@@ -0,0 +1,295 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::net::IpAddr;
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::rest::{self, ApiFuture, RecordApi, RestRecord};
+
+const BASE_URL: &str = "https://api.digitalocean.com/v2/";
+const DEFAULT_TTL: u32 = 300;
+const MIN_TTL: u32 = 30;
+const PAGE_SIZE: u32 = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigDigitalOcean {
+    pub domain: String,
+    /// The host record, `@` for the domain itself.
+    pub record_host: Option<String>,
+    /// The TTL of records, at least 30.
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// The personal access token, or set it through env variable DIGITALOCEAN_TOKEN.
+    pub token: Option<String>,
+    /// The API base, `https://api.digitalocean.com/v2/` by default.
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordList {
+    domain_records: Vec<Record>,
+    #[serde(default)]
+    links: Links,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Links {
+    pages: Option<Pages>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pages {
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Record {
+    id: u64,
+    #[serde(rename = "type")]
+    record_type: String,
+    name: String,
+    data: String,
+    ttl: Option<u32>,
+}
+
+struct Api<'a> {
+    conf: &'a ConfigDigitalOcean,
+    client: &'a HttpClient,
+    records_url: Url,
+    token: String,
+}
+
+/// Update DNS record on DigitalOcean
+///
+/// The document of DigitalOcean API: https://docs.digitalocean.com/reference/api/api-reference/#tag/Domain-Records
+///
+/// # Example:
+/// ```rust
+/// digitalocean::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigDigitalOcean, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let update_inner = async { rest::update(&Api::new(conf, client)?, ip).await };
+    update_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+/// Make the records of the host contain exactly the given IPs, one record per IP.
+pub async fn update_set(conf: &ConfigDigitalOcean, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let update_set_inner = async { rest::update_set(&Api::new(conf, client)?, record_type, ips).await };
+    update_set_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+pub fn record_name(conf: &ConfigDigitalOcean) -> String {
+    rest::record_name(&conf.domain, conf.record_host.as_deref())
+}
+
+fn api_message(val: &Value) -> Option<&str> {
+    val["message"].as_str()
+}
+
+impl<'a> Api<'a> {
+    fn new(conf: &'a ConfigDigitalOcean, client: &'a HttpClient) -> Result<Self, DwdError> {
+        let token = match conf.token.as_ref() {
+            Some(val) => val.to_owned(),
+            None => env::var("DIGITALOCEAN_TOKEN")
+                .map_err(|_| DwdError::config("Please set env variable DIGITALOCEAN_TOKEN."))?,
+        };
+        let records_url = Url::parse(conf.base_url.as_deref().unwrap_or(BASE_URL))?
+            .join(&format!("domains/{}/records/", conf.domain))?;
+        Ok(Api {
+            conf,
+            client,
+            records_url,
+            token,
+        })
+    }
+
+    fn host(&self) -> &str {
+        rest::relative_host(self.conf.record_host.as_deref())
+    }
+
+    fn body(&self, record_type: &str, value: String) -> Value {
+        json!({"type": record_type, "name": self.host(), "data": value, "ttl": self.ttl()})
+    }
+}
+
+impl RecordApi for Api<'_> {
+    fn ttl(&self) -> u32 {
+        rest::ttl(self.conf.record_ttl, DEFAULT_TTL, MIN_TTL)
+    }
+
+    fn list<'a>(&'a self, record_type: &'a str) -> ApiFuture<'a, Vec<RestRecord>> {
+        Box::pin(async move {
+            let mut url = self.records_url.clone();
+            url.query_pairs_mut()
+                .append_pair("type", record_type)
+                .append_pair("name", &record_name(self.conf))
+                .append_pair("per_page", &PAGE_SIZE.to_string());
+
+            let mut records = Vec::new();
+            loop {
+                let builder = self.client.get(url).bearer_auth(&self.token);
+                let ret = rest::send(self.client, builder, api_message)
+                    .await?
+                    .json::<RecordList>()
+                    .await?;
+                records.extend(
+                    ret.domain_records
+                        .into_iter()
+                        .filter(|record| record.name == self.host() && record.record_type == record_type)
+                        .map(|record| RestRecord {
+                            id: record.id.to_string(),
+                            value: record.data,
+                            ttl: record.ttl,
+                        }),
+                );
+
+                // The next page is a full URL with the same filters.
+                match ret.links.pages.and_then(|pages| pages.next) {
+                    Some(next) => url = Url::parse(&next)?,
+                    None => break,
+                }
+            }
+            Ok(records)
+        })
+    }
+
+    fn create<'a>(&'a self, record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .post(self.records_url.clone())
+                .bearer_auth(&self.token)
+                .json(&self.body(record_type, value));
+            rest::send(self.client, builder, api_message).await?;
+            Ok(())
+        })
+    }
+
+    fn update<'a>(&'a self, record: &'a RestRecord, record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .put(self.records_url.join(&record.id)?)
+                .bearer_auth(&self.token)
+                .json(&self.body(record_type, value));
+            rest::send(self.client, builder, api_message).await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, record: &'a RestRecord) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .delete(self.records_url.join(&record.id)?)
+                .bearer_auth(&self.token);
+            rest::send(self.client, builder, api_message).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn conf(server: &MockServer) -> ConfigDigitalOcean {
+        ConfigDigitalOcean {
+            domain: String::from("example.com"),
+            record_host: Some(String::from("home")),
+            record_ttl: Some(10),
+            record_type: None,
+            token: Some(String::from("t")),
+            base_url: Some(server.url.clone()),
+        }
+    }
+
+    #[tokio::test]
+    async fn update_set_should_read_all_pages() {
+        let server = MockServer::start(|request| {
+            let url = request.headers["host"].to_owned();
+            match (request.method.as_str(), request.path.contains("page=2")) {
+                ("GET", false) => MockResponse::json(
+                    200,
+                    json!({
+                        "domain_records": [{"id": 1, "type": "A", "name": "home", "data": "1.1.1.1", "ttl": 30}],
+                        "links": {"pages": {"next": format!("http://{}/domains/example.com/records/?page=2", url)}}
+                    }),
+                ),
+                ("GET", true) => MockResponse::json(
+                    200,
+                    json!({
+                        "domain_records": [
+                            {"id": 2, "type": "A", "name": "home", "data": "3.3.3.3", "ttl": 30},
+                            {"id": 3, "type": "A", "name": "nas", "data": "3.3.3.3", "ttl": 30}
+                        ],
+                        "links": {}
+                    }),
+                ),
+                ("POST", _) => MockResponse::json(201, json!({"domain_record": {"id": 4}})),
+                _ => MockResponse::new(204, ""),
+            }
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update_set(
+            &conf(&server),
+            &client,
+            &["1.1.1.1".parse().unwrap(), "2.2.2.2".parse().unwrap()],
+        )
+        .await
+        .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(
+            requests[0].path,
+            "/domains/example.com/records/?type=A&name=home.example.com&per_page=200"
+        );
+        assert_eq!(requests[0].headers["authorization"], "Bearer t");
+        assert_eq!(requests[2].method, "POST");
+        assert_eq!(
+            serde_json::from_str::<Value>(&requests[2].body).unwrap(),
+            json!({"type": "A", "name": "home", "data": "2.2.2.2", "ttl": 30})
+        );
+        assert_eq!(requests[3].method, "DELETE");
+        assert_eq!(requests[3].path, "/domains/example.com/records/2");
+        assert_eq!(requests.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn update_set_should_skip_empty_set() {
+        let server = MockServer::start(|_| MockResponse::json(200, json!({"domain_records": [], "links": {}}))).await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update_set(&conf(&server), &client, &["::1".parse().unwrap()])
+            .await
+            .unwrap();
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_should_classify_errors() {
+        let server = MockServer::start(|_| {
+            MockResponse::json(
+                401,
+                json!({"id": "unauthorized", "message": "Unable to authenticate you."}),
+            )
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        let err = update(&conf(&server), &client, &"1.1.1.1".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Auth);
+        assert!(err.message.contains("Unable to authenticate you."));
+    }
+}
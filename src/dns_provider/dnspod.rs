@@ -0,0 +1,493 @@
+use chrono::{DateTime, Utc};
+use log::trace;
+use openssl::hash::MessageDigest;
+use reqwest::{header::HeaderValue, Request, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::net::IpAddr;
+
+use super::super::error::{DwdError, ErrorKind};
+use super::super::http::HttpClient;
+use super::super::util::{error_style, info_style};
+use super::signature::{hex, hmac, host, sha256_hex};
+use super::{ips_of_type, plan_record_set};
+
+const DEFAULT_ENDPOINT: &str = "https://dnspod.tencentcloudapi.com/";
+const SERVICE: &str = "dnspod";
+const API_VERSION: &str = "2021-03-23";
+const CONTENT_TYPE: &str = "application/json; charset=utf-8";
+/// The minimum TTL of the free plan.
+const DEFAULT_TTL: u32 = 600;
+const DEFAULT_LINE: &str = "默认";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigDnspod {
+    pub domain: String,
+    /// The host record, `@` for the domain itself.
+    pub record_host: Option<String>,
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// The line of records, `默认` (default) by default.
+    pub record_line: Option<String>,
+    /// The API key of Tencent Cloud, or set them through env variables DNSPOD_SECRET_ID and DNSPOD_SECRET_KEY.
+    pub secret_id: Option<String>,
+    pub secret_key: Option<String>,
+    /// The API endpoint, `https://dnspod.tencentcloudapi.com/` by default.
+    pub endpoint: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiError {
+    code: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RecordList {
+    record_list: Vec<Record>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Record {
+    record_id: u64,
+    name: String,
+    #[serde(rename = "Type")]
+    record_type: String,
+    line: String,
+    value: String,
+}
+
+/// The response of the actions changing records.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RecordId {
+    record_id: u64,
+}
+
+/// Update DNS record on DNSPod through Tencent Cloud API 3.0
+///
+/// The document of DNSPod API: https://cloud.tencent.com/document/api/1427/56194
+///
+/// # Example:
+/// ```rust
+/// dnspod::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigDnspod, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    update_inner(conf, client, ip)
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+async fn update_inner(conf: &ConfigDnspod, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+    let records = find_records(conf, client, record_type).await?;
+    let value = ip.to_string();
+
+    match records.first() {
+        Some(record) if record.value == value => trace!("Record {} is up to date", record.record_id),
+        Some(record) => {
+            let ret: RecordId = call(
+                conf,
+                client,
+                "ModifyDynamicDNS",
+                json!({
+                    "Domain": conf.domain,
+                    "SubDomain": record_host(conf),
+                    "RecordId": record.record_id,
+                    "RecordLine": record.line,
+                    "Value": value,
+                    "Ttl": conf.record_ttl.unwrap_or(DEFAULT_TTL),
+                }),
+            )
+            .await?;
+            trace!("Update record {} to: {}", ret.record_id, ip);
+        }
+        None => {
+            let ret = create_record(conf, client, record_type, ip).await?;
+            trace!("Create record {} with: {}", ret.record_id, ip);
+        }
+    }
+
+    Ok(())
+}
+
+/// Make the records of the host contain exactly the given IPs, one record per IP.
+///
+/// Only the IPs matching the configured `record_type` are published, so A and AAAA records need separated configs.
+pub async fn update_set(conf: &ConfigDnspod, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    update_set_inner(conf, client, ips)
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+async fn update_set_inner(conf: &ConfigDnspod, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let ips = match ips_of_type(record_type, ips)? {
+        Some(val) => val,
+        None => return Ok(()),
+    };
+
+    let records = find_records(conf, client, record_type)
+        .await?
+        .into_iter()
+        .map(|record| (record.record_id, record.value))
+        .collect();
+    let (stale, missing) = plan_record_set(records, &ips);
+
+    for ip in missing {
+        create_record(conf, client, record_type, &ip).await?;
+    }
+    for record_id in stale {
+        let _: Value = call(
+            conf,
+            client,
+            "DeleteRecord",
+            json!({"Domain": conf.domain, "RecordId": record_id}),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub fn record_name(conf: &ConfigDnspod) -> String {
+    match record_host(conf) {
+        "@" => conf.domain.to_owned(),
+        host => format!("{}.{}", host, conf.domain),
+    }
+}
+
+fn record_host(conf: &ConfigDnspod) -> &str {
+    match conf.record_host.as_deref() {
+        Some(host) if !host.is_empty() => host,
+        _ => "@",
+    }
+}
+
+/// Find the records of the host, type and line, an empty list is reported as `ResourceNotFound.NoDataOfRecord`.
+async fn find_records(conf: &ConfigDnspod, client: &HttpClient, record_type: &str) -> Result<Vec<Record>, DwdError> {
+    let ret = call::<RecordList>(
+        conf,
+        client,
+        "DescribeRecordList",
+        json!({
+            "Domain": conf.domain,
+            "Subdomain": record_host(conf),
+            "RecordType": record_type,
+        }),
+    )
+    .await;
+    let records = match ret {
+        Ok(val) => val.record_list,
+        Err(e) if e.kind == ErrorKind::NotFound && e.message.contains("NoDataOfRecord") => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let line = conf.record_line.as_deref().unwrap_or(DEFAULT_LINE);
+    let records = records
+        .into_iter()
+        .filter(|record| record.name == record_host(conf) && record.record_type == record_type && record.line == line)
+        .collect::<Vec<_>>();
+
+    trace!("Find records from DNSPod: {:?}", records);
+
+    Ok(records)
+}
+
+async fn create_record(
+    conf: &ConfigDnspod,
+    client: &HttpClient,
+    record_type: &str,
+    ip: &IpAddr,
+) -> Result<RecordId, DwdError> {
+    call(
+        conf,
+        client,
+        "CreateRecord",
+        json!({
+            "Domain": conf.domain,
+            "SubDomain": record_host(conf),
+            "RecordType": record_type,
+            "RecordLine": conf.record_line.as_deref().unwrap_or(DEFAULT_LINE),
+            "Value": ip.to_string(),
+            "TTL": conf.record_ttl.unwrap_or(DEFAULT_TTL),
+        }),
+    )
+    .await
+}
+
+/// Call an action of the API, the errors are reported in the body with status 200.
+async fn call<T: DeserializeOwned>(
+    conf: &ConfigDnspod,
+    client: &HttpClient,
+    action: &str,
+    payload: Value,
+) -> Result<T, DwdError> {
+    let (secret_id, secret_key) = credentials(conf)?;
+    let url = Url::parse(conf.endpoint.as_deref().unwrap_or(DEFAULT_ENDPOINT))?;
+    let mut request = client
+        .post(url)
+        .header("Content-Type", CONTENT_TYPE)
+        .header("X-TC-Action", action)
+        .header("X-TC-Version", API_VERSION)
+        .body(payload.to_string())
+        .build()?;
+    sign(&mut request, SERVICE, &secret_id, &secret_key, Utc::now())?;
+
+    let response = client.execute(request).await?;
+    let status = response.status();
+    trace!("POST {} {}", action, status);
+
+    let text = response.text().await?;
+    trace!("Response: {}", text);
+    let mut body = serde_json::from_str::<Value>(&text).map_err(|_| {
+        DwdError::from_status(
+            status,
+            format!("API response error: {} {}", error_style(status), error_style(&text)),
+        )
+    })?;
+    let response = body["Response"].take();
+    if let Some(err) = response.get("Error") {
+        let err = serde_json::from_value::<ApiError>(err.clone())?;
+        let message = format!("API response error: {}: {}", err.code, err.message);
+        let code = err.code.as_str();
+        return Err(
+            // The signature expires when the local clock is off, it may work again once the clock is synced.
+            if code == "AuthFailure.SignatureExpire" {
+                DwdError::new(ErrorKind::Transport, message)
+            } else if code.starts_with("AuthFailure") || code.starts_with("UnauthorizedOperation") {
+                DwdError::new(ErrorKind::Auth, message)
+            } else if code.starts_with("RequestLimitExceeded") {
+                DwdError::new(ErrorKind::RateLimit, message)
+            } else if code.starts_with("ResourceNotFound") {
+                DwdError::new(ErrorKind::NotFound, message)
+            } else if code.starts_with("InternalError") {
+                DwdError::from_status(StatusCode::INTERNAL_SERVER_ERROR, message)
+            } else {
+                DwdError::from_status(status, message)
+            },
+        );
+    }
+
+    Ok(serde_json::from_value(response)?)
+}
+
+/// Sign the request with TC3-HMAC-SHA256, the `Content-Type` and `Host` headers are signed.
+///
+/// The document of the signature: https://cloud.tencent.com/document/api/1427/56189
+fn sign(
+    request: &mut Request,
+    service: &str,
+    secret_id: &str,
+    secret_key: &str,
+    now: DateTime<Utc>,
+) -> Result<(), DwdError> {
+    let timestamp = now.timestamp();
+    // The date in the scope is the UTC date of the timestamp.
+    let date = now.format("%Y-%m-%d").to_string();
+
+    let payload = request.body().and_then(|body| body.as_bytes()).unwrap_or_default();
+    let canonical_request = format!(
+        "POST\n/\n\ncontent-type:{}\nhost:{}\n\ncontent-type;host\n{}",
+        CONTENT_TYPE,
+        host(request.url()),
+        sha256_hex(payload)
+    );
+    let scope = format!("{}/{}/tc3_request", date, service);
+    let string_to_sign = format!(
+        "TC3-HMAC-SHA256\n{}\n{}\n{}",
+        timestamp,
+        scope,
+        sha256_hex(canonical_request.as_bytes())
+    );
+
+    let mut key = format!("TC3{}", secret_key).into_bytes();
+    for part in [date.as_str(), service, "tc3_request"] {
+        key = hmac(MessageDigest::sha256(), &key, part.as_bytes())?;
+    }
+    let signature = hex(&hmac(MessageDigest::sha256(), &key, string_to_sign.as_bytes())?);
+
+    let authorization = format!(
+        "TC3-HMAC-SHA256 Credential={}/{}, SignedHeaders=content-type;host, Signature={}",
+        secret_id, scope, signature
+    );
+    let headers = request.headers_mut();
+    headers.insert("Authorization", HeaderValue::from_str(&authorization)?);
+    headers.insert("X-TC-Timestamp", HeaderValue::from_str(&timestamp.to_string())?);
+
+    Ok(())
+}
+
+fn credentials(conf: &ConfigDnspod) -> Result<(String, String), DwdError> {
+    let secret_id = match conf.secret_id.as_ref() {
+        Some(val) => val.to_owned(),
+        None => {
+            env::var("DNSPOD_SECRET_ID").map_err(|_| DwdError::config("Please set env variable DNSPOD_SECRET_ID."))?
+        }
+    };
+    let secret_key = match conf.secret_key.as_ref() {
+        Some(val) => val.to_owned(),
+        None => {
+            env::var("DNSPOD_SECRET_KEY").map_err(|_| DwdError::config("Please set env variable DNSPOD_SECRET_KEY."))?
+        }
+    };
+
+    trace!("SecretId: {:?}", info_style(&secret_id));
+
+    Ok((secret_id, secret_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+    use chrono::TimeZone;
+    use reqwest::Method;
+
+    fn conf(server: &MockServer) -> ConfigDnspod {
+        ConfigDnspod {
+            domain: String::from("example.com"),
+            record_host: Some(String::from("home")),
+            record_ttl: None,
+            record_type: None,
+            record_line: None,
+            secret_id: Some(String::from("id")),
+            secret_key: Some(String::from("key")),
+            endpoint: Some(server.url.clone()),
+        }
+    }
+
+    #[test]
+    fn sign_should_scope_by_utc_date() {
+        let mut request = Request::new(Method::POST, "https://dnspod.tencentcloudapi.com/".parse().unwrap());
+        *request.body_mut() = Some(String::from("{}").into());
+        // 2019-02-26 00:04:25 in UTC+8 is still 2019-02-25 in UTC.
+        sign(
+            &mut request,
+            SERVICE,
+            "id",
+            "key",
+            Utc.timestamp_opt(1551110665, 0).unwrap(),
+        )
+        .unwrap();
+
+        let authorization = request.headers()["Authorization"].to_str().unwrap();
+        assert!(authorization.starts_with(
+            "TC3-HMAC-SHA256 Credential=id/2019-02-25/dnspod/tc3_request, SignedHeaders=content-type;host, Signature="
+        ));
+        assert_eq!(request.headers()["X-TC-Timestamp"], "1551110665");
+    }
+
+    #[test]
+    fn sign_should_match_document_example() {
+        // The example of the signature document, which calls DescribeInstances of CVM.
+        let mut request = Request::new(Method::POST, "https://cvm.tencentcloudapi.com/".parse().unwrap());
+        *request.body_mut() = Some(
+            String::from(r#"{"Limit": 1, "Filters": [{"Values": ["\u672a\u547d\u540d"], "Name": "instance-name"}]}"#)
+                .into(),
+        );
+        sign(
+            &mut request,
+            "cvm",
+            "AKIDz8krbsJ5yKBZQpn74WFkmLPx3EXAMPLE",
+            "Gu5t9xGARNpq86cd98joQYCN3EXAMPLE",
+            Utc.timestamp_opt(1551113065, 0).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            request.headers()["Authorization"],
+            "TC3-HMAC-SHA256 Credential=AKIDz8krbsJ5yKBZQpn74WFkmLPx3EXAMPLE/2019-02-25/cvm/tc3_request, \
+             SignedHeaders=content-type;host, \
+             Signature=72e494ea809ad7a8c8f7a4507b9bddcbaa8e581f516e8da2f66e2c5a96525168"
+        );
+    }
+
+    #[tokio::test]
+    async fn update_should_create_record_if_not_found() {
+        let server = MockServer::start(|request| match request.headers["x-tc-action"].as_str() {
+            "DescribeRecordList" => MockResponse::json(
+                200,
+                json!({"Response": {
+                    "Error": {"Code": "ResourceNotFound.NoDataOfRecord", "Message": "No records."},
+                    "RequestId": "x"
+                }}),
+            ),
+            _ => MockResponse::json(200, json!({"Response": {"RecordId": 1, "RequestId": "y"}})),
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update(&conf(&server), &client, &"2001:db8::1".parse().unwrap())
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].headers["x-tc-action"], "CreateRecord");
+        assert_eq!(requests[1].headers["x-tc-version"], "2021-03-23");
+        assert!(requests[1].headers["authorization"].starts_with("TC3-HMAC-SHA256 Credential=id/"));
+        let body = serde_json::from_str::<Value>(&requests[1].body).unwrap();
+        assert_eq!(body["SubDomain"], "home");
+        assert_eq!(body["RecordType"], "AAAA");
+        assert_eq!(body["Value"], "2001:db8::1");
+    }
+
+    #[tokio::test]
+    async fn update_should_classify_errors() {
+        let server = MockServer::start(|_| {
+            MockResponse::json(
+                200,
+                json!({"Response": {
+                    "Error": {"Code": "AuthFailure.SignatureFailure", "Message": "Oops"},
+                    "RequestId": "x"
+                }}),
+            )
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        let err = update(&conf(&server), &client, &"1.1.1.1".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Auth);
+        assert_eq!(err.record.as_deref(), Some("home.example.com"));
+    }
+
+    #[tokio::test]
+    async fn update_should_retry_expired_signature() {
+        let server = MockServer::start(|_| {
+            MockResponse::json(
+                200,
+                json!({"Response": {
+                    "Error": {"Code": "AuthFailure.SignatureExpire", "Message": "Signature expired."},
+                    "RequestId": "x"
+                }}),
+            )
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        let err = update(&conf(&server), &client, &"1.1.1.1".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Transport);
+        assert!(err.is_transient());
+    }
+
+    #[tokio::test]
+    async fn update_set_should_skip_empty_set() {
+        let server = MockServer::start(|_| MockResponse::json(200, json!({"Response": {"RequestId": "x"}}))).await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update_set(&conf(&server), &client, &["2001:db8::1".parse().unwrap()])
+            .await
+            .unwrap();
+        assert!(server.requests().is_empty());
+    }
+}
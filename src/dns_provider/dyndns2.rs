@@ -0,0 +1,209 @@
+use log::trace;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use super::super::error::{DwdError, ErrorKind};
+use super::super::http::HttpClient;
+use super::super::util::error_style;
+
+/// The spec asks clients not to retry within 30 minutes after the server responds with `911`.
+const RETRY_AFTER_911: Duration = Duration::from_secs(1800);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigDyndns2 {
+    /// The update URL of the service, e.g. `https://dynupdate.no-ip.com/nic/update`.
+    pub server: String,
+    /// The hostnames to update, they are updated in one request.
+    pub hostnames: Vec<String>,
+    pub username: String,
+    /// The password or the update key, or set it through env variable DYNDNS2_PASSWORD.
+    pub password: Option<String>,
+    /// The parameter of IPv6 addresses, `myipv6` by default. Some services take both IPv4 and IPv6 addresses in
+    /// `myip`, set it to `myip` for them.
+    pub ipv6_param: Option<String>,
+}
+
+/// Update the hostnames through the DynDNS2 protocol, which is spoken by No-IP, Dyn, DNS-O-Matic and many others.
+///
+/// The document of the protocol: https://help.dyn.com/remote-access-api/perform-update/
+///
+/// # Example:
+/// ```rust
+/// dyndns2::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigDyndns2, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    update_inner(conf, client, ip)
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+async fn update_inner(conf: &ConfigDyndns2, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    if conf.hostnames.is_empty() {
+        return Err(DwdError::config("The config.dyndns2.hostnames should not be empty."));
+    }
+    let password = password(conf)?;
+    let ip_param = match ip {
+        IpAddr::V4(_) => "myip",
+        IpAddr::V6(_) => conf.ipv6_param.as_deref().unwrap_or("myipv6"),
+    };
+
+    let mut url = Url::parse(&conf.server)?;
+    url.query_pairs_mut()
+        .append_pair("hostname", &conf.hostnames.join(","))
+        .append_pair(ip_param, &ip.to_string());
+    let response = client
+        .send(client.get(url).basic_auth(&conf.username, Some(&password)))
+        .await?;
+    let status = response.status();
+    trace!("GET {} {}", conf.server, status);
+
+    let text = response.text().await?;
+    trace!("Response: {}", text.trim());
+    // The services reply one line for each hostname, in the same order as the request.
+    let lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    if lines.is_empty() && !status.is_success() {
+        return Err(DwdError::from_status(
+            status,
+            format!("API response error: {}", error_style(status)),
+        ));
+    }
+    if lines.is_empty() {
+        return Err(DwdError::parse("The server replied nothing."));
+    }
+
+    for (index, line) in lines.iter().enumerate() {
+        let hostname = conf.hostnames.get(index).or_else(|| conf.hostnames.first());
+        if let Err(e) = check_return_code(line) {
+            // No request should be sent until the server recovers, even if the IP changes again.
+            if let Some(retry_after) = e.retry_after {
+                client.block(retry_after);
+            }
+            return Err(match hostname {
+                Some(hostname) if conf.hostnames.len() > 1 => e.with_record(hostname),
+                _ => e,
+            });
+        }
+    }
+
+    trace!("Update {} to: {}", record_name(conf), ip);
+
+    Ok(())
+}
+
+/// Classify the return code, `badauth` and `abuse` are errors of `ErrorKind::Auth`, so the provider is disabled and
+/// never retried as the spec requires.
+fn check_return_code(line: &str) -> Result<(), DwdError> {
+    let code = line.split_whitespace().next().unwrap_or_default();
+    let err = match code {
+        "good" | "nochg" => return Ok(()),
+        "badauth" => DwdError::new(ErrorKind::Auth, "The username or password is rejected (badauth)."),
+        "abuse" => DwdError::new(ErrorKind::Auth, "The hostname is blocked for abuse (abuse)."),
+        "nohost" => DwdError::new(
+            ErrorKind::NotFound,
+            "The hostname does not exist in the account (nohost).",
+        ),
+        "notfqdn" => DwdError::config("The hostname is not a fully qualified domain name (notfqdn)."),
+        "numhost" => DwdError::config("Too many hostnames are in one request (numhost)."),
+        "!donator" => DwdError::config("The feature is only available to paid accounts (!donator)."),
+        "badagent" => DwdError::config("The user agent is blocked (badagent)."),
+        "dnserr" | "911" => {
+            let mut err = DwdError::rate_limited(RETRY_AFTER_911);
+            err.message = format!(
+                "The server has a problem ({}), retry after {}s.",
+                code,
+                RETRY_AFTER_911.as_secs()
+            );
+            err
+        }
+        _ => DwdError::parse(format!("Unknown return code: {}", error_style(line))),
+    };
+    Err(err)
+}
+
+/// The hostnames which are updated, they are used in logs and notifications.
+pub fn record_name(conf: &ConfigDyndns2) -> String {
+    conf.hostnames.join(", ")
+}
+
+fn password(conf: &ConfigDyndns2) -> Result<String, DwdError> {
+    match conf.password.as_ref() {
+        Some(val) => Ok(val.to_owned()),
+        None => env::var("DYNDNS2_PASSWORD").map_err(|_| DwdError::config("Please set env variable DYNDNS2_PASSWORD.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn conf(server: &MockServer) -> ConfigDyndns2 {
+        ConfigDyndns2 {
+            server: format!("{}nic/update", server.url),
+            hostnames: vec![String::from("a.example.com"), String::from("b.example.com")],
+            username: String::from("user"),
+            password: Some(String::from("pass")),
+            ipv6_param: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn update_should_send_hostnames_and_ip() {
+        let server = MockServer::start(|_| MockResponse::new(200, "good 2001:db8::1\nnochg 2001:db8::1\n")).await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update(&conf(&server), &client, &"2001:db8::1".parse().unwrap())
+            .await
+            .unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(
+            request.path,
+            "/nic/update?hostname=a.example.com%2Cb.example.com&myipv6=2001%3Adb8%3A%3A1"
+        );
+        // The base64 of `user:pass`.
+        assert_eq!(request.headers["authorization"], "Basic dXNlcjpwYXNz");
+    }
+
+    #[tokio::test]
+    async fn update_should_interpret_return_codes() {
+        let server = MockServer::start(|request| {
+            if request.path.contains("myip=1.1.1.1") {
+                MockResponse::new(200, "good 1.1.1.1\nabuse")
+            } else {
+                MockResponse::new(200, "911")
+            }
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default())
+            .unwrap()
+            .for_provider("dyndns2.test");
+        let err = update(&conf(&server), &client, &"1.1.1.1".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Auth);
+        assert_eq!(err.record.as_deref(), Some("b.example.com"));
+
+        let err = update(&conf(&server), &client, &"2.2.2.2".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::RateLimit);
+        assert_eq!(err.retry_after, Some(RETRY_AFTER_911));
+
+        // The server is not asked again within the window.
+        let err = update(&conf(&server), &client, &"3.3.3.3".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::RateLimit);
+        assert_eq!(server.requests().len(), 2);
+    }
+}
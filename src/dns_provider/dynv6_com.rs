@@ -1,18 +1,42 @@
 use log::trace;
-use reqwest::{Client, Url};
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use std::env;
-use std::error::Error;
+use std::net::IpAddr;
 use std::str;
 
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
 use super::super::util::{error_style, info_style};
+use super::{ips_of_type, plan_record_set};
 
 const BASE_URL: &str = "https://dynv6.com/api/update";
+const API_URL: &str = "https://dynv6.com/api/v2/";
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigDynv6Com {
     pub zone: String,
     pub token: Option<String>,
+    /// The name of records in the zone which multi-value updates manage, the zone itself can not hold multiple
+    /// records on dynv6.com.
+    pub record_name: Option<String>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Zone {
+    id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    #[serde(rename = "type")]
+    record_type: String,
+    name: String,
+    data: String,
 }
 
 /// Update DDNS record on dynv6.com
@@ -23,27 +47,31 @@ pub struct ConfigDynv6Com {
 /// ```rust
 /// dynv6_com::update(domain, record_type, record_host, ip, record_ttl).await?;
 /// ```
-pub async fn update(conf: &ConfigDynv6Com, ip: &str) -> Result<(), Box<dyn Error>> {
-    let token = match conf.token.as_ref() {
-        Some(val) => val.to_owned(),
-        None => env::var("DYNV6_COM_TOKEN").map_err(|_| "Please set env variable DYNV6_COM_TOKEN.")?,
-    };
-
-    trace!("Token: {:?}", info_style(&token));
+pub async fn update(conf: &ConfigDynv6Com, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    update_inner(conf, client, ip)
+        .await
+        .map_err(|e| e.with_record(&conf.zone))
+}
 
-    let client = Client::new();
-    let base_url = Url::parse(BASE_URL).map_err(|e| e.to_string())?;
+async fn update_inner(conf: &ConfigDynv6Com, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let token = token(conf)?;
+    let base_url = Url::parse(BASE_URL)?;
 
+    let ip_param = if ip.is_ipv4() { "ipv4" } else { "ipv6" };
     let request = client
         .get(base_url)
-        .query(&[("zone", conf.zone.as_str()), ("token", token.as_str()), ("ipv4", ip)])
+        .query(&[
+            ("zone", conf.zone.as_str()),
+            ("token", token.as_str()),
+            (ip_param, ip.to_string().as_str()),
+        ])
         .build()?;
     let response = client.execute(request).await?;
 
     trace!("GET {} {}", response.url(), response.status());
 
     if let Err(err) = response.error_for_status_ref() {
-        return Err(format!("API response error: {}", error_style(err)).into());
+        return Err(api_error(err));
     }
 
     trace!("Update record to: {:?}", conf.zone);
@@ -51,6 +79,112 @@ pub async fn update(conf: &ConfigDynv6Com, ip: &str) -> Result<(), Box<dyn Error
     Ok(())
 }
 
+/// Make the records of `record_name` in the zone contain exactly the given IPs, one record per IP.
+///
+/// The REST API is used here, because the update API could only update the zone itself.
+/// The document of dynv6.com REST API: https://dynv6.com/docs/apis#rest
+pub async fn update_set(conf: &ConfigDynv6Com, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_name = conf
+        .record_name
+        .as_ref()
+        .ok_or_else(|| DwdError::config("The config.dynv6_com.record_name is required for multi-value records."))?;
+    update_set_inner(conf, client, ips, record_name)
+        .await
+        .map_err(|e| e.with_record(format!("{}.{}", record_name, conf.zone)))
+}
+
+async fn update_set_inner(
+    conf: &ConfigDynv6Com,
+    client: &HttpClient,
+    ips: &[IpAddr],
+    record_name: &str,
+) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let ips = match ips_of_type(record_type, ips)? {
+        Some(val) => val,
+        None => return Ok(()),
+    };
+
+    let token = token(conf)?;
+    let api_url = Url::parse(API_URL)?;
+
+    let url = api_url.join(&format!("zones/by-name/{}", conf.zone))?;
+    let response = client.send(client.get(url).bearer_auth(&token)).await?;
+    trace!("GET {} {}", response.url(), response.status());
+    if let Err(err) = response.error_for_status_ref() {
+        return Err(api_error(err));
+    }
+    let zone = response.json::<Zone>().await?;
+
+    let records_url = api_url.join(&format!("zones/{}/records", zone.id))?;
+    let response = client.send(client.get(records_url.clone()).bearer_auth(&token)).await?;
+    trace!("GET {} {}", response.url(), response.status());
+    if let Err(err) = response.error_for_status_ref() {
+        return Err(api_error(err));
+    }
+    let records = response
+        .json::<Vec<Record>>()
+        .await?
+        .into_iter()
+        .filter(|record| record.name == record_name && record.record_type == record_type)
+        .map(|record| {
+            let data = record.data.to_owned();
+            (record, data)
+        })
+        .collect();
+    let (stale, missing) = plan_record_set(records, &ips);
+
+    // Create new records first, so the name will always be resolvable.
+    for ip in missing {
+        let record = Record {
+            id: None,
+            record_type: record_type.to_owned(),
+            name: record_name.to_owned(),
+            data: ip.to_string(),
+        };
+        let response = client
+            .send(client.post(records_url.clone()).bearer_auth(&token).json(&record))
+            .await?;
+        trace!("POST {} {}", response.url(), response.status());
+        if let Err(err) = response.error_for_status_ref() {
+            return Err(api_error(err));
+        }
+    }
+    for record in stale {
+        let id = record
+            .id
+            .ok_or_else(|| DwdError::parse(format!("The record {} of {} has no ID.", record.data, record_name)))?;
+        let url = api_url.join(&format!("zones/{}/records/{}", zone.id, id))?;
+        let response = client.send(client.delete(url).bearer_auth(&token)).await?;
+        trace!("DELETE {} {}", response.url(), response.status());
+        if let Err(err) = response.error_for_status_ref() {
+            return Err(api_error(err));
+        }
+    }
+
+    trace!("Update records of {} in {} to: {:?}", record_name, conf.zone, ips);
+
+    Ok(())
+}
+
+fn token(conf: &ConfigDynv6Com) -> Result<String, DwdError> {
+    let token = match conf.token.as_ref() {
+        Some(val) => val.to_owned(),
+        None => {
+            env::var("DYNV6_COM_TOKEN").map_err(|_| DwdError::config("Please set env variable DYNV6_COM_TOKEN."))?
+        }
+    };
+
+    trace!("Token: {:?}", info_style(&token));
+
+    Ok(token)
+}
+
+fn api_error(err: reqwest::Error) -> DwdError {
+    let status = err.status().unwrap_or_default();
+    DwdError::from_status(status, format!("API response error: {}", error_style(err)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -63,8 +197,15 @@ mod tests {
         let config = ConfigDynv6Com {
             zone: String::from("dwd-unittest.dynv6.net"),
             token: None,
+            record_name: None,
+            record_type: None,
         };
-        let ret = update(&config, "127.0.0.1").await;
+        let ret = update(
+            &config,
+            &HttpClient::new(&Default::default(), &Default::default()).unwrap(),
+            &"127.0.0.1".parse().unwrap(),
+        )
+        .await;
         assert!(ret.is_ok(), "{}", ret.unwrap_err().to_string());
     }
 }
@@ -0,0 +1,144 @@
+//! Gandi LiveDNS manages records as RRsets, a PUT to `domains/<domain>/records/<name>/<type>` replaces all values of
+//! the name and the type at once and creates the RRset if it does not exist, so the records are never listed first.
+
+use log::trace;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::net::IpAddr;
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::{ips_of_type, rest};
+
+const BASE_URL: &str = "https://api.gandi.net/v5/livedns/";
+const DEFAULT_TTL: u32 = 10800;
+const MIN_TTL: u32 = 300;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigGandi {
+    pub domain: String,
+    /// The host record, `@` for the domain itself.
+    pub record_host: Option<String>,
+    /// The TTL of records, at least 300.
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// The personal access token, or set it through env variable GANDI_TOKEN.
+    pub token: Option<String>,
+    /// The API base, `https://api.gandi.net/v5/livedns/` by default.
+    pub base_url: Option<String>,
+}
+
+/// Update DNS record on Gandi LiveDNS
+///
+/// The document of Gandi LiveDNS API: https://api.gandi.net/docs/livedns/
+///
+/// # Example:
+/// ```rust
+/// gandi::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigGandi, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+    put_rrset(conf, client, record_type, &[*ip])
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+/// Replace the RRset with the IPs of `record_type`, the IPs of the other family are ignored.
+pub async fn update_set(conf: &ConfigGandi, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let update_set_inner = async {
+        match ips_of_type(record_type, ips)? {
+            Some(ips) => put_rrset(conf, client, record_type, &ips).await,
+            None => Ok(()),
+        }
+    };
+    update_set_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+pub fn record_name(conf: &ConfigGandi) -> String {
+    rest::record_name(&conf.domain, conf.record_host.as_deref())
+}
+
+fn api_message(val: &Value) -> Option<&str> {
+    val["message"].as_str()
+}
+
+/// Replace the RRset with the IPs.
+async fn put_rrset(conf: &ConfigGandi, client: &HttpClient, record_type: &str, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let token = match conf.token.as_ref() {
+        Some(val) => val.to_owned(),
+        None => env::var("GANDI_TOKEN").map_err(|_| DwdError::config("Please set env variable GANDI_TOKEN."))?,
+    };
+    let url = Url::parse(conf.base_url.as_deref().unwrap_or(BASE_URL))?.join(&format!(
+        "domains/{}/records/{}/{}",
+        conf.domain,
+        rest::relative_host(conf.record_host.as_deref()),
+        record_type
+    ))?;
+
+    let body = json!({
+        "rrset_values": ips,
+        "rrset_ttl": rest::ttl(conf.record_ttl, DEFAULT_TTL, MIN_TTL),
+    });
+    trace!("Request body: {}", body);
+    rest::send(client, client.put(url).bearer_auth(&token).json(&body), api_message).await?;
+    trace!("Update {} to: {:?}", record_name(conf), ips);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn conf(server: &MockServer) -> ConfigGandi {
+        ConfigGandi {
+            domain: String::from("example.com"),
+            record_host: None,
+            record_ttl: Some(60),
+            record_type: Some(String::from("AAAA")),
+            token: Some(String::from("t")),
+            base_url: Some(server.url.clone()),
+        }
+    }
+
+    #[tokio::test]
+    async fn update_set_should_put_rrset() {
+        let server = MockServer::start(|_| MockResponse::json(201, json!({"message": "DNS Record Created"}))).await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update_set(
+            &conf(&server),
+            &client,
+            &["1.1.1.1".parse().unwrap(), "2001:db8::1".parse().unwrap()],
+        )
+        .await
+        .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(requests[0].path, "/domains/example.com/records/@/AAAA");
+        assert_eq!(requests[0].headers["authorization"], "Bearer t");
+        assert_eq!(
+            serde_json::from_str::<Value>(&requests[0].body).unwrap(),
+            json!({"rrset_values": ["2001:db8::1"], "rrset_ttl": 300})
+        );
+    }
+
+    #[tokio::test]
+    async fn update_set_should_skip_empty_rrset() {
+        let server = MockServer::start(|_| MockResponse::json(201, json!({"message": "DNS Record Created"}))).await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update_set(&conf(&server), &client, &["1.1.1.1".parse().unwrap()])
+            .await
+            .unwrap();
+        assert!(server.requests().is_empty());
+    }
+}
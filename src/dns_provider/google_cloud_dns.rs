@@ -0,0 +1,365 @@
+use chrono::Utc;
+use log::trace;
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use reqwest::{Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{env, fs, net::IpAddr};
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::super::util::error_style;
+use super::signature::base64url;
+use super::{ips_of_type, oauth};
+
+const DEFAULT_API_BASE: &str = "https://dns.googleapis.com/dns/v1/";
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const SCOPE: &str = "https://www.googleapis.com/auth/ndev.clouddns.readwrite";
+const DEFAULT_TTL: u32 = 300;
+/// The lifetime of the JWT which is exchanged for a token, one hour at most.
+const JWT_LIFETIME: i64 = 3600;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigGoogleCloudDns {
+    /// The project of the managed zone, the project of the service account by default.
+    pub project: Option<String>,
+    /// The name of the managed zone, not the DNS name, e.g. `example-com`.
+    pub managed_zone: String,
+    /// The full name of the record, e.g. `home.example.com`.
+    pub record_name: String,
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// The JSON key of the service account, or set it through env variable GOOGLE_APPLICATION_CREDENTIALS.
+    pub credentials_file: Option<String>,
+    /// The OAuth token endpoint, the `token_uri` in the key by default.
+    pub token_uri: Option<String>,
+    /// The API base, `https://dns.googleapis.com/dns/v1/` by default.
+    pub api_base: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServiceAccountKey {
+    project_id: Option<String>,
+    private_key_id: Option<String>,
+    private_key: String,
+    client_email: String,
+    token_uri: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RecordSet {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    ttl: u32,
+    rrdatas: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordSetList {
+    #[serde(default)]
+    rrsets: Vec<RecordSet>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Change {
+    id: String,
+    status: String,
+}
+
+/// Update DNS record on Google Cloud DNS
+///
+/// The document of Cloud DNS API: https://cloud.google.com/dns/docs/reference/rest/v1/changes/create
+///
+/// # Example:
+/// ```rust
+/// google_cloud_dns::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigGoogleCloudDns, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+    update_record_set(conf, client, record_type, &[*ip])
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+/// Replace the record set with the IPs of `record_type`, the IPs of the other family are ignored.
+pub async fn update_set(conf: &ConfigGoogleCloudDns, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let update_set_inner = async {
+        match ips_of_type(record_type, ips)? {
+            Some(ips) => update_record_set(conf, client, record_type, &ips).await,
+            None => Ok(()),
+        }
+    };
+    update_set_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+pub fn record_name(conf: &ConfigGoogleCloudDns) -> String {
+    conf.record_name.trim_end_matches('.').to_owned()
+}
+
+/// Replace the existing record set in one change, Cloud DNS applies the deletion and the addition atomically.
+async fn update_record_set(
+    conf: &ConfigGoogleCloudDns,
+    client: &HttpClient,
+    record_type: &str,
+    ips: &[IpAddr],
+) -> Result<(), DwdError> {
+    let key = service_account_key(conf)?;
+    let project = match conf.project.as_ref().or(key.project_id.as_ref()) {
+        Some(val) => val.to_owned(),
+        None => return Err(DwdError::config("The config.google_cloud_dns.project is required.")),
+    };
+    let token_key = token_key(conf, &key);
+    let token = access_token(conf, client, &key).await?;
+
+    let base_url = Url::parse(conf.api_base.as_deref().unwrap_or(DEFAULT_API_BASE))?;
+    let zone_url = base_url.join(&format!("projects/{}/managedZones/{}/", project, conf.managed_zone))?;
+    let name = format!("{}.", record_name(conf));
+
+    let response = client
+        .send(
+            client
+                .get(zone_url.join("rrsets")?)
+                .query(&[("name", name.as_str()), ("type", record_type)])
+                .bearer_auth(&token),
+        )
+        .await?;
+    trace!("GET {} {}", response.url(), response.status());
+    if !response.status().is_success() {
+        return Err(api_error(response, &token_key).await);
+    }
+    let existing = response
+        .json::<RecordSetList>()
+        .await?
+        .rrsets
+        .into_iter()
+        .find(|rrset| rrset.name == name && rrset.record_type == record_type);
+
+    let mut rrdatas = ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>();
+    rrdatas.sort();
+    let expected = RecordSet {
+        name,
+        record_type: record_type.to_owned(),
+        ttl: conf.record_ttl.unwrap_or(DEFAULT_TTL),
+        rrdatas,
+    };
+    if let Some(existing) = existing.as_ref() {
+        let mut sorted = existing.clone();
+        sorted.rrdatas.sort();
+        if sorted == expected {
+            trace!("Record set {} is up to date", expected.name);
+            return Ok(());
+        }
+    }
+
+    let mut change = json!({ "additions": [expected] });
+    if let Some(existing) = existing {
+        change["deletions"] = json!([existing]);
+    }
+    trace!("Change: {}", change);
+    let response = client
+        .send(client.post(zone_url.join("changes")?).json(&change).bearer_auth(&token))
+        .await?;
+    trace!("POST {} {}", response.url(), response.status());
+    if !response.status().is_success() {
+        return Err(api_error(response, &token_key).await);
+    }
+    let change = response.json::<Change>().await?;
+    trace!("Change {} is {}", change.id, change.status);
+
+    Ok(())
+}
+
+async fn api_error(response: Response, token_key: &str) -> DwdError {
+    let status = response.status();
+    if status == StatusCode::UNAUTHORIZED {
+        oauth::invalidate(token_key);
+    }
+    let message = match response.json::<Value>().await {
+        Ok(val) => val["error"]["message"].as_str().unwrap_or_default().to_owned(),
+        Err(e) => e.to_string(),
+    };
+    DwdError::from_status(
+        status,
+        format!("API response error: {} {}", error_style(status), error_style(message)),
+    )
+}
+
+fn service_account_key(conf: &ConfigGoogleCloudDns) -> Result<ServiceAccountKey, DwdError> {
+    let path = match conf.credentials_file.as_ref() {
+        Some(val) => val.to_owned(),
+        None => env::var("GOOGLE_APPLICATION_CREDENTIALS").map_err(|_| {
+            DwdError::config(
+                "Please set config.google_cloud_dns.credentials_file or env variable GOOGLE_APPLICATION_CREDENTIALS.",
+            )
+        })?,
+    };
+    let text = fs::read_to_string(&path)
+        .map_err(|e| DwdError::config(format!("Read the service account key {} failed: {}", path, e)))?;
+    serde_json::from_str(&text)
+        .map_err(|e| DwdError::config(format!("Parse the service account key {} failed: {}", path, e)))
+}
+
+fn token_uri<'a>(conf: &'a ConfigGoogleCloudDns, key: &'a ServiceAccountKey) -> &'a str {
+    conf.token_uri
+        .as_deref()
+        .or(key.token_uri.as_deref())
+        .unwrap_or(DEFAULT_TOKEN_URI)
+}
+
+fn token_key(conf: &ConfigGoogleCloudDns, key: &ServiceAccountKey) -> String {
+    format!("{}/{}", token_uri(conf, key), key.client_email)
+}
+
+/// Exchange a JWT signed by the service account for an access token.
+///
+/// The document of the flow: https://developers.google.com/identity/protocols/oauth2/service-account#httprest
+async fn access_token(
+    conf: &ConfigGoogleCloudDns,
+    client: &HttpClient,
+    key: &ServiceAccountKey,
+) -> Result<String, DwdError> {
+    let token_uri = token_uri(conf, key);
+    oauth::token(client, &token_key(conf, key), token_uri, || {
+        Ok(vec![
+            (
+                "grant_type",
+                String::from("urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ),
+            ("assertion", jwt(key, token_uri, Utc::now().timestamp())?),
+        ])
+    })
+    .await
+}
+
+fn jwt(key: &ServiceAccountKey, audience: &str, now: i64) -> Result<String, DwdError> {
+    let header = json!({"alg": "RS256", "typ": "JWT", "kid": key.private_key_id});
+    let claims = json!({
+        "iss": key.client_email,
+        "scope": SCOPE,
+        "aud": audience,
+        "iat": now,
+        "exp": now + JWT_LIFETIME,
+    });
+    let input = format!(
+        "{}.{}",
+        base64url(header.to_string().as_bytes()),
+        base64url(claims.to_string().as_bytes())
+    );
+
+    let sign = || {
+        let pkey = PKey::private_key_from_pem(key.private_key.as_bytes())?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+        signer.update(input.as_bytes())?;
+        signer.sign_to_vec()
+    };
+    let signature = sign().map_err(|e| DwdError::config(format!("Sign the JWT with the private key failed: {}", e)))?;
+
+    Ok(format!("{}.{}", input, base64url(&signature)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+    use openssl::{rsa::Rsa, sign::Verifier};
+
+    #[tokio::test]
+    async fn update_should_replace_record_set_with_cached_token() {
+        let server = MockServer::start(|request| {
+            if request.path == "/token" {
+                MockResponse::json(
+                    200,
+                    json!({"access_token": "t", "expires_in": 3600, "token_type": "Bearer"}),
+                )
+            } else if request.path.contains("/rrsets") {
+                MockResponse::json(
+                    200,
+                    json!({"rrsets": [{"name": "home.example.com.", "type": "A", "ttl": 300, "rrdatas": ["1.1.1.1"]}]}),
+                )
+            } else {
+                MockResponse::json(200, json!({"id": "1", "status": "pending"}))
+            }
+        })
+        .await;
+
+        let rsa = Rsa::generate(2048).unwrap();
+        let path = env::temp_dir().join(format!("dwd-google-key-{}.json", std::process::id()));
+        let key = json!({
+            "type": "service_account",
+            "project_id": "my-project",
+            "private_key_id": "k1",
+            "private_key": String::from_utf8(rsa.private_key_to_pem().unwrap()).unwrap(),
+            "client_email": "dwd@my-project.iam.gserviceaccount.com",
+            "token_uri": format!("{}token", server.url),
+        });
+        fs::write(&path, key.to_string()).unwrap();
+        let conf = ConfigGoogleCloudDns {
+            project: None,
+            managed_zone: String::from("example-com"),
+            record_name: String::from("home.example.com"),
+            record_ttl: None,
+            record_type: None,
+            credentials_file: Some(path.to_string_lossy().into_owned()),
+            token_uri: None,
+            api_base: Some(server.url.clone()),
+        };
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update(&conf, &client, &"2.2.2.2".parse().unwrap()).await.unwrap();
+        update(&conf, &client, &"2.2.2.2".parse().unwrap()).await.unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 5);
+        assert_eq!(requests.iter().filter(|request| request.path == "/token").count(), 1);
+
+        // The JWT is signed by the private key of the service account.
+        let form = Url::parse(&format!("http://localhost/?{}", requests[0].body)).unwrap();
+        let assertion = form.query_pairs().find(|(key, _)| key == "assertion").unwrap().1;
+        let (input, signature) = assertion.rsplit_once('.').unwrap();
+        let mut signature = signature.replace('-', "+").replace('_', "/");
+        while signature.len() % 4 != 0 {
+            signature.push('=');
+        }
+        let signature = openssl::base64::decode_block(&signature).unwrap();
+        let pkey = PKey::from_rsa(rsa).unwrap();
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &pkey).unwrap();
+        verifier.update(input.as_bytes()).unwrap();
+        assert!(verifier.verify(&signature).unwrap());
+
+        assert_eq!(
+            requests[1].path,
+            "/projects/my-project/managedZones/example-com/rrsets?name=home.example.com.&type=A"
+        );
+        assert_eq!(requests[2].headers["authorization"], "Bearer t");
+        let change = serde_json::from_str::<Value>(&requests[2].body).unwrap();
+        assert_eq!(change["deletions"][0]["rrdatas"], json!(["1.1.1.1"]));
+        assert_eq!(change["additions"][0]["rrdatas"], json!(["2.2.2.2"]));
+        assert_eq!(change["additions"][0]["ttl"], 300);
+    }
+
+    #[tokio::test]
+    async fn update_set_should_skip_empty_set() {
+        let server = MockServer::start(|_| MockResponse::json(200, json!({"rrsets": []}))).await;
+        let conf = ConfigGoogleCloudDns {
+            project: Some(String::from("my-project")),
+            managed_zone: String::from("example-com"),
+            record_name: String::from("home.example.com"),
+            record_ttl: None,
+            record_type: None,
+            credentials_file: Some(String::from("/nonexistent/key.json")),
+            token_uri: None,
+            api_base: Some(server.url.clone()),
+        };
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update_set(&conf, &client, &["2001:db8::1".parse().unwrap()])
+            .await
+            .unwrap();
+        assert!(server.requests().is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+use reqwest::{RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::net::IpAddr;
+
+use super::super::error::{DwdError, ErrorKind};
+use super::super::http::HttpClient;
+use super::rest::{self, ApiFuture, RecordApi, RestRecord};
+
+const BASE_URL: &str = "https://dns.hetzner.com/api/v1/";
+const DEFAULT_TTL: u32 = 300;
+const MIN_TTL: u32 = 60;
+const PAGE_SIZE: u32 = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigHetzner {
+    /// The name of the zone, e.g. `example.com`.
+    pub domain: String,
+    /// The ID of the zone, it is looked up by the name if omitted.
+    pub zone_id: Option<String>,
+    /// The host record, `@` for the zone itself.
+    pub record_host: Option<String>,
+    /// The TTL of records, at least 60.
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// The API token, or set it through env variable HETZNER_DNS_TOKEN.
+    pub token: Option<String>,
+    /// The API base, `https://dns.hetzner.com/api/v1/` by default.
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ZoneList {
+    zones: Vec<Zone>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Zone {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordList {
+    #[serde(default)]
+    records: Vec<Record>,
+    meta: Option<Meta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Meta {
+    pagination: Pagination,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pagination {
+    last_page: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Record {
+    id: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    name: String,
+    value: String,
+    ttl: Option<u32>,
+}
+
+struct Api<'a> {
+    conf: &'a ConfigHetzner,
+    client: &'a HttpClient,
+    base_url: Url,
+    token: String,
+    zone_id: String,
+}
+
+/// Update DNS record on Hetzner DNS
+///
+/// The document of Hetzner DNS API: https://dns.hetzner.com/api-docs
+///
+/// # Example:
+/// ```rust
+/// hetzner::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigHetzner, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let update_inner = async { rest::update(&Api::new(conf, client).await?, ip).await };
+    update_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+/// Make the records of the host contain exactly the given IPs, one record per IP.
+pub async fn update_set(conf: &ConfigHetzner, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let update_set_inner = async { rest::update_set(&Api::new(conf, client).await?, record_type, ips).await };
+    update_set_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+pub fn record_name(conf: &ConfigHetzner) -> String {
+    rest::record_name(&conf.domain, conf.record_host.as_deref())
+}
+
+fn api_message(val: &Value) -> Option<&str> {
+    val["error"]["message"].as_str().or_else(|| val["message"].as_str())
+}
+
+impl<'a> Api<'a> {
+    async fn new(conf: &'a ConfigHetzner, client: &'a HttpClient) -> Result<Api<'a>, DwdError> {
+        let token = match conf.token.as_ref() {
+            Some(val) => val.to_owned(),
+            None => env::var("HETZNER_DNS_TOKEN")
+                .map_err(|_| DwdError::config("Please set env variable HETZNER_DNS_TOKEN."))?,
+        };
+        let mut api = Api {
+            conf,
+            client,
+            base_url: Url::parse(conf.base_url.as_deref().unwrap_or(BASE_URL))?,
+            token,
+            zone_id: conf.zone_id.clone().unwrap_or_default(),
+        };
+        if api.zone_id.is_empty() {
+            api.zone_id = api.find_zone_id().await?;
+        }
+        Ok(api)
+    }
+
+    async fn find_zone_id(&self) -> Result<String, DwdError> {
+        let mut url = self.base_url.join("zones")?;
+        url.query_pairs_mut().append_pair("name", &self.conf.domain);
+        let zones = rest::send(self.client, self.request(self.client.get(url)), api_message)
+            .await?
+            .json::<ZoneList>()
+            .await?;
+        zones
+            .zones
+            .into_iter()
+            .find(|zone| zone.name == self.conf.domain)
+            .map(|zone| zone.id)
+            .ok_or_else(|| DwdError::new(ErrorKind::NotFound, format!("Zone {} is not found.", self.conf.domain)))
+    }
+
+    fn request(&self, builder: RequestBuilder) -> RequestBuilder {
+        builder.header("Auth-API-Token", &self.token)
+    }
+
+    fn host(&self) -> &str {
+        rest::relative_host(self.conf.record_host.as_deref())
+    }
+
+    fn body(&self, record_type: &str, value: String) -> Value {
+        json!({
+            "zone_id": self.zone_id,
+            "type": record_type,
+            "name": self.host(),
+            "value": value,
+            "ttl": self.ttl(),
+        })
+    }
+}
+
+impl RecordApi for Api<'_> {
+    fn ttl(&self) -> u32 {
+        rest::ttl(self.conf.record_ttl, DEFAULT_TTL, MIN_TTL)
+    }
+
+    fn list<'a>(&'a self, record_type: &'a str) -> ApiFuture<'a, Vec<RestRecord>> {
+        Box::pin(async move {
+            let mut records = Vec::new();
+            let mut page = 1;
+            loop {
+                let mut url = self.base_url.join("records")?;
+                url.query_pairs_mut()
+                    .append_pair("zone_id", &self.zone_id)
+                    .append_pair("page", &page.to_string())
+                    .append_pair("per_page", &PAGE_SIZE.to_string());
+                let ret = rest::send(self.client, self.request(self.client.get(url)), api_message)
+                    .await?
+                    .json::<RecordList>()
+                    .await?;
+                records.extend(
+                    ret.records
+                        .into_iter()
+                        .filter(|record| record.name == self.host() && record.record_type == record_type)
+                        .map(|record| RestRecord {
+                            id: record.id,
+                            value: record.value,
+                            ttl: record.ttl,
+                        }),
+                );
+
+                match ret.meta {
+                    Some(meta) if meta.pagination.last_page > page => page += 1,
+                    _ => break,
+                }
+            }
+            Ok(records)
+        })
+    }
+
+    fn create<'a>(&'a self, record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .post(self.base_url.join("records")?)
+                .json(&self.body(record_type, value));
+            rest::send(self.client, self.request(builder), api_message).await?;
+            Ok(())
+        })
+    }
+
+    fn update<'a>(&'a self, record: &'a RestRecord, record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .put(self.base_url.join(&format!("records/{}", record.id))?)
+                .json(&self.body(record_type, value));
+            rest::send(self.client, self.request(builder), api_message).await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, record: &'a RestRecord) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .delete(self.base_url.join(&format!("records/{}", record.id))?);
+            rest::send(self.client, self.request(builder), api_message).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+
+    #[tokio::test]
+    async fn update_should_find_zone_and_update_record() {
+        let server = MockServer::start(|request| {
+            if request.path.starts_with("/zones") {
+                MockResponse::json(200, json!({"zones": [{"id": "z1", "name": "example.com"}]}))
+            } else if request.path.contains("&page=1&") {
+                MockResponse::json(
+                    200,
+                    json!({
+                        "records": [{"id": "r1", "type": "AAAA", "name": "home", "value": "2001:db8::1", "ttl": 300}],
+                        "meta": {"pagination": {"page": 1, "per_page": 100, "last_page": 2, "total_entries": 2}}
+                    }),
+                )
+            } else if request.path.contains("&page=2&") {
+                MockResponse::json(
+                    200,
+                    json!({
+                        "records": [{"id": "r2", "type": "A", "name": "home", "value": "1.1.1.1"}],
+                        "meta": {"pagination": {"page": 2, "per_page": 100, "last_page": 2, "total_entries": 2}}
+                    }),
+                )
+            } else {
+                MockResponse::json(200, json!({"record": {"id": "r2"}}))
+            }
+        })
+        .await;
+
+        let conf = ConfigHetzner {
+            domain: String::from("example.com"),
+            zone_id: None,
+            record_host: Some(String::from("home")),
+            record_ttl: None,
+            record_type: None,
+            token: Some(String::from("t")),
+            base_url: Some(server.url.clone()),
+        };
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update(&conf, &client, &"2.2.2.2".parse().unwrap()).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0].path, "/zones?name=example.com");
+        assert_eq!(requests[0].headers["auth-api-token"], "t");
+        assert_eq!(requests[1].path, "/records?zone_id=z1&page=1&per_page=100");
+        assert_eq!(requests[3].method, "PUT");
+        assert_eq!(requests[3].path, "/records/r2");
+        assert_eq!(
+            serde_json::from_str::<Value>(&requests[3].body).unwrap(),
+            json!({"zone_id": "z1", "type": "A", "name": "home", "value": "2.2.2.2", "ttl": 300})
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::net::IpAddr;
+
+use super::super::error::{DwdError, ErrorKind};
+use super::super::http::HttpClient;
+use super::rest::{self, ApiFuture, RecordApi, RestRecord};
+
+const BASE_URL: &str = "https://api.linode.com/v4/";
+const DEFAULT_TTL: u32 = 300;
+const PAGE_SIZE: u32 = 500;
+/// Linode rounds a TTL up to one of these values, so it is rounded here as well to compare with the records.
+const VALID_TTLS: [u32; 14] = [
+    30, 120, 300, 3600, 7200, 14400, 28800, 57600, 86400, 172800, 345600, 604800, 1209600, 2419200,
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigLinode {
+    pub domain: String,
+    /// The ID of the domain, it is looked up by the name if omitted.
+    pub domain_id: Option<u64>,
+    /// The host record, `@` for the domain itself.
+    pub record_host: Option<String>,
+    /// The TTL of records, rounded up to 30, 120, 300, 3600 and so on.
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// The personal access token, or set it through env variable LINODE_TOKEN.
+    pub token: Option<String>,
+    /// The API base, `https://api.linode.com/v4/` by default.
+    pub base_url: Option<String>,
+}
+
+/// A page of the list APIs.
+#[derive(Debug, Deserialize)]
+struct Page<T> {
+    data: Vec<T>,
+    page: u32,
+    pages: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Domain {
+    id: u64,
+    domain: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Record {
+    id: u64,
+    #[serde(rename = "type")]
+    record_type: String,
+    name: String,
+    target: String,
+    ttl_sec: u32,
+}
+
+struct Api<'a> {
+    conf: &'a ConfigLinode,
+    client: &'a HttpClient,
+    records_url: Url,
+    token: String,
+}
+
+/// Update DNS record on Linode
+///
+/// The document of Linode API: https://techdocs.akamai.com/linode-api/reference/get-domain-records
+///
+/// # Example:
+/// ```rust
+/// linode::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigLinode, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let update_inner = async { rest::update(&Api::new(conf, client).await?, ip).await };
+    update_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+/// Make the records of the host contain exactly the given IPs, one record per IP.
+pub async fn update_set(conf: &ConfigLinode, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let update_set_inner = async { rest::update_set(&Api::new(conf, client).await?, record_type, ips).await };
+    update_set_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+pub fn record_name(conf: &ConfigLinode) -> String {
+    rest::record_name(&conf.domain, conf.record_host.as_deref())
+}
+
+fn api_message(val: &Value) -> Option<&str> {
+    val["errors"][0]["reason"].as_str()
+}
+
+impl<'a> Api<'a> {
+    async fn new(conf: &'a ConfigLinode, client: &'a HttpClient) -> Result<Api<'a>, DwdError> {
+        let token = match conf.token.as_ref() {
+            Some(val) => val.to_owned(),
+            None => env::var("LINODE_TOKEN").map_err(|_| DwdError::config("Please set env variable LINODE_TOKEN."))?,
+        };
+        let base_url = Url::parse(conf.base_url.as_deref().unwrap_or(BASE_URL))?;
+        let domain_id = match conf.domain_id {
+            Some(val) => val,
+            None => find_domain_id(conf, client, &base_url, &token).await?,
+        };
+        Ok(Api {
+            conf,
+            client,
+            records_url: base_url.join(&format!("domains/{}/records/", domain_id))?,
+            token,
+        })
+    }
+
+    /// The name of the domain itself is empty on Linode.
+    fn host(&self) -> &str {
+        match rest::relative_host(self.conf.record_host.as_deref()) {
+            "@" => "",
+            host => host,
+        }
+    }
+}
+
+async fn find_domain_id(
+    conf: &ConfigLinode,
+    client: &HttpClient,
+    base_url: &Url,
+    token: &str,
+) -> Result<u64, DwdError> {
+    let builder = client
+        .get(base_url.join("domains")?)
+        .bearer_auth(token)
+        .header("X-Filter", json!({"domain": conf.domain}).to_string());
+    let domains = rest::send(client, builder, api_message)
+        .await?
+        .json::<Page<Domain>>()
+        .await?;
+    domains
+        .data
+        .into_iter()
+        .find(|domain| domain.domain == conf.domain)
+        .map(|domain| domain.id)
+        .ok_or_else(|| DwdError::new(ErrorKind::NotFound, format!("Domain {} is not found.", conf.domain)))
+}
+
+impl RecordApi for Api<'_> {
+    fn ttl(&self) -> u32 {
+        let ttl = rest::ttl(self.conf.record_ttl, DEFAULT_TTL, VALID_TTLS[0]);
+        VALID_TTLS
+            .iter()
+            .find(|val| **val >= ttl)
+            .cloned()
+            .unwrap_or(VALID_TTLS[VALID_TTLS.len() - 1])
+    }
+
+    fn list<'a>(&'a self, record_type: &'a str) -> ApiFuture<'a, Vec<RestRecord>> {
+        Box::pin(async move {
+            let mut records = Vec::new();
+            let mut page = 1;
+            loop {
+                let builder = self
+                    .client
+                    .get(self.records_url.clone())
+                    .query(&[("page", page), ("page_size", PAGE_SIZE)])
+                    .bearer_auth(&self.token);
+                let ret = rest::send(self.client, builder, api_message)
+                    .await?
+                    .json::<Page<Record>>()
+                    .await?;
+                records.extend(
+                    ret.data
+                        .into_iter()
+                        .filter(|record| record.name == self.host() && record.record_type == record_type)
+                        .map(|record| RestRecord {
+                            id: record.id.to_string(),
+                            value: record.target,
+                            ttl: Some(record.ttl_sec),
+                        }),
+                );
+
+                if ret.page >= ret.pages {
+                    break;
+                }
+                page = ret.page + 1;
+            }
+            Ok(records)
+        })
+    }
+
+    fn create<'a>(&'a self, record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .post(self.records_url.clone())
+                .bearer_auth(&self.token)
+                .json(&json!({
+                    "type": record_type,
+                    "name": self.host(),
+                    "target": value,
+                    "ttl_sec": self.ttl(),
+                }));
+            rest::send(self.client, builder, api_message).await?;
+            Ok(())
+        })
+    }
+
+    fn update<'a>(&'a self, record: &'a RestRecord, _record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .put(self.records_url.join(&record.id)?)
+                .bearer_auth(&self.token)
+                .json(&json!({"target": value, "ttl_sec": self.ttl()}));
+            rest::send(self.client, builder, api_message).await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, record: &'a RestRecord) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .delete(self.records_url.join(&record.id)?)
+                .bearer_auth(&self.token);
+            rest::send(self.client, builder, api_message).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+
+    #[tokio::test]
+    async fn update_should_create_record_of_apex() {
+        let server = MockServer::start(|request| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/domains") => MockResponse::json(
+                200,
+                json!({"data": [{"id": 7, "domain": "example.com"}], "page": 1, "pages": 1, "results": 1}),
+            ),
+            ("GET", path) if path.contains("page=1") => MockResponse::json(
+                200,
+                json!({
+                    "data": [{"id": 1, "type": "A", "name": "www", "target": "1.1.1.1", "ttl_sec": 300}],
+                    "page": 1, "pages": 2, "results": 2
+                }),
+            ),
+            ("GET", _) => MockResponse::json(
+                200,
+                json!({
+                    "data": [{"id": 2, "type": "AAAA", "name": "", "target": "2001:db8::1", "ttl_sec": 300}],
+                    "page": 2, "pages": 2, "results": 2
+                }),
+            ),
+            _ => MockResponse::json(200, json!({"id": 3})),
+        })
+        .await;
+
+        let conf = ConfigLinode {
+            domain: String::from("example.com"),
+            domain_id: None,
+            record_host: None,
+            record_ttl: Some(60),
+            record_type: None,
+            token: Some(String::from("t")),
+            base_url: Some(server.url.clone()),
+        };
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update(&conf, &client, &"2.2.2.2".parse().unwrap()).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0].headers["x-filter"], r#"{"domain":"example.com"}"#);
+        assert_eq!(requests[1].path, "/domains/7/records/?page=1&page_size=500");
+        assert_eq!(requests[2].path, "/domains/7/records/?page=2&page_size=500");
+        assert_eq!(requests[3].method, "POST");
+        assert_eq!(
+            serde_json::from_str::<Value>(&requests[3].body).unwrap(),
+            json!({"type": "A", "name": "", "target": "2.2.2.2", "ttl_sec": 120})
+        );
+    }
+}
@@ -2,24 +2,124 @@
 //! At last don't forget to add it to the `fn update_record` below.
 
 use lazy_static::lazy_static;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use simple_error::SimpleError;
 use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    time::Instant,
+};
+use tokio::time;
 
-use super::config::Config;
+use super::control;
+use super::error::{DwdError, ErrorKind};
+use super::hooks::{self, Context, Hook};
+use super::http::HttpClient;
+use super::metrics;
+use super::mqtt;
+use super::notify::{self, Event, EventKind};
+use super::supervisor;
 use super::util::{error_style, info_style};
 
+pub mod alidns;
+pub mod azure_dns;
+pub mod digitalocean;
+pub mod dnspod;
+pub mod dyndns2;
 pub mod dynv6_com;
+pub mod gandi;
+pub mod google_cloud_dns;
+pub mod hetzner;
+pub mod linode;
 pub mod name_com;
+mod oauth;
+pub mod porkbun;
+mod rest;
+pub mod rfc2136;
+pub mod route53;
+mod signature;
+pub mod vultr;
 
 lazy_static! {
-    pub static ref DNS_PROVIDERS: Vec<&'static str> = vec!["name.com", "dynv6.com"];
+    pub static ref DNS_PROVIDERS: Vec<&'static str> = vec!["name.com", "dynv6.com", "dyndns2", "rfc2136", "route53", "alidns", "dnspod", "google_cloud_dns", "azure_dns", "digitalocean", "hetzner", "linode", "vultr", "porkbun", "gandi"];
+    /// The rate limited updates which are scheduled to retry, keyed by provider and IP so they are not duplicated.
+    static ref PENDING_RETRIES: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    /// The targets whose credentials are rejected, they are not updated any more until restart, because retrying
+    /// with a bad token never works and may get the account locked.
+    static ref DISABLED_TARGETS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    /// The targets which failed last time, a recovery is notified when they succeed again.
+    static ref FAILING_TARGETS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    /// The IPs which were published to the targets successfully, keyed by target and record type.
+    static ref PUBLISHED_IPS: Mutex<HashMap<String, IpAddr>> = Mutex::new(HashMap::new());
 }
 
-#[derive(Debug)]
+/// The DNS providers which an IP will be published to, and the configs of them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigDnsTarget {
+    #[serde(default)]
+    pub dns_provider: Vec<String>,
+
+    pub name_com: Option<name_com::ConfigNameCom>,
+    pub dynv6_com: Option<dynv6_com::ConfigDynv6Com>,
+    pub dyndns2: Option<dyndns2::ConfigDyndns2>,
+    pub rfc2136: Option<rfc2136::ConfigRfc2136>,
+    pub route53: Option<route53::ConfigRoute53>,
+    pub alidns: Option<alidns::ConfigAlidns>,
+    pub dnspod: Option<dnspod::ConfigDnspod>,
+    pub google_cloud_dns: Option<google_cloud_dns::ConfigGoogleCloudDns>,
+    pub azure_dns: Option<azure_dns::ConfigAzureDns>,
+    pub digitalocean: Option<digitalocean::ConfigDigitalOcean>,
+    pub hetzner: Option<hetzner::ConfigHetzner>,
+    pub linode: Option<linode::ConfigLinode>,
+    pub vultr: Option<vultr::ConfigVultr>,
+    pub porkbun: Option<porkbun::ConfigPorkbun>,
+    pub gandi: Option<gandi::ConfigGandi>,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum DnsProvider {
     NameCom,
     Dynv6Com,
+    Dyndns2,
+    Rfc2136,
+    Route53,
+    Alidns,
+    Dnspod,
+    GoogleCloudDns,
+    AzureDns,
+    DigitalOcean,
+    Hetzner,
+    Linode,
+    Vultr,
+    Porkbun,
+    Gandi,
+}
+
+impl DnsProvider {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DnsProvider::NameCom => "name.com",
+            DnsProvider::Dynv6Com => "dynv6.com",
+            DnsProvider::Dyndns2 => "dyndns2",
+            DnsProvider::Rfc2136 => "rfc2136",
+            DnsProvider::Route53 => "route53",
+            DnsProvider::Alidns => "alidns",
+            DnsProvider::Dnspod => "dnspod",
+            DnsProvider::GoogleCloudDns => "google_cloud_dns",
+            DnsProvider::AzureDns => "azure_dns",
+            DnsProvider::DigitalOcean => "digitalocean",
+            DnsProvider::Hetzner => "hetzner",
+            DnsProvider::Linode => "linode",
+            DnsProvider::Vultr => "vultr",
+            DnsProvider::Porkbun => "porkbun",
+            DnsProvider::Gandi => "gandi",
+        }
+    }
 }
 
 impl TryFrom<&str> for DnsProvider {
@@ -29,6 +129,19 @@ impl TryFrom<&str> for DnsProvider {
         let provider = match input {
             "name.com" => DnsProvider::NameCom,
             "dynv6.com" => DnsProvider::Dynv6Com,
+            "dyndns2" => DnsProvider::Dyndns2,
+            "rfc2136" => DnsProvider::Rfc2136,
+            "route53" => DnsProvider::Route53,
+            "alidns" => DnsProvider::Alidns,
+            "dnspod" => DnsProvider::Dnspod,
+            "google_cloud_dns" => DnsProvider::GoogleCloudDns,
+            "azure_dns" => DnsProvider::AzureDns,
+            "digitalocean" => DnsProvider::DigitalOcean,
+            "hetzner" => DnsProvider::Hetzner,
+            "linode" => DnsProvider::Linode,
+            "vultr" => DnsProvider::Vultr,
+            "porkbun" => DnsProvider::Porkbun,
+            "gandi" => DnsProvider::Gandi,
             _ => return Err(SimpleError::new("Unknown provider")),
         };
         Ok(provider)
@@ -37,8 +150,11 @@ impl TryFrom<&str> for DnsProvider {
 
 /// Update record through DNS provider API
 ///
-/// ⚠️ This function suppose to be never crash!
-pub async fn update_dns_for_all(conf: &Config, ip: &str) {
+/// Return false if any provider failed with a transient error, so the update is worth retrying in the next round.
+/// Permanent errors like bad credentials or invalid configs are not retried.
+///
+/// Each provider is updated in a supervised task, a panic only fails that provider.
+pub async fn update_dns_for_all(conf: &ConfigDnsTarget, client: &HttpClient, ip: &IpAddr) -> bool {
     let providers = &conf.dns_provider;
     debug!(
         "Requesting {} to update DNS record ...",
@@ -46,34 +162,613 @@ pub async fn update_dns_for_all(conf: &Config, ip: &str) {
     );
 
     // TODO Replace this with somethind like Promise.all
+    let mut settled = true;
+    for name in providers.iter() {
+        let provider = match DnsProvider::try_from(name.as_str()) {
+            Ok(val) => val,
+            Err(_) => {
+                error!(target: "error", "DNS provider {} does not supported.", error_style(name));
+                continue;
+            }
+        };
+
+        let (conf, client, ip) = (conf.to_owned(), client.for_provider(name), *ip);
+        let ret = supervisor::catch_panic(&format!("Updating DNS provider {:?}", provider), async move {
+            update_record(&conf, &client, provider, &ip).await
+        })
+        .await;
+        settled &= ret.unwrap_or(false);
+    }
+
+    settled
+}
+
+/// Update the multi-value records through DNS provider API, so they contain exactly the given IPs.
+///
+/// Return true if all providers succeed, the disabled providers are skipped.
+pub async fn update_dns_set_for_all(conf: &ConfigDnsTarget, client: &HttpClient, ips: &[IpAddr]) -> bool {
+    let providers = &conf.dns_provider;
+    debug!(
+        "Requesting {} to update multi-value DNS records ...",
+        info_style(providers.join(", "))
+    );
+
+    let mut all_succeeded = true;
     for name in providers.iter() {
         let provider = match DnsProvider::try_from(name.as_str()) {
             Ok(val) => val,
             Err(_) => {
                 error!(target: "error", "DNS provider {} does not supported.", error_style(name));
+                all_succeeded = false;
                 continue;
             }
         };
 
-        update_record(conf, provider, ip).await;
+        if is_disabled(conf, provider) {
+            debug!("DNS provider {:?} is disabled, skip.", provider);
+            continue;
+        }
+
+        let (owned_conf, client, owned_ips) = (conf.to_owned(), client.for_provider(name), ips.to_vec());
+        let started_at = Instant::now();
+        let ret = supervisor::catch_panic(&format!("Updating DNS provider {:?}", provider), async move {
+            update_set_provider(&owned_conf, &client, provider, &owned_ips).await
+        })
+        .await;
+
+        let ret = match ret {
+            Some(val) => val,
+            None => {
+                metrics::dns_updated(provider.name(), "failure", started_at.elapsed());
+                control::report_dns_provider(provider.name(), Some(String::from("The provider panicked.")));
+                mqtt::report_dns_provider(provider.name(), Some("The provider panicked."));
+                all_succeeded = false;
+                continue;
+            }
+        };
+        metrics::dns_updated(provider.name(), outcome(&ret), started_at.elapsed());
+        let ret = ret.map_err(|e| e.with_provider(provider.name()));
+        control::report_dns_provider(provider.name(), ret.as_ref().err().map(|e| e.to_string()));
+        mqtt::report_dns_provider(provider.name(), ret.as_ref().err().map(|e| e.to_string()).as_deref());
+        if let Err(e) = ret {
+            error!(
+                target: "error",
+                event = "update_failed",
+                provider = provider.name(),
+                record = e.record.as_deref().unwrap_or_default(),
+                kind:% = e.kind;
+                "Update multi-value records on DNS provider {:?} failed, error: {}",
+                provider,
+                e
+            );
+            disable_on_auth_error(conf, provider, &e);
+            report_result(conf, provider, None, Err(&e));
+            all_succeeded = false;
+        } else {
+            report_result(conf, provider, None, Ok(()));
+            info!(
+                target: "success",
+                event = "update_succeeded",
+                provider = provider.name();
+                "Successfully updated multi-value records on DNS provider {:?}.",
+                provider
+            );
+        }
     }
+
+    all_succeeded
+}
+
+/// The names of records which the IP is published to, they are used in notifications.
+pub fn record_names(conf: &ConfigDnsTarget) -> Vec<String> {
+    conf.dns_provider
+        .iter()
+        .filter_map(|name| {
+            let record = record_name(conf, DnsProvider::try_from(name.as_str()).ok()?)?;
+            Some(format!("{} ({})", record, name))
+        })
+        .collect()
 }
 
-async fn update_record(conf: &Config, provider: DnsProvider, ip: &str) {
-    let ret = match provider {
+/// The keys of the targets of the providers, they are hashed as the configs contain credentials.
+pub fn target_keys(conf: &ConfigDnsTarget) -> Vec<String> {
+    conf.dns_provider
+        .iter()
+        .filter_map(|name| DnsProvider::try_from(name.as_str()).ok())
+        .map(|provider| signature::sha256_hex(target_key(conf, provider).as_bytes()))
+        .collect()
+}
+
+/// The keys of the targets which have got the IP, the failing and disabled ones are excluded.
+pub fn updated_target_keys(conf: &ConfigDnsTarget) -> Vec<String> {
+    let failing = FAILING_TARGETS
+        .lock()
+        .expect("The lock of failing targets should not be poisoned.");
+    conf.dns_provider
+        .iter()
+        .filter_map(|name| DnsProvider::try_from(name.as_str()).ok())
+        .map(|provider| target_key(conf, provider))
+        .filter(|key| !failing.contains(key) && !is_disabled_key(key))
+        .map(|key| signature::sha256_hex(key.as_bytes()))
+        .collect()
+}
+
+/// The name of the record which the provider updates.
+fn record_name(conf: &ConfigDnsTarget, provider: DnsProvider) -> Option<String> {
+    match provider {
+        DnsProvider::NameCom => Some(name_com::record_name(conf.name_com.as_ref()?)),
+        DnsProvider::Dynv6Com => Some(conf.dynv6_com.as_ref()?.zone.to_owned()),
+        DnsProvider::Dyndns2 => Some(dyndns2::record_name(conf.dyndns2.as_ref()?)),
+        DnsProvider::Rfc2136 => Some(rfc2136::record_name(conf.rfc2136.as_ref()?)),
+        DnsProvider::Route53 => Some(route53::record_name(conf.route53.as_ref()?)),
+        DnsProvider::Alidns => Some(alidns::record_name(conf.alidns.as_ref()?)),
+        DnsProvider::Dnspod => Some(dnspod::record_name(conf.dnspod.as_ref()?)),
+        DnsProvider::GoogleCloudDns => Some(google_cloud_dns::record_name(conf.google_cloud_dns.as_ref()?)),
+        DnsProvider::AzureDns => Some(azure_dns::record_name(conf.azure_dns.as_ref()?)),
+        DnsProvider::DigitalOcean => Some(digitalocean::record_name(conf.digitalocean.as_ref()?)),
+        DnsProvider::Hetzner => Some(hetzner::record_name(conf.hetzner.as_ref()?)),
+        DnsProvider::Linode => Some(linode::record_name(conf.linode.as_ref()?)),
+        DnsProvider::Vultr => Some(vultr::record_name(conf.vultr.as_ref()?)),
+        DnsProvider::Porkbun => Some(porkbun::record_name(conf.porkbun.as_ref()?)),
+        DnsProvider::Gandi => Some(gandi::record_name(conf.gandi.as_ref()?)),
+    }
+}
+
+/// Pick the IPs of the record type for a multi-value update.
+///
+/// It is `None` if there is no IP of the type, e.g. all IPv6 WANs are down, then the update should be skipped, because
+/// the providers would delete all records of the host otherwise, and stale IPs are better than an unresolvable
+/// hostname.
+pub fn ips_of_type(record_type: &str, ips: &[IpAddr]) -> Result<Option<Vec<IpAddr>>, DwdError> {
+    if record_type != "A" && record_type != "AAAA" {
+        return Err(DwdError::config(format!(
+            "Invalid record_type {:?} of multi-value records, it should be A or AAAA.",
+            record_type
+        )));
+    }
+    let ips = ips
+        .iter()
+        .filter(|ip| ip.is_ipv4() == (record_type == "A"))
+        .cloned()
+        .collect::<Vec<_>>();
+    if ips.is_empty() {
+        warn!(
+            event = "update_skipped";
+            "No {} record to publish, keep the records unchanged.",
+            record_type
+        );
+        return Ok(None);
+    }
+    Ok(Some(ips))
+}
+
+/// Compare the existing records with the expected IPs, return the stale records which should be deleted and the IPs
+/// which are missing.
+pub fn plan_record_set<T>(existing: Vec<(T, String)>, ips: &[IpAddr]) -> (Vec<T>, Vec<IpAddr>) {
+    let mut found = Vec::new();
+    let mut stale = Vec::new();
+    for (record, answer) in existing {
+        match answer.parse::<IpAddr>() {
+            Ok(ip) if ips.contains(&ip) && !found.contains(&ip) => found.push(ip),
+            _ => stale.push(record),
+        }
+    }
+
+    let missing = ips.iter().filter(|ip| !found.contains(ip)).cloned().collect();
+
+    (stale, missing)
+}
+
+fn update_record<'a>(
+    conf: &'a ConfigDnsTarget,
+    client: &'a HttpClient,
+    provider: DnsProvider,
+    ip: &'a IpAddr,
+) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+    Box::pin(async move {
+        if is_disabled(conf, provider) {
+            debug!("DNS provider {:?} is disabled, skip.", provider);
+            return true;
+        }
+
+        let started_at = Instant::now();
+        let ret = update_provider(conf, client, provider, ip)
+            .await
+            .map_err(|e| e.with_provider(provider.name()));
+        metrics::dns_updated(provider.name(), outcome(&ret), started_at.elapsed());
+        control::report_dns_provider(provider.name(), ret.as_ref().err().map(|e| e.to_string()));
+        mqtt::report_dns_provider(provider.name(), ret.as_ref().err().map(|e| e.to_string()).as_deref());
+
+        match ret {
+            Ok(_) => {
+                info!(
+                    target: "success",
+                    event = "update_succeeded",
+                    provider = provider.name(),
+                    ip:% = ip;
+                    "Successfully updated DNS provider {:?}.",
+                    provider
+                );
+                report_result(conf, provider, Some(*ip), Ok(()));
+                true
+            }
+            Err(e) => match (e.kind, e.retry_after) {
+                (ErrorKind::RateLimit, Some(retry_after)) => {
+                    warn!(
+                        event = "update_rate_limited",
+                        provider = provider.name(),
+                        ip:% = ip;
+                        "DNS provider {:?} is rate limited, retry after {}s.",
+                        provider,
+                        retry_after.as_secs()
+                    );
+                    schedule_retry(conf, client, provider, ip, retry_after);
+                    // Not settled until the IP is published, so the next round retries if the retry fails as well.
+                    false
+                }
+                _ => {
+                    error!(
+                        target: "error",
+                        event = "update_failed",
+                        provider = provider.name(),
+                        record = e.record.as_deref().unwrap_or_default(),
+                        ip:% = ip,
+                        kind:% = e.kind;
+                        "Update DNS provider {:?} failed, error: {}",
+                        provider,
+                        e
+                    );
+                    disable_on_auth_error(conf, provider, &e);
+                    report_result(conf, provider, Some(*ip), Err(&e));
+                    !e.is_transient()
+                }
+            },
+        }
+    })
+}
+
+/// Run the hook of the result, and notify the failure of a target, or the recovery if it failed last time.
+fn report_result(conf: &ConfigDnsTarget, provider: DnsProvider, ip: Option<IpAddr>, ret: Result<(), &DwdError>) {
+    let key = target_key(conf, provider);
+    let record = ret
+        .err()
+        .and_then(|e| e.record.clone())
+        .or_else(|| record_name(conf, provider));
+    let context = Context {
+        old_ip: ip.and_then(|ip| previous_ip(&key, ip, ret.is_ok())),
+        new_ip: ip,
+        provider: Some(provider.name().to_owned()),
+        record: record.clone(),
+        error: ret.err().map(|e| e.to_string()),
+        ..Default::default()
+    };
+    hooks::run(
+        if ret.is_ok() {
+            Hook::UpdateSuccess
+        } else {
+            Hook::UpdateFailure
+        },
+        context,
+    );
+
+    let mut failing = FAILING_TARGETS
+        .lock()
+        .expect("The lock of failing targets should not be poisoned.");
+    let event = match ret {
+        Ok(_) if failing.remove(&key) => Event::new(EventKind::UpdateRecovered),
+        Ok(_) => return,
+        Err(e) => {
+            failing.insert(key);
+            Event::new(EventKind::UpdateFailed).with_error(&e.to_string())
+        }
+    };
+    // The recovery carries the record as well, so it only clears the failure of the same record.
+    notify::send(
+        event
+            .with_provider(provider.name())
+            .with_record(record_name(conf, provider).or(record).as_deref())
+            .with_ips(None, ip),
+    );
+}
+
+/// The IP which was published to the target before, the IP is recorded as published if the update succeeded.
+fn previous_ip(target: &str, ip: IpAddr, succeeded: bool) -> Option<IpAddr> {
+    let key = format!("{}/{}", target, if ip.is_ipv4() { "A" } else { "AAAA" });
+    let mut published = PUBLISHED_IPS
+        .lock()
+        .expect("The lock of published IPs should not be poisoned.");
+    if succeeded {
+        published.insert(key, ip)
+    } else {
+        published.get(&key).cloned()
+    }
+}
+
+/// The outcome of an update in metrics.
+fn outcome(ret: &Result<(), DwdError>) -> &'static str {
+    match ret {
+        Ok(_) => "success",
+        Err(e) if e.kind == ErrorKind::RateLimit => "rate_limited",
+        Err(_) => "failure",
+    }
+}
+
+/// Retry the rate limited update in background, so other providers and the next round are not held up.
+fn schedule_retry(
+    conf: &ConfigDnsTarget,
+    client: &HttpClient,
+    provider: DnsProvider,
+    ip: &IpAddr,
+    retry_after: std::time::Duration,
+) {
+    let key = format!("{:?}/{}", provider, ip);
+    let mut pending = PENDING_RETRIES
+        .lock()
+        .expect("The lock of pending retries should not be poisoned.");
+    if !pending.insert(key.clone()) {
+        debug!("The retry of {} is already scheduled, skip.", key);
+        return;
+    }
+
+    let conf = conf.clone();
+    let client = client.clone();
+    let ip = *ip;
+    tokio::spawn(async move {
+        time::sleep(retry_after).await;
+        PENDING_RETRIES
+            .lock()
+            .expect("The lock of pending retries should not be poisoned.")
+            .remove(&key);
+        if control::is_paused() {
+            debug!("Updates are paused, drop the retry of {}.", key);
+            return;
+        }
+        update_record(&conf, &client, provider, &ip).await;
+    });
+}
+
+async fn update_provider(
+    conf: &ConfigDnsTarget,
+    client: &HttpClient,
+    provider: DnsProvider,
+    ip: &IpAddr,
+) -> Result<(), DwdError> {
+    match provider {
         DnsProvider::NameCom => match conf.name_com.as_ref() {
-            Some(sub_conf) => name_com::update(sub_conf, ip).await,
-            None => Err("The config.name_com is required.".into()),
+            Some(sub_conf) => name_com::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.name_com is required.")),
         },
         DnsProvider::Dynv6Com => match conf.dynv6_com.as_ref() {
-            Some(sub_conf) => dynv6_com::update(sub_conf, ip).await,
-            None => Err("The config.dynv6_com is required.".into()),
+            Some(sub_conf) => dynv6_com::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.dynv6_com is required.")),
         },
-    };
+        DnsProvider::Dyndns2 => match conf.dyndns2.as_ref() {
+            Some(sub_conf) => dyndns2::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.dyndns2 is required.")),
+        },
+        DnsProvider::Rfc2136 => match conf.rfc2136.as_ref() {
+            Some(sub_conf) => rfc2136::update(sub_conf, ip).await,
+            None => Err(DwdError::config("The config.rfc2136 is required.")),
+        },
+        DnsProvider::Route53 => match conf.route53.as_ref() {
+            Some(sub_conf) => route53::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.route53 is required.")),
+        },
+        DnsProvider::Alidns => match conf.alidns.as_ref() {
+            Some(sub_conf) => alidns::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.alidns is required.")),
+        },
+        DnsProvider::Dnspod => match conf.dnspod.as_ref() {
+            Some(sub_conf) => dnspod::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.dnspod is required.")),
+        },
+        DnsProvider::GoogleCloudDns => match conf.google_cloud_dns.as_ref() {
+            Some(sub_conf) => google_cloud_dns::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.google_cloud_dns is required.")),
+        },
+        DnsProvider::AzureDns => match conf.azure_dns.as_ref() {
+            Some(sub_conf) => azure_dns::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.azure_dns is required.")),
+        },
+        DnsProvider::DigitalOcean => match conf.digitalocean.as_ref() {
+            Some(sub_conf) => digitalocean::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.digitalocean is required.")),
+        },
+        DnsProvider::Hetzner => match conf.hetzner.as_ref() {
+            Some(sub_conf) => hetzner::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.hetzner is required.")),
+        },
+        DnsProvider::Linode => match conf.linode.as_ref() {
+            Some(sub_conf) => linode::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.linode is required.")),
+        },
+        DnsProvider::Vultr => match conf.vultr.as_ref() {
+            Some(sub_conf) => vultr::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.vultr is required.")),
+        },
+        DnsProvider::Porkbun => match conf.porkbun.as_ref() {
+            Some(sub_conf) => porkbun::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.porkbun is required.")),
+        },
+        DnsProvider::Gandi => match conf.gandi.as_ref() {
+            Some(sub_conf) => gandi::update(sub_conf, client, ip).await,
+            None => Err(DwdError::config("The config.gandi is required.")),
+        },
+    }
+}
 
-    if let Err(e) = ret {
-        error!(target: "error", "Update DNS provider {:?} failed, error: {}", provider, e);
-    } else {
-        info!( target: "success", "Successfully updated DNS provider {:?}.", provider );
+async fn update_set_provider(
+    conf: &ConfigDnsTarget,
+    client: &HttpClient,
+    provider: DnsProvider,
+    ips: &[IpAddr],
+) -> Result<(), DwdError> {
+    match provider {
+        DnsProvider::NameCom => match conf.name_com.as_ref() {
+            Some(sub_conf) => name_com::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.name_com is required.")),
+        },
+        DnsProvider::Dynv6Com => match conf.dynv6_com.as_ref() {
+            Some(sub_conf) => dynv6_com::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.dynv6_com is required.")),
+        },
+        // The protocol sets one address of each family for a hostname, it could not hold multiple records.
+        DnsProvider::Dyndns2 => Err(DwdError::config(
+            "The dyndns2 protocol does not support multi-value records.",
+        )),
+        DnsProvider::Rfc2136 => match conf.rfc2136.as_ref() {
+            Some(sub_conf) => rfc2136::update_set(sub_conf, ips).await,
+            None => Err(DwdError::config("The config.rfc2136 is required.")),
+        },
+        DnsProvider::Route53 => match conf.route53.as_ref() {
+            Some(sub_conf) => route53::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.route53 is required.")),
+        },
+        DnsProvider::Alidns => match conf.alidns.as_ref() {
+            Some(sub_conf) => alidns::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.alidns is required.")),
+        },
+        DnsProvider::Dnspod => match conf.dnspod.as_ref() {
+            Some(sub_conf) => dnspod::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.dnspod is required.")),
+        },
+        DnsProvider::GoogleCloudDns => match conf.google_cloud_dns.as_ref() {
+            Some(sub_conf) => google_cloud_dns::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.google_cloud_dns is required.")),
+        },
+        DnsProvider::AzureDns => match conf.azure_dns.as_ref() {
+            Some(sub_conf) => azure_dns::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.azure_dns is required.")),
+        },
+        DnsProvider::DigitalOcean => match conf.digitalocean.as_ref() {
+            Some(sub_conf) => digitalocean::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.digitalocean is required.")),
+        },
+        DnsProvider::Hetzner => match conf.hetzner.as_ref() {
+            Some(sub_conf) => hetzner::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.hetzner is required.")),
+        },
+        DnsProvider::Linode => match conf.linode.as_ref() {
+            Some(sub_conf) => linode::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.linode is required.")),
+        },
+        DnsProvider::Vultr => match conf.vultr.as_ref() {
+            Some(sub_conf) => vultr::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.vultr is required.")),
+        },
+        DnsProvider::Porkbun => match conf.porkbun.as_ref() {
+            Some(sub_conf) => porkbun::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.porkbun is required.")),
+        },
+        DnsProvider::Gandi => match conf.gandi.as_ref() {
+            Some(sub_conf) => gandi::update_set(sub_conf, client, ips).await,
+            None => Err(DwdError::config("The config.gandi is required.")),
+        },
+    }
+}
+
+/// The key of a provider in a target, the same provider with different configs in other targets is not affected.
+fn target_key(conf: &ConfigDnsTarget, provider: DnsProvider) -> String {
+    match provider {
+        DnsProvider::NameCom => format!("{:?}/{:?}", provider, conf.name_com),
+        DnsProvider::Dynv6Com => format!("{:?}/{:?}", provider, conf.dynv6_com),
+        DnsProvider::Dyndns2 => format!("{:?}/{:?}", provider, conf.dyndns2),
+        DnsProvider::Rfc2136 => format!("{:?}/{:?}", provider, conf.rfc2136),
+        DnsProvider::Route53 => format!("{:?}/{:?}", provider, conf.route53),
+        DnsProvider::Alidns => format!("{:?}/{:?}", provider, conf.alidns),
+        DnsProvider::Dnspod => format!("{:?}/{:?}", provider, conf.dnspod),
+        DnsProvider::GoogleCloudDns => format!("{:?}/{:?}", provider, conf.google_cloud_dns),
+        DnsProvider::AzureDns => format!("{:?}/{:?}", provider, conf.azure_dns),
+        DnsProvider::DigitalOcean => format!("{:?}/{:?}", provider, conf.digitalocean),
+        DnsProvider::Hetzner => format!("{:?}/{:?}", provider, conf.hetzner),
+        DnsProvider::Linode => format!("{:?}/{:?}", provider, conf.linode),
+        DnsProvider::Vultr => format!("{:?}/{:?}", provider, conf.vultr),
+        DnsProvider::Porkbun => format!("{:?}/{:?}", provider, conf.porkbun),
+        DnsProvider::Gandi => format!("{:?}/{:?}", provider, conf.gandi),
+    }
+}
+
+fn is_disabled(conf: &ConfigDnsTarget, provider: DnsProvider) -> bool {
+    is_disabled_key(&target_key(conf, provider))
+}
+
+fn is_disabled_key(key: &str) -> bool {
+    DISABLED_TARGETS
+        .lock()
+        .expect("The lock of disabled targets should not be poisoned.")
+        .contains(key)
+}
+
+/// Stop updating the provider if the credentials are rejected, other errors are retried in the next round.
+fn disable_on_auth_error(conf: &ConfigDnsTarget, provider: DnsProvider, err: &DwdError) {
+    if err.kind != ErrorKind::Auth {
+        return;
+    }
+
+    error!(
+        target: "error",
+        event = "provider_disabled",
+        provider = provider.name();
+        "The credentials of DNS provider {:?} are rejected, {}",
+        provider,
+        error_style("it will not be updated until the config is fixed and DWD is restarted.")
+    );
+    control::report_dns_provider_disabled(provider.name());
+    DISABLED_TARGETS
+        .lock()
+        .expect("The lock of disabled targets should not be poisoned.")
+        .insert(target_key(conf, provider));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ips_of_type_should_skip_empty_set() {
+        let ips = vec!["1.1.1.1".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+        assert_eq!(ips_of_type("AAAA", &ips).unwrap(), Some(vec![ips[1]]));
+        assert_eq!(ips_of_type("AAAA", &ips[..1]).unwrap(), None);
+        assert!(ips_of_type("CNAME", &ips).is_err());
+    }
+
+    #[test]
+    fn updated_target_keys_should_exclude_failing_targets() {
+        let conf = ConfigDnsTarget {
+            dns_provider: vec![String::from("hetzner"), String::from("linode")],
+            ..Default::default()
+        };
+        FAILING_TARGETS
+            .lock()
+            .unwrap()
+            .insert(target_key(&conf, DnsProvider::Linode));
+
+        let keys = target_keys(&conf);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(updated_target_keys(&conf), keys[..1]);
+    }
+
+    #[test]
+    fn previous_ip_should_be_the_last_published_one_of_the_family() {
+        let ip = |val: &str| val.parse::<IpAddr>().unwrap();
+        assert_eq!(previous_ip("previous.test", ip("1.1.1.1"), true), None);
+        assert_eq!(previous_ip("previous.test", ip("2001:db8::1"), true), None);
+        assert_eq!(previous_ip("previous.test", ip("2.2.2.2"), false), Some(ip("1.1.1.1")));
+        assert_eq!(previous_ip("previous.test", ip("2.2.2.2"), true), Some(ip("1.1.1.1")));
+        assert_eq!(previous_ip("previous.test", ip("3.3.3.3"), true), Some(ip("2.2.2.2")));
+    }
+
+    #[test]
+    fn plan_record_set_should_find_stale_and_missing() {
+        let ips = vec!["1.1.1.1".parse().unwrap(), "2.2.2.2".parse().unwrap()];
+        let existing = vec![
+            (1, String::from("1.1.1.1")),
+            (2, String::from("3.3.3.3")),
+            (3, String::from("1.1.1.1")),
+        ];
+
+        let (stale, missing) = plan_record_set(existing, &ips);
+        assert_eq!(stale, vec![2, 3]);
+        assert_eq!(missing, vec!["2.2.2.2".parse::<IpAddr>().unwrap()]);
     }
 }
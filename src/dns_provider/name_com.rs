@@ -1,13 +1,17 @@
 use log::trace;
-use reqwest::{Client, Url};
+use reqwest::{Response, Url};
 use serde::{Deserialize, Serialize};
 use std::env;
-use std::error::Error;
+use std::net::IpAddr;
 use std::str;
 
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
 use super::super::util::{error_style, info_style};
+use super::rest::{self, ApiFuture, RecordApi, RestRecord};
 
 const BASE_URL: &str = "https://api.name.com/v4/domains/";
+const MIN_TTL: u32 = 300;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ApiError {
@@ -29,12 +33,16 @@ struct Record {
     record_ttl: u32,
 }
 
-impl Record {
-    fn update(&mut self, record_type: &str, record_answer: &str, record_ttl: u32) {
-        self.record_type = String::from(record_type);
-        self.record_answer = String::from(record_answer);
-        self.record_ttl = record_ttl;
-    }
+#[derive(Debug, Serialize)]
+struct NewRecord {
+    #[serde(rename = "host")]
+    record_host: Option<String>,
+    #[serde(rename = "type")]
+    record_type: String,
+    #[serde(rename = "answer")]
+    record_answer: String,
+    #[serde(rename = "ttl")]
+    record_ttl: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -46,14 +54,25 @@ struct RecordList {
     last_page: Option<i32>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigNameCom {
     pub domain: String,
     pub username: Option<String>,
     pub token: Option<String>,
     pub record_type: String,
     pub record_host: Option<String>,
+    /// The TTL of records, at least 300.
     pub record_ttl: u32,
+    /// The API base, `https://api.name.com/v4/domains/` by default.
+    pub base_url: Option<String>,
+}
+
+struct NameComApi<'a> {
+    conf: &'a ConfigNameCom,
+    client: &'a HttpClient,
+    base_url: Url,
+    username: String,
+    token: String,
 }
 
 /// Update DNS record on name.com
@@ -65,100 +84,172 @@ pub struct ConfigNameCom {
 /// ```rust
 /// name_com::update(domain, record_type, record_host, ip, record_ttl).await?;
 /// ```
-pub async fn update(conf: &ConfigNameCom, ip: &str) -> Result<(), Box<dyn Error>> {
+pub async fn update(conf: &ConfigNameCom, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    update_inner(conf, client, ip)
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+async fn update_inner(conf: &ConfigNameCom, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let expected_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+    if conf.record_type != expected_type {
+        return Err(DwdError::config(format!(
+            "The record_type should be {} for IP {}, but {} is configured.",
+            expected_type, ip, conf.record_type
+        )));
+    }
+
+    rest::update(&NameComApi::new(conf, client)?, ip).await
+}
+
+/// Make the records of the host contain exactly the given IPs, one record per IP.
+///
+/// Only the IPs matching the configured `record_type` are published, so A and AAAA records need separated configs.
+pub async fn update_set(conf: &ConfigNameCom, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let update_set_inner = async { rest::update_set(&NameComApi::new(conf, client)?, &conf.record_type, ips).await };
+    update_set_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+pub fn record_name(conf: &ConfigNameCom) -> String {
+    match conf.record_host.as_ref() {
+        Some(host) => format!("{}.{}", host, conf.domain),
+        None => conf.domain.to_owned(),
+    }
+}
+
+fn credentials(conf: &ConfigNameCom) -> Result<(String, String), DwdError> {
     let username = match conf.username.as_ref() {
         Some(val) => val.to_owned(),
         None => env::var("NAME_COM_USERNAME")
-            .map_err(|_| "Please set username in config file or through env variable USERNAME.")?,
+            .map_err(|_| DwdError::config("Please set username in config file or through env variable USERNAME."))?,
     };
     let token = match conf.token.as_ref() {
         Some(val) => val.to_owned(),
-        None => env::var("NAME_COM_TOKEN").map_err(|_| "Please set env variable TOKEN.")?,
+        None => env::var("NAME_COM_TOKEN").map_err(|_| DwdError::config("Please set env variable TOKEN."))?,
     };
 
     trace!("Username: {:?}", info_style(&username));
     trace!("Token: {:?}", info_style(&token));
 
-    let client = Client::new();
-    let base_url = Url::parse(BASE_URL).map_err(|e| e.to_string())?;
+    Ok((username, token))
+}
 
-    let ret = find_record(&client, &base_url, &username, &token, &conf.domain, &conf.record_host).await?;
-    if ret.is_none() {
-        todo!()
-    }
+/// List all DNS records of the domain, page by page.
+async fn list_records(
+    client: &HttpClient,
+    base_url: &Url,
+    username: &str,
+    token: &str,
+    domain: &str,
+) -> Result<Vec<Record>, DwdError> {
+    let url = base_url.join(&format!("{}/records", domain))?;
+
+    let mut records = Vec::new();
+    let mut page = 1;
+    loop {
+        let response = client
+            .send(
+                client
+                    .get(url.clone())
+                    .query(&[("page", page)])
+                    .basic_auth(username, Some(token)),
+            )
+            .await?;
+
+        trace!("GET {} {}", response.url(), response.status());
+
+        if response.error_for_status_ref().is_err() {
+            return Err(api_error(response).await);
+        }
 
-    let mut record = ret.unwrap();
-    record.update(&conf.record_type, ip, conf.record_ttl);
+        let record_list = response.json::<RecordList>().await?;
+        records.extend(record_list.records);
 
-    update_record(&client, &base_url, &username, &token, record).await?;
+        match record_list.next_page {
+            Some(next_page) if next_page > page => page = next_page,
+            _ => break,
+        }
+    }
 
-    Ok(())
+    Ok(records)
 }
 
-/// Find DNS record by host
-///
-/// Because name.com do not support fetch record by host directly, so we need to find the record first.
-///
-/// > Pagination is not supported here.
-async fn find_record(
-    client: &Client,
+/// Create DNS record
+async fn create_record(
+    client: &HttpClient,
     base_url: &Url,
     username: &str,
     token: &str,
     domain: &str,
-    record_host: &Option<String>,
-) -> Result<Option<Record>, Box<dyn Error>> {
+    record: NewRecord,
+) -> Result<(), DwdError> {
     let url = base_url.join(&format!("{}/records", domain))?;
-    let response = client.get(url).basic_auth(username, Some(token)).send().await?;
+    let response = client
+        .send(client.post(url).json(&record).basic_auth(username, Some(token)))
+        .await?;
 
-    trace!("GET {} {}", response.url(), response.status());
+    trace!("POST {} {}", response.url(), response.status());
 
     if response.error_for_status_ref().is_err() {
-        let api_error = response.json::<ApiError>().await?;
-        return Err(format!("API response error: {}", error_style(api_error.message)).into());
+        return Err(api_error(response).await);
     }
 
-    let record_list = response.json::<RecordList>().await.map_err(|e| e.to_string())?;
-    let record = record_list.records.into_iter().find(|record| {
-        if record_host.is_none() && record.record_host.is_none() {
-            return true;
-        } else if record.record_host.is_some() && record_host == &record.record_host {
-            return true;
-        }
+    trace!("Create record: {:?}", record);
 
-        false
-    });
+    Ok(())
+}
 
-    trace!("Find record from name.com: {:?}", record);
+/// Delete DNS record
+async fn delete_record(
+    client: &HttpClient,
+    base_url: &Url,
+    username: &str,
+    token: &str,
+    domain: &str,
+    id: &str,
+) -> Result<(), DwdError> {
+    let url = base_url.join(&format!("{}/records/{}", domain, id))?;
+    let response = client
+        .send(client.delete(url).basic_auth(username, Some(token)))
+        .await?;
+
+    trace!("DELETE {} {}", response.url(), response.status());
+
+    if response.error_for_status_ref().is_err() {
+        return Err(api_error(response).await);
+    }
+
+    trace!("Delete record: {}", id);
 
-    Ok(record)
+    Ok(())
 }
 
 /// Update DNS record
 async fn update_record(
-    client: &Client,
+    client: &HttpClient,
     base_url: &Url,
     username: &str,
     token: &str,
     record: Record,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), DwdError> {
     let url = base_url.join(&format!("{}/records/{}", record.domain_name, record.id))?;
     let request = client
         .put(url)
         .json(&record)
         .basic_auth(username, Some(token))
         .build()?;
-    let raw_body = str::from_utf8(request.body().unwrap().as_bytes().unwrap())
-        .unwrap()
-        .to_owned();
+    let raw_body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map(|body| String::from_utf8_lossy(body).into_owned())
+        .unwrap_or_default();
     let response = client.execute(request).await?;
 
     trace!("PUT {} {}", response.url(), response.status());
     trace!("Body: {}", raw_body);
 
     if response.error_for_status_ref().is_err() {
-        let api_error = response.json::<ApiError>().await?;
-        return Err(format!("API response error: {}", error_style(api_error.message)).into());
+        return Err(api_error(response).await);
     }
 
     trace!("Update record to: {:?}", record);
@@ -166,19 +257,121 @@ async fn update_record(
     Ok(())
 }
 
+impl<'a> NameComApi<'a> {
+    fn new(conf: &'a ConfigNameCom, client: &'a HttpClient) -> Result<Self, DwdError> {
+        let (username, token) = credentials(conf)?;
+        Ok(NameComApi {
+            conf,
+            client,
+            base_url: Url::parse(conf.base_url.as_deref().unwrap_or(BASE_URL))?,
+            username,
+            token,
+        })
+    }
+}
+
+impl RecordApi for NameComApi<'_> {
+    fn ttl(&self) -> u32 {
+        rest::ttl(Some(self.conf.record_ttl), MIN_TTL, MIN_TTL)
+    }
+
+    fn list<'a>(&'a self, record_type: &'a str) -> ApiFuture<'a, Vec<RestRecord>> {
+        Box::pin(async move {
+            let records = list_records(
+                self.client,
+                &self.base_url,
+                &self.username,
+                &self.token,
+                &self.conf.domain,
+            )
+            .await?
+            .into_iter()
+            .filter(|record| record.record_host == self.conf.record_host && record.record_type == record_type)
+            .map(|record| RestRecord {
+                id: record.id.to_string(),
+                value: record.record_answer,
+                ttl: Some(record.record_ttl),
+            })
+            .collect();
+            Ok(records)
+        })
+    }
+
+    fn create<'a>(&'a self, record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let record = NewRecord {
+                record_host: self.conf.record_host.to_owned(),
+                record_type: record_type.to_owned(),
+                record_answer: value,
+                record_ttl: self.ttl(),
+            };
+            create_record(
+                self.client,
+                &self.base_url,
+                &self.username,
+                &self.token,
+                &self.conf.domain,
+                record,
+            )
+            .await
+        })
+    }
+
+    fn update<'a>(&'a self, record: &'a RestRecord, record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let record = Record {
+                id: record
+                    .id
+                    .parse()
+                    .map_err(|_| DwdError::parse(format!("Invalid record ID: {}", record.id)))?,
+                domain_name: self.conf.domain.to_owned(),
+                record_host: self.conf.record_host.to_owned(),
+                record_type: record_type.to_owned(),
+                record_answer: value,
+                record_ttl: self.ttl(),
+            };
+            update_record(self.client, &self.base_url, &self.username, &self.token, record).await
+        })
+    }
+
+    fn delete<'a>(&'a self, record: &'a RestRecord) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            delete_record(
+                self.client,
+                &self.base_url,
+                &self.username,
+                &self.token,
+                &self.conf.domain,
+                &record.id,
+            )
+            .await
+        })
+    }
+}
+
+/// Classify the error response by its status, with the message from the API.
+async fn api_error(response: Response) -> DwdError {
+    let status = response.status();
+    let message = match response.json::<ApiError>().await {
+        Ok(api_error) => api_error.message,
+        Err(_) => status.to_string(),
+    };
+    DwdError::from_status(status, format!("API response error: {}", error_style(message)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::super::util::is_ip;
     use super::*;
+    use crate::mock_server::{MockResponse, MockServer};
     use dotenv::dotenv;
+    use serde_json::json;
 
-    fn before() -> (Client, Url, String, String) {
+    fn before() -> (HttpClient, Url, String, String) {
         dotenv().ok();
 
-        let client = Client::new();
-        let url = Url::parse(BASE_URL)
-            .map_err(|e| e.to_string())
-            .expect("Expect valid base url.");
+        let client = HttpClient::new(&Default::default(), &Default::default()).unwrap();
+        let url = Url::parse(BASE_URL).expect("Expect valid base url.");
         let username = env::var("NAME_COM_USERNAME").expect("Please config .env for environments.");
         let password = env::var("NAME_COM_TOKEN").expect("Please config .env for environments.");
 
@@ -188,22 +381,29 @@ mod tests {
     #[tokio::test]
     async fn find_record_should_works() {
         let (client, url, username, password) = before();
-        let record = Some(String::from("dwd-unittest"));
-        let ret = find_record(&client, &url, &username, &password, "xieal.me", &record).await;
+        let conf = ConfigNameCom {
+            domain: String::from("xieal.me"),
+            username: Some(username),
+            token: Some(password),
+            record_type: String::from("A"),
+            record_host: Some(String::from("dwd-unittest")),
+            record_ttl: 300,
+            base_url: Some(url.to_string()),
+        };
+        // Because name.com do not support fetch record by host directly, the records of the host are filtered.
+        let ret = NameComApi::new(&conf, &client).unwrap().list("A").await;
         assert!(ret.is_ok(), "{}", ret.unwrap_err().to_string());
 
         println!("ret = {:?}", ret);
-        let record = ret.unwrap().unwrap();
-        assert_eq!(record.domain_name, String::from("xieal.me"));
-        assert_eq!(record.record_host, Some(String::from("dwd-unittest")));
-        assert!(is_ip(&record.record_answer));
+        let record = ret.unwrap().into_iter().next().unwrap();
+        assert!(is_ip(&record.value));
     }
 
     #[tokio::test]
     async fn update_record_should_works() {
         let (client, url, username, password) = before();
         let record = Record {
-            id: 214943323, // WARNING, This is retrieved by find_record.
+            id: 214943323, // WARNING, This is retrieved by find_record_should_works.
             domain_name: String::from("xieal.me"),
             record_host: Some(String::from("dwd-unittest")),
             record_type: String::from("A"),
@@ -214,4 +414,40 @@ mod tests {
         let ret = update_record(&client, &url, &username, &password, record).await;
         assert!(ret.is_ok(), "{}", ret.unwrap_err().to_string());
     }
+
+    #[tokio::test]
+    async fn update_should_raise_ttl_and_update_record() {
+        let server = MockServer::start(|request| match request.method.as_str() {
+            "GET" => MockResponse::json(
+                200,
+                json!({"records": [
+                    {"id": 1, "domainName": "example.com", "host": "home", "type": "A", "answer": "1.1.1.1", "ttl": 300}
+                ]}),
+            ),
+            _ => MockResponse::json(200, json!({})),
+        })
+        .await;
+
+        let conf = ConfigNameCom {
+            domain: String::from("example.com"),
+            username: Some(String::from("u")),
+            token: Some(String::from("t")),
+            record_type: String::from("A"),
+            record_host: Some(String::from("home")),
+            record_ttl: 60,
+            base_url: Some(server.url.clone()),
+        };
+        let client = HttpClient::new(&Default::default(), &Default::default()).unwrap();
+        update(&conf, &client, &"2.2.2.2".parse().unwrap()).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].path, "/example.com/records?page=1");
+        assert_eq!(requests[1].method, "PUT");
+        assert_eq!(requests[1].path, "/example.com/records/1");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&requests[1].body).unwrap(),
+            json!({"id": 1, "domainName": "example.com", "host": "home", "type": "A", "answer": "2.2.2.2", "ttl": 300})
+        );
+    }
 }
@@ -0,0 +1,99 @@
+//! The OAuth 2.0 access tokens of the cloud providers, they are cached until shortly before they expire, so a token
+//! is not requested for every update.
+
+use lazy_static::lazy_static;
+use log::trace;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::super::error::{DwdError, ErrorKind};
+use super::super::http::HttpClient;
+use super::super::util::error_style;
+
+/// A token is requested again when it expires within this time.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+/// The lifetime of a token if the endpoint does not tell.
+const DEFAULT_EXPIRES_IN: u64 = 3600;
+
+lazy_static! {
+    /// The tokens keyed by the endpoint and the client, with the time they expire.
+    static ref TOKENS: Mutex<HashMap<String, (String, Instant)>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenError {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Return the cached token of the key, or request a new one by posting the form to the token endpoint.
+///
+/// The form is only built when a new token is requested, e.g. to skip signing a JWT.
+pub async fn token<F>(client: &HttpClient, key: &str, url: &str, form: F) -> Result<String, DwdError>
+where
+    F: FnOnce() -> Result<Vec<(&'static str, String)>, DwdError>,
+{
+    if let Some((token, expires_at)) = TOKENS
+        .lock()
+        .expect("The lock of tokens should not be poisoned.")
+        .get(key)
+    {
+        if Instant::now() + EXPIRY_MARGIN < *expires_at {
+            return Ok(token.to_owned());
+        }
+    }
+
+    let response = client.send(client.post(url).form(&form()?)).await?;
+    let status = response.status();
+    trace!("POST {} {}", url, status);
+
+    let text = response.text().await?;
+    if !status.is_success() {
+        let err = match serde_json::from_str::<TokenError>(&text) {
+            Ok(err) => err,
+            Err(_) => {
+                return Err(DwdError::from_status(
+                    status,
+                    format!("Request token failed: {} {}", error_style(status), error_style(&text)),
+                ))
+            }
+        };
+        let message = format!(
+            "Request token failed: {} {}: {}",
+            error_style(status),
+            err.error,
+            err.error_description.unwrap_or_default()
+        );
+        return Err(match err.error.as_str() {
+            "invalid_client" | "invalid_grant" | "unauthorized_client" => DwdError::new(ErrorKind::Auth, message),
+            _ => DwdError::from_status(status, message),
+        });
+    }
+
+    let ret = serde_json::from_str::<TokenResponse>(&text)?;
+    let expires_at = Instant::now() + Duration::from_secs(ret.expires_in.unwrap_or(DEFAULT_EXPIRES_IN));
+    TOKENS
+        .lock()
+        .expect("The lock of tokens should not be poisoned.")
+        .insert(key.to_owned(), (ret.access_token.to_owned(), expires_at));
+
+    Ok(ret.access_token)
+}
+
+/// Drop the cached token, e.g. when it is rejected, so a new one is requested next time.
+pub fn invalidate(key: &str) {
+    TOKENS
+        .lock()
+        .expect("The lock of tokens should not be poisoned.")
+        .remove(key);
+}
@@ -0,0 +1,273 @@
+use log::trace;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::net::IpAddr;
+
+use super::super::error::{DwdError, ErrorKind};
+use super::super::http::HttpClient;
+use super::super::util::error_style;
+use super::rest::{self, ApiFuture, RecordApi, RestRecord};
+
+const BASE_URL: &str = "https://api.porkbun.com/api/json/v3/";
+const DEFAULT_TTL: u32 = 600;
+const MIN_TTL: u32 = 600;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigPorkbun {
+    pub domain: String,
+    /// The host record, `@` for the domain itself.
+    pub record_host: Option<String>,
+    /// The TTL of records, at least 600.
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// The API key, or set it through env variable PORKBUN_API_KEY.
+    pub api_key: Option<String>,
+    /// The secret API key, or set it through env variable PORKBUN_SECRET_API_KEY.
+    pub secret_api_key: Option<String>,
+    /// The API base, `https://api.porkbun.com/api/json/v3/` by default.
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordList {
+    #[serde(default)]
+    records: Vec<Record>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Record {
+    id: String,
+    content: String,
+    /// The TTL is a string in the responses.
+    ttl: String,
+}
+
+struct Api<'a> {
+    conf: &'a ConfigPorkbun,
+    client: &'a HttpClient,
+    base_url: Url,
+    api_key: String,
+    secret_api_key: String,
+}
+
+/// Update DNS record on Porkbun
+///
+/// The document of Porkbun API: https://porkbun.com/api/json/v3/documentation
+///
+/// # Example:
+/// ```rust
+/// porkbun::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigPorkbun, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let update_inner = async { rest::update(&Api::new(conf, client)?, ip).await };
+    update_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+/// Make the records of the host contain exactly the given IPs, one record per IP.
+pub async fn update_set(conf: &ConfigPorkbun, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let update_set_inner = async { rest::update_set(&Api::new(conf, client)?, record_type, ips).await };
+    update_set_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+pub fn record_name(conf: &ConfigPorkbun) -> String {
+    rest::record_name(&conf.domain, conf.record_host.as_deref())
+}
+
+impl<'a> Api<'a> {
+    fn new(conf: &'a ConfigPorkbun, client: &'a HttpClient) -> Result<Self, DwdError> {
+        let api_key = match conf.api_key.as_ref() {
+            Some(val) => val.to_owned(),
+            None => {
+                env::var("PORKBUN_API_KEY").map_err(|_| DwdError::config("Please set env variable PORKBUN_API_KEY."))?
+            }
+        };
+        let secret_api_key = match conf.secret_api_key.as_ref() {
+            Some(val) => val.to_owned(),
+            None => env::var("PORKBUN_SECRET_API_KEY")
+                .map_err(|_| DwdError::config("Please set env variable PORKBUN_SECRET_API_KEY."))?,
+        };
+        Ok(Api {
+            conf,
+            client,
+            base_url: Url::parse(conf.base_url.as_deref().unwrap_or(BASE_URL))?,
+            api_key,
+            secret_api_key,
+        })
+    }
+
+    /// The subdomain of the record, empty for the domain itself.
+    fn host(&self) -> &str {
+        match rest::relative_host(self.conf.record_host.as_deref()) {
+            "@" => "",
+            host => host,
+        }
+    }
+
+    /// Call an API, every API is a POST with the keys in the JSON body, and tells errors by its `status` field.
+    async fn call(&self, path: &str, mut body: Value) -> Result<Value, DwdError> {
+        body["apikey"] = json!(self.api_key);
+        body["secretapikey"] = json!(self.secret_api_key);
+        let response = self
+            .client
+            .send(self.client.post(self.base_url.join(path)?).json(&body))
+            .await?;
+        let status = response.status();
+        trace!("{} {}", path, status);
+
+        let text = response.text().await.unwrap_or_default();
+        let val = serde_json::from_str::<Value>(&text).unwrap_or_default();
+        if status.is_success() && val["status"] == "SUCCESS" {
+            return Ok(val);
+        }
+
+        let detail = val["message"].as_str().unwrap_or(&text).trim().to_owned();
+        let message = format!("API response error: {} {}", error_style(status), error_style(&detail));
+        // The rejected keys are reported as a bad request.
+        if detail.starts_with("Invalid API key") || detail.contains("not opted in to API access") {
+            return Err(DwdError::new(ErrorKind::Auth, message));
+        }
+        if status.is_success() {
+            Err(DwdError::new(ErrorKind::HttpStatus, message))
+        } else {
+            Err(DwdError::from_status(status, message))
+        }
+    }
+}
+
+impl RecordApi for Api<'_> {
+    fn ttl(&self) -> u32 {
+        rest::ttl(self.conf.record_ttl, DEFAULT_TTL, MIN_TTL)
+    }
+
+    fn list<'a>(&'a self, record_type: &'a str) -> ApiFuture<'a, Vec<RestRecord>> {
+        Box::pin(async move {
+            // All records of the name and the type are returned at once.
+            let mut path = format!("dns/retrieveByNameType/{}/{}", self.conf.domain, record_type);
+            if !self.host().is_empty() {
+                path = format!("{}/{}", path, self.host());
+            }
+            let ret = serde_json::from_value::<RecordList>(self.call(&path, json!({})).await?)?;
+            Ok(ret
+                .records
+                .into_iter()
+                .map(|record| RestRecord {
+                    id: record.id,
+                    value: record.content,
+                    ttl: record.ttl.parse().ok(),
+                })
+                .collect())
+        })
+    }
+
+    fn create<'a>(&'a self, record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let body = json!({
+                "name": self.host(),
+                "type": record_type,
+                "content": value,
+                "ttl": self.ttl().to_string(),
+            });
+            self.call(&format!("dns/create/{}", self.conf.domain), body).await?;
+            Ok(())
+        })
+    }
+
+    fn update<'a>(&'a self, record: &'a RestRecord, record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let body = json!({
+                "name": self.host(),
+                "type": record_type,
+                "content": value,
+                "ttl": self.ttl().to_string(),
+            });
+            self.call(&format!("dns/edit/{}/{}", self.conf.domain, record.id), body)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, record: &'a RestRecord) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            self.call(&format!("dns/delete/{}/{}", self.conf.domain, record.id), json!({}))
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn conf(server: &MockServer) -> ConfigPorkbun {
+        ConfigPorkbun {
+            domain: String::from("example.com"),
+            record_host: Some(String::from("home")),
+            record_ttl: Some(60),
+            record_type: None,
+            api_key: Some(String::from("pk")),
+            secret_api_key: Some(String::from("sk")),
+            base_url: Some(server.url.clone()),
+        }
+    }
+
+    #[tokio::test]
+    async fn update_should_edit_record() {
+        let server = MockServer::start(|request| {
+            if request.path.starts_with("/dns/retrieveByNameType/") {
+                MockResponse::json(
+                    200,
+                    json!({"status": "SUCCESS", "records": [{
+                        "id": "106926659", "name": "home.example.com", "type": "A",
+                        "content": "1.1.1.1", "ttl": "600", "prio": "0", "notes": ""
+                    }]}),
+                )
+            } else {
+                MockResponse::json(200, json!({"status": "SUCCESS"}))
+            }
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update(&conf(&server), &client, &"2.2.2.2".parse().unwrap())
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].path, "/dns/retrieveByNameType/example.com/A/home");
+        assert_eq!(
+            serde_json::from_str::<Value>(&requests[0].body).unwrap(),
+            json!({"apikey": "pk", "secretapikey": "sk"})
+        );
+        assert_eq!(requests[1].path, "/dns/edit/example.com/106926659");
+        assert_eq!(
+            serde_json::from_str::<Value>(&requests[1].body).unwrap(),
+            json!({
+                "apikey": "pk", "secretapikey": "sk",
+                "name": "home", "type": "A", "content": "2.2.2.2", "ttl": "600"
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn update_should_classify_invalid_keys() {
+        let server = MockServer::start(|_| {
+            MockResponse::json(400, json!({"status": "ERROR", "message": "Invalid API key. (002)"}))
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        let err = update(&conf(&server), &client, &"1.1.1.1".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Auth);
+        assert_eq!(err.record.as_deref(), Some("home.example.com"));
+    }
+}
@@ -0,0 +1,148 @@
+//! The find-or-create-then-update flow of the providers whose REST APIs manage records one by one, so a provider only
+//! implements how to list, create, update and delete the records of a host. Take a look at digitalocean.rs for an
+//! example.
+
+use log::{debug, trace};
+use reqwest::{RequestBuilder, Response};
+use serde_json::Value;
+use std::{future::Future, net::IpAddr, pin::Pin};
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::super::util::error_style;
+use super::{ips_of_type, plan_record_set};
+
+pub type ApiFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, DwdError>> + Send + 'a>>;
+
+/// A record of the host, with the fields which the flow needs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestRecord {
+    pub id: String,
+    pub value: String,
+    /// The TTL of the record, `None` if the API does not tell.
+    pub ttl: Option<u32>,
+}
+
+/// The record API of a provider, it is bound to the configured host.
+pub trait RecordApi: Sync {
+    /// The TTL of the records which are created or updated.
+    fn ttl(&self) -> u32;
+
+    /// List the records of the host and the type, all pages are read.
+    fn list<'a>(&'a self, record_type: &'a str) -> ApiFuture<'a, Vec<RestRecord>>;
+
+    fn create<'a>(&'a self, record_type: &'a str, value: String) -> ApiFuture<'a, ()>;
+
+    fn update<'a>(&'a self, record: &'a RestRecord, record_type: &'a str, value: String) -> ApiFuture<'a, ()>;
+
+    fn delete<'a>(&'a self, record: &'a RestRecord) -> ApiFuture<'a, ()>;
+}
+
+/// Update the record of the host to the IP, it is created if not found, and left alone if it is up to date.
+pub async fn update<A: RecordApi>(api: &A, ip: &IpAddr) -> Result<(), DwdError> {
+    let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+    let records = api.list(record_type).await?;
+    trace!("Find records: {:?}", records);
+
+    match records.first() {
+        Some(record)
+            if record.value.parse::<IpAddr>().ok() == Some(*ip) && record.ttl.is_none_or(|ttl| ttl == api.ttl()) =>
+        {
+            trace!("Record {} is up to date", record.id)
+        }
+        Some(record) => {
+            api.update(record, record_type, ip.to_string()).await?;
+            trace!("Update record {} to: {}", record.id, ip);
+        }
+        None => {
+            api.create(record_type, ip.to_string()).await?;
+            trace!("Create record with: {}", ip);
+        }
+    }
+
+    Ok(())
+}
+
+/// Make the records of the host contain exactly the given IPs of the type, one record per IP.
+pub async fn update_set<A: RecordApi>(api: &A, record_type: &str, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let ips = match ips_of_type(record_type, ips)? {
+        Some(val) => val,
+        None => return Ok(()),
+    };
+
+    let records = api
+        .list(record_type)
+        .await?
+        .into_iter()
+        .map(|record| {
+            let value = record.value.to_owned();
+            (record, value)
+        })
+        .collect();
+    let (stale, missing) = plan_record_set(records, &ips);
+
+    // Create new records first, so the host will always be resolvable.
+    for ip in missing {
+        api.create(record_type, ip.to_string()).await?;
+    }
+    for record in stale {
+        api.delete(&record).await?;
+    }
+
+    Ok(())
+}
+
+/// Send the request, an error response is classified by its status, with the message picked from the JSON body.
+pub async fn send(
+    client: &HttpClient,
+    builder: RequestBuilder,
+    message: fn(&Value) -> Option<&str>,
+) -> Result<Response, DwdError> {
+    let response = client.send(builder).await?;
+    let status = response.status();
+    trace!("{} {}", response.url(), status);
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let text = response.text().await.unwrap_or_default();
+    let detail = serde_json::from_str::<Value>(&text)
+        .ok()
+        .and_then(|val| message(&val).map(str::to_owned))
+        .unwrap_or(text);
+    Err(DwdError::from_status(
+        status,
+        format!(
+            "API response error: {} {}",
+            error_style(status),
+            error_style(detail.trim())
+        ),
+    ))
+}
+
+/// The TTL in the config, raised to the minimum of the provider.
+pub fn ttl(ttl: Option<u32>, default: u32, min: u32) -> u32 {
+    let val = ttl.unwrap_or(default);
+    if val < min {
+        debug!(
+            "The TTL {} is less than the minimum {} of the provider, use {}.",
+            val, min, min
+        );
+    }
+    val.max(min)
+}
+
+/// The host relative to the domain, `@` for the domain itself.
+pub fn relative_host(host: Option<&str>) -> &str {
+    match host {
+        Some(host) if !host.is_empty() => host,
+        _ => "@",
+    }
+}
+
+pub fn record_name(domain: &str, host: Option<&str>) -> String {
+    match relative_host(host) {
+        "@" => domain.to_owned(),
+        host => format!("{}.{}", host, domain),
+    }
+}
@@ -0,0 +1,787 @@
+//! Update the records on an authoritative server with RFC 2136 dynamic updates, e.g. BIND or Knot.
+//!
+//! The UPDATE message deletes the RRset and adds the new records in one transaction, and is signed with TSIG
+//! (RFC 8945) if a key is configured. It is sent over UDP and retried over TCP if the response is truncated.
+
+use chrono::Utc;
+use log::trace;
+use openssl::{hash::MessageDigest, memcmp};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{lookup_host, TcpStream, UdpSocket},
+    time,
+};
+
+use super::super::error::{DwdError, ErrorKind};
+use super::{ips_of_type, signature};
+
+const DEFAULT_TTL: u32 = 300;
+const DEFAULT_TIMEOUT: u64 = 10;
+/// How many seconds the clocks of DWD and the server may differ, as recommended by RFC 8945.
+const FUDGE: u16 = 300;
+const MAX_UDP_SIZE: usize = 4096;
+
+const OPCODE_UPDATE: u16 = 5 << 11;
+const FLAG_QR: u16 = 1 << 15;
+const FLAG_TC: u16 = 1 << 9;
+const TYPE_A: u16 = 1;
+const TYPE_SOA: u16 = 6;
+const TYPE_AAAA: u16 = 28;
+const TYPE_TSIG: u16 = 250;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rfc2136Transport {
+    /// UDP, and TCP if the response is truncated.
+    Udp,
+    Tcp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TsigAlgorithm {
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
+    #[serde(rename = "hmac-sha512")]
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    fn digest(&self) -> MessageDigest {
+        match self {
+            TsigAlgorithm::HmacSha256 => MessageDigest::sha256(),
+            TsigAlgorithm::HmacSha512 => MessageDigest::sha512(),
+        }
+    }
+}
+
+/// The prerequisites which the server checks before applying the update, see RFC 2136 section 2.4.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Prerequisite {
+    /// The name has at least one record of any type, so no new name is created.
+    NameInUse,
+    /// The name has no record of any type.
+    NameNotInUse,
+    /// The name has at least one record of the updated type.
+    RrsetExists,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigRfc2136 {
+    /// The primary server, e.g. `ns1.example.com` or `192.0.2.1:53`.
+    pub server: String,
+    pub zone: String,
+    /// The name of the record relative to the zone, the zone itself by default.
+    pub record_host: Option<String>,
+    /// 300 by default.
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// `udp` by default.
+    pub transport: Option<Rfc2136Transport>,
+    /// Seconds to wait for the response, 10 by default.
+    pub timeout: Option<u64>,
+    pub prerequisite: Option<Prerequisite>,
+
+    /// The name of the TSIG key, the update is not signed if it is omitted.
+    pub tsig_key_name: Option<String>,
+    /// `hmac-sha256` by default.
+    pub tsig_algorithm: Option<TsigAlgorithm>,
+    /// The secret in base64, or set it through env variable RFC2136_TSIG_SECRET.
+    pub tsig_secret: Option<String>,
+}
+
+struct TsigKey {
+    name: String,
+    algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+/// Update the record with the IP through RFC 2136 dynamic update
+///
+/// # Example:
+/// ```rust
+/// rfc2136::update(conf, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigRfc2136, ip: &IpAddr) -> Result<(), DwdError> {
+    let record_type = if ip.is_ipv4() { TYPE_A } else { TYPE_AAAA };
+    send_update(conf, record_type, &[*ip])
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+/// Replace the RRset with the given IPs of `record_type`, one record per IP.
+pub async fn update_set(conf: &ConfigRfc2136, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let update_set_inner = async {
+        let ips = match ips_of_type(record_type, ips)? {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+        send_update(conf, if record_type == "A" { TYPE_A } else { TYPE_AAAA }, &ips).await
+    };
+    update_set_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+pub fn record_name(conf: &ConfigRfc2136) -> String {
+    let zone = conf.zone.trim_end_matches('.');
+    match conf.record_host.as_deref() {
+        Some(host) if !host.is_empty() && host != "@" => format!("{}.{}", host, zone),
+        _ => zone.to_owned(),
+    }
+}
+
+async fn send_update(conf: &ConfigRfc2136, record_type: u16, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let key = tsig_key(conf)?;
+    let name = record_name(conf);
+    let ttl = conf.record_ttl.unwrap_or(DEFAULT_TTL);
+
+    let mut id = [0; 2];
+    openssl::rand::rand_bytes(&mut id).map_err(|e| DwdError::config(e.to_string()))?;
+    let id = u16::from_be_bytes(id);
+
+    let mut message = Message::new(id);
+    // The zone section.
+    message.name(&conf.zone)?;
+    message.u16(TYPE_SOA).u16(CLASS_IN);
+    message.zones += 1;
+
+    if let Some(prerequisite) = conf.prerequisite {
+        let (rr_type, class) = match prerequisite {
+            Prerequisite::NameInUse => (TYPE_ANY, CLASS_ANY),
+            Prerequisite::NameNotInUse => (TYPE_ANY, CLASS_NONE),
+            Prerequisite::RrsetExists => (record_type, CLASS_ANY),
+        };
+        message.name(&name)?;
+        message.u16(rr_type).u16(class).u32(0).u16(0);
+        message.prerequisites += 1;
+    }
+
+    // Delete the RRset, then add the records, they are applied atomically.
+    message.name(&name)?;
+    message.u16(record_type).u16(CLASS_ANY).u32(0).u16(0);
+    message.updates += 1;
+    for ip in ips {
+        let data = match ip {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        message.name(&name)?;
+        message.u16(record_type).u16(CLASS_IN).u32(ttl).u16(data.len() as u16);
+        message.buf.extend_from_slice(&data);
+        message.updates += 1;
+    }
+
+    let mut request = message.finish();
+    let mac = match key.as_ref() {
+        Some(key) => Some(sign(&mut request, key, Utc::now().timestamp() as u64)?),
+        None => None,
+    };
+
+    let server = resolve(&conf.server).await?;
+    let timeout = Duration::from_secs(conf.timeout.unwrap_or(DEFAULT_TIMEOUT));
+    let response = match conf.transport.unwrap_or(Rfc2136Transport::Udp) {
+        Rfc2136Transport::Udp => {
+            let response = exchange_udp(server, &request, timeout).await?;
+            if header(&response, 2) & FLAG_TC != 0 {
+                trace!("The response is truncated, retry over TCP.");
+                exchange_tcp(server, &request, timeout).await?
+            } else {
+                response
+            }
+        }
+        Rfc2136Transport::Tcp => exchange_tcp(server, &request, timeout).await?,
+    };
+    trace!("Received {} bytes from {}", response.len(), server);
+
+    check_response(&response, id, key.as_ref().zip(mac.as_deref()))?;
+    trace!("Update {} to: {:?}", name, ips);
+
+    Ok(())
+}
+
+fn tsig_key(conf: &ConfigRfc2136) -> Result<Option<TsigKey>, DwdError> {
+    let name = match conf.tsig_key_name.as_ref() {
+        Some(val) => val,
+        None => return Ok(None),
+    };
+    let secret = match conf.tsig_secret.as_ref() {
+        Some(val) => val.to_owned(),
+        None => env::var("RFC2136_TSIG_SECRET")
+            .map_err(|_| DwdError::config("Please set env variable RFC2136_TSIG_SECRET."))?,
+    };
+    let secret = openssl::base64::decode_block(secret.trim())
+        .map_err(|_| DwdError::config("The TSIG secret should be in base64."))?;
+
+    Ok(Some(TsigKey {
+        name: name.to_owned(),
+        algorithm: conf.tsig_algorithm.unwrap_or(TsigAlgorithm::HmacSha256),
+        secret,
+    }))
+}
+
+/// Resolve the server, the port is 53 if it is omitted. IPv6 addresses with a port should be bracketed.
+async fn resolve(server: &str) -> Result<SocketAddr, DwdError> {
+    if let Ok(ip) = server.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    let addr = if server.contains(':') {
+        server.to_owned()
+    } else {
+        format!("{}:53", server)
+    };
+    let mut addrs = lookup_host(addr)
+        .await
+        .map_err(|e| DwdError::new(ErrorKind::Transport, format!("Resolve {} failed: {}", server, e)))?;
+    addrs
+        .next()
+        .ok_or_else(|| DwdError::new(ErrorKind::Transport, format!("Resolve {} failed.", server)))
+}
+
+async fn exchange_udp(server: SocketAddr, request: &[u8], timeout: Duration) -> Result<Vec<u8>, DwdError> {
+    let local: SocketAddr = if server.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(local).await.map_err(transport_error)?;
+    socket.connect(server).await.map_err(transport_error)?;
+    socket.send(request).await.map_err(transport_error)?;
+
+    let mut buf = vec![0; MAX_UDP_SIZE];
+    let len = time::timeout(timeout, socket.recv(&mut buf))
+        .await
+        .map_err(|_| timeout_error(server))?
+        .map_err(transport_error)?;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+async fn exchange_tcp(server: SocketAddr, request: &[u8], timeout: Duration) -> Result<Vec<u8>, DwdError> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(server).await?;
+        let mut framed = (request.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(request);
+        stream.write_all(&framed).await?;
+
+        let len = stream.read_u16().await?;
+        let mut buf = vec![0; len as usize];
+        stream.read_exact(&mut buf).await?;
+        Ok(buf)
+    };
+    time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| timeout_error(server))?
+        .map_err(transport_error)
+}
+
+fn transport_error(e: std::io::Error) -> DwdError {
+    DwdError::new(ErrorKind::Transport, format!("DNS transport error: {}", e))
+}
+
+fn timeout_error(server: SocketAddr) -> DwdError {
+    DwdError::new(ErrorKind::Transport, format!("No response from {} in time.", server))
+}
+
+/// Read a 16-bit field of the header, the offset is in bytes.
+fn header(message: &[u8], offset: usize) -> u16 {
+    match message.get(offset..offset + 2) {
+        Some(val) => u16::from_be_bytes([val[0], val[1]]),
+        None => 0,
+    }
+}
+
+/// Check the RCODE and the TSIG of the response.
+fn check_response(response: &[u8], id: u16, key: Option<(&TsigKey, &[u8])>) -> Result<(), DwdError> {
+    if response.len() < 12 || header(response, 0) != id || header(response, 2) & FLAG_QR == 0 {
+        return Err(DwdError::parse("The response does not match the update."));
+    }
+
+    let tsig = find_tsig(response)?;
+    if let Some(tsig) = tsig.as_ref() {
+        match tsig.error {
+            0 => (),
+            16 => {
+                return Err(DwdError::new(
+                    ErrorKind::Auth,
+                    "The TSIG signature is rejected (BADSIG).",
+                ))
+            }
+            17 => {
+                return Err(DwdError::new(
+                    ErrorKind::Auth,
+                    "The TSIG key is unknown to the server (BADKEY).",
+                ))
+            }
+            18 => return Err(DwdError::new(ErrorKind::Auth, "The clocks differ too much (BADTIME).")),
+            val => return Err(DwdError::new(ErrorKind::Auth, format!("TSIG error {}.", val))),
+        }
+    }
+
+    let rcode = header(response, 2) & 0x000f;
+    let err = match rcode {
+        0 => None,
+        1 => Some(DwdError::parse("The server could not understand the update (FORMERR).")),
+        2 => Some(DwdError::new(ErrorKind::Transport, "The server failed (SERVFAIL).")),
+        3 => Some(DwdError::new(
+            ErrorKind::NotFound,
+            "Prerequisite failed, the name does not exist (NXDOMAIN).",
+        )),
+        4 => Some(DwdError::config(
+            "The server does not support dynamic updates (NOTIMP).",
+        )),
+        5 => Some(DwdError::new(
+            ErrorKind::Auth,
+            "The update is refused by the server (REFUSED).",
+        )),
+        6 => Some(DwdError::config("Prerequisite failed, the name exists (YXDOMAIN).")),
+        7 => Some(DwdError::config("Prerequisite failed, the RRset exists (YXRRSET).")),
+        8 => Some(DwdError::new(
+            ErrorKind::NotFound,
+            "Prerequisite failed, the RRset does not exist (NXRRSET).",
+        )),
+        9 => Some(DwdError::new(
+            ErrorKind::Auth,
+            "The server is not authoritative or the key is not accepted (NOTAUTH).",
+        )),
+        10 => Some(DwdError::config("The name is not in the zone (NOTZONE).")),
+        val => Some(DwdError::parse(format!("Unknown RCODE {}.", val))),
+    };
+    if let Some(err) = err {
+        return Err(err);
+    }
+
+    // A signed update should get a signed response, otherwise it may be forged.
+    if let Some((key, request_mac)) = key {
+        let tsig = tsig.ok_or_else(|| DwdError::new(ErrorKind::Auth, "The response is not signed."))?;
+        verify(response, &tsig, key, request_mac)?;
+    }
+    Ok(())
+}
+
+struct Message {
+    buf: Vec<u8>,
+    zones: u16,
+    prerequisites: u16,
+    updates: u16,
+}
+
+impl Message {
+    fn new(id: u16) -> Self {
+        let mut buf = Vec::with_capacity(512);
+        buf.extend_from_slice(&id.to_be_bytes());
+        buf.extend_from_slice(&OPCODE_UPDATE.to_be_bytes());
+        // The counts are filled in by `finish`.
+        buf.extend_from_slice(&[0; 8]);
+        Message {
+            buf,
+            zones: 0,
+            prerequisites: 0,
+            updates: 0,
+        }
+    }
+
+    fn u16(&mut self, val: u16) -> &mut Self {
+        self.buf.extend_from_slice(&val.to_be_bytes());
+        self
+    }
+
+    fn u32(&mut self, val: u32) -> &mut Self {
+        self.buf.extend_from_slice(&val.to_be_bytes());
+        self
+    }
+
+    fn name(&mut self, name: &str) -> Result<(), DwdError> {
+        self.buf.extend_from_slice(&encode_name(name)?);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.buf[4..6].copy_from_slice(&self.zones.to_be_bytes());
+        self.buf[6..8].copy_from_slice(&self.prerequisites.to_be_bytes());
+        self.buf[8..10].copy_from_slice(&self.updates.to_be_bytes());
+        self.buf
+    }
+}
+
+/// Encode the name in the wire format without compression, in lowercase as the canonical form of TSIG.
+fn encode_name(name: &str) -> Result<Vec<u8>, DwdError> {
+    let mut buf = Vec::with_capacity(name.len() + 2);
+    for label in name.trim_end_matches('.').split('.').filter(|label| !label.is_empty()) {
+        if label.len() > 63 {
+            return Err(DwdError::config(format!(
+                "The label {:?} is longer than 63 bytes.",
+                label
+            )));
+        }
+        buf.push(label.len() as u8);
+        buf.extend(label.bytes().map(|c| c.to_ascii_lowercase()));
+    }
+    buf.push(0);
+    if buf.len() > 255 {
+        return Err(DwdError::config(format!(
+            "The name {:?} is longer than 255 bytes.",
+            name
+        )));
+    }
+    Ok(buf)
+}
+
+/// The TSIG variables which are signed after the message, see RFC 8945 section 4.3.3.
+fn tsig_variables(key: &TsigKey, time_signed: u64, error: u16) -> Result<Vec<u8>, DwdError> {
+    let mut buf = encode_name(&key.name)?;
+    buf.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    buf.extend_from_slice(&0u32.to_be_bytes());
+    buf.extend_from_slice(&encode_name(key.algorithm.name())?);
+    buf.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+    buf.extend_from_slice(&FUDGE.to_be_bytes());
+    buf.extend_from_slice(&error.to_be_bytes());
+    // No other data.
+    buf.extend_from_slice(&0u16.to_be_bytes());
+    Ok(buf)
+}
+
+/// Append the TSIG record to the message, return the MAC which the response is signed with.
+fn sign(message: &mut Vec<u8>, key: &TsigKey, time_signed: u64) -> Result<Vec<u8>, DwdError> {
+    let mut data = message.clone();
+    data.extend_from_slice(&tsig_variables(key, time_signed, 0)?);
+    let mac = signature::hmac(key.algorithm.digest(), &key.secret, &data)?;
+
+    let mut rdata = encode_name(key.algorithm.name())?;
+    rdata.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+    rdata.extend_from_slice(&FUDGE.to_be_bytes());
+    rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+    rdata.extend_from_slice(&mac);
+    rdata.extend_from_slice(&message[0..2]);
+    // No error and no other data.
+    rdata.extend_from_slice(&[0; 4]);
+
+    message.extend_from_slice(&encode_name(&key.name)?);
+    message.extend_from_slice(&TYPE_TSIG.to_be_bytes());
+    message.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    message.extend_from_slice(&0u32.to_be_bytes());
+    message.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    message.extend_from_slice(&rdata);
+    let additional = header(message, 10) + 1;
+    message[10..12].copy_from_slice(&additional.to_be_bytes());
+
+    Ok(mac)
+}
+
+struct Tsig {
+    /// Where the TSIG record starts in the message.
+    offset: usize,
+    time_signed: u64,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+}
+
+/// Skip a possibly compressed name, return the offset after it.
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(offset)? as usize;
+        match len {
+            0 => return Some(offset + 1),
+            _ if len & 0xc0 == 0xc0 => return Some(offset + 2),
+            _ => offset += len + 1,
+        }
+    }
+}
+
+/// Find the TSIG record, it must be the last record of the additional section.
+fn find_tsig(message: &[u8]) -> Result<Option<Tsig>, DwdError> {
+    let invalid = || DwdError::parse("The response is malformed.");
+    let additional = header(message, 10);
+    if additional == 0 {
+        return Ok(None);
+    }
+
+    let mut offset = 12;
+    for _ in 0..header(message, 4) {
+        offset = skip_name(message, offset).ok_or_else(invalid)? + 4;
+    }
+    let records = header(message, 6) as usize + header(message, 8) as usize + additional as usize;
+    let mut last = None;
+    for _ in 0..records {
+        let start = offset;
+        offset = skip_name(message, offset).ok_or_else(invalid)?;
+        let rr_type = header(message, offset);
+        let rdlen = header(message, offset + 8) as usize;
+        last = Some((start, rr_type, offset + 10));
+        offset += 10 + rdlen;
+        if offset > message.len() {
+            return Err(invalid());
+        }
+    }
+
+    let (start, rdata) = match last {
+        Some((start, TYPE_TSIG, rdata)) => (start, rdata),
+        _ => return Ok(None),
+    };
+    let offset = skip_name(message, rdata).ok_or_else(invalid)?;
+    let time = message.get(offset..offset + 6).ok_or_else(invalid)?;
+    let time_signed = time.iter().fold(0u64, |acc, val| (acc << 8) | *val as u64);
+    let mac_len = header(message, offset + 8) as usize;
+    let mac = message
+        .get(offset + 10..offset + 10 + mac_len)
+        .ok_or_else(invalid)?
+        .to_vec();
+    let offset = offset + 10 + mac_len;
+    if offset + 4 > message.len() {
+        return Err(invalid());
+    }
+
+    Ok(Some(Tsig {
+        offset: start,
+        time_signed,
+        mac,
+        original_id: header(message, offset),
+        error: header(message, offset + 2),
+    }))
+}
+
+/// Verify the signature of the response, it covers the MAC of the request, see RFC 8945 section 5.3.
+fn verify(response: &[u8], tsig: &Tsig, key: &TsigKey, request_mac: &[u8]) -> Result<(), DwdError> {
+    let mut data = (request_mac.len() as u16).to_be_bytes().to_vec();
+    data.extend_from_slice(request_mac);
+    let mut message = response[..tsig.offset].to_vec();
+    message[0..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+    let additional = header(&message, 10) - 1;
+    message[10..12].copy_from_slice(&additional.to_be_bytes());
+    data.extend_from_slice(&message);
+    data.extend_from_slice(&tsig_variables(key, tsig.time_signed, tsig.error)?);
+
+    let expected = signature::hmac(key.algorithm.digest(), &key.secret, &data)?;
+    if expected.len() != tsig.mac.len() || !memcmp::eq(&expected, &tsig.mac) {
+        return Err(DwdError::new(
+            ErrorKind::Auth,
+            "The signature of the response is invalid.",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    const SECRET: &str = "c2VjcmV0LWtleS1mb3ItdGVzdGluZy1vbmx5";
+
+    fn conf(server: SocketAddr, transport: Option<Rfc2136Transport>) -> ConfigRfc2136 {
+        ConfigRfc2136 {
+            server: server.to_string(),
+            zone: String::from("example.com"),
+            record_host: Some(String::from("home")),
+            record_ttl: Some(60),
+            record_type: None,
+            transport,
+            timeout: Some(2),
+            prerequisite: Some(Prerequisite::NameInUse),
+            tsig_key_name: Some(String::from("dwd-key")),
+            tsig_algorithm: Some(TsigAlgorithm::HmacSha512),
+            tsig_secret: Some(String::from(SECRET)),
+        }
+    }
+
+    fn key() -> TsigKey {
+        TsigKey {
+            name: String::from("dwd-key"),
+            algorithm: TsigAlgorithm::HmacSha512,
+            secret: openssl::base64::decode_block(SECRET).unwrap(),
+        }
+    }
+
+    /// Answer the update like a primary server, verify the request and sign the response with the key.
+    fn answer(request: &[u8], rcode: u16, truncated: bool) -> Vec<u8> {
+        let tsig = find_tsig(request).unwrap().unwrap();
+        let mut unsigned = request[..tsig.offset].to_vec();
+        unsigned[10..12].copy_from_slice(&0u16.to_be_bytes());
+        let mut data = unsigned.clone();
+        data.extend_from_slice(&tsig_variables(&key(), tsig.time_signed, 0).unwrap());
+        assert_eq!(
+            signature::hmac(MessageDigest::sha512(), &key().secret, &data).unwrap(),
+            tsig.mac,
+            "The request is not signed correctly."
+        );
+
+        // The response echoes the header and the zone section.
+        let zone_end = skip_name(&unsigned, 12).unwrap() + 4;
+        let mut response = unsigned[..zone_end].to_vec();
+        let flags = FLAG_QR | OPCODE_UPDATE | rcode | if truncated { FLAG_TC } else { 0 };
+        response[2..4].copy_from_slice(&flags.to_be_bytes());
+        response[6..10].copy_from_slice(&[0; 4]);
+
+        // Sign the response with the MAC of the request.
+        let mut data = (tsig.mac.len() as u16).to_be_bytes().to_vec();
+        data.extend_from_slice(&tsig.mac);
+        data.extend_from_slice(&response);
+        data.extend_from_slice(&tsig_variables(&key(), tsig.time_signed, 0).unwrap());
+        let mac = signature::hmac(MessageDigest::sha512(), &key().secret, &data).unwrap();
+        let mut rdata = encode_name("hmac-sha512").unwrap();
+        rdata.extend_from_slice(&tsig.time_signed.to_be_bytes()[2..]);
+        rdata.extend_from_slice(&FUDGE.to_be_bytes());
+        rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&mac);
+        rdata.extend_from_slice(&request[0..2]);
+        rdata.extend_from_slice(&[0; 4]);
+        response.extend_from_slice(&encode_name("dwd-key").unwrap());
+        response.extend_from_slice(&[0, 250, 0, 255, 0, 0, 0, 0]);
+        response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        response.extend_from_slice(&rdata);
+        response[10..12].copy_from_slice(&1u16.to_be_bytes());
+        response
+    }
+
+    /// A stand-in of the primary server on UDP and TCP of the same port, the UDP responses are truncated if
+    /// `truncate_udp` is set. It returns the address and the received requests with the transport.
+    async fn start_server(rcode: u16, truncate_udp: bool) -> (SocketAddr, Arc<Mutex<Vec<(String, Vec<u8>)>>>) {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).await.unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = received.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0; MAX_UDP_SIZE];
+            while let Ok((len, peer)) = udp.recv_from(&mut buf).await {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((String::from("udp"), buf[..len].to_vec()));
+                let response = answer(&buf[..len], rcode, truncate_udp);
+                udp.send_to(&response, peer).await.unwrap();
+            }
+        });
+        let recorded = received.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = tcp.accept().await {
+                let len = stream.read_u16().await.unwrap();
+                let mut request = vec![0; len as usize];
+                stream.read_exact(&mut request).await.unwrap();
+                recorded.lock().unwrap().push((String::from("tcp"), request.clone()));
+                let response = answer(&request, rcode, false);
+                stream.write_u16(response.len() as u16).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+
+        (addr, received)
+    }
+
+    #[tokio::test]
+    async fn update_should_replace_rrset_with_tsig() {
+        let (addr, received) = start_server(0, false).await;
+
+        update(&conf(addr, None), &"192.0.2.7".parse().unwrap()).await.unwrap();
+
+        let received = received.lock().unwrap();
+        let (transport, request) = &received[0];
+        assert_eq!(transport, "udp");
+        // One zone, one prerequisite, two updates and the TSIG record.
+        assert_eq!(&request[4..12], &[0, 1, 0, 1, 0, 2, 0, 1]);
+        let name = encode_name("home.example.com").unwrap();
+        let mut delete = name.clone();
+        delete.extend_from_slice(&[0, 1, 0, 255, 0, 0, 0, 0, 0, 0]);
+        let mut add = name.clone();
+        add.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 7]);
+        let mut prerequisite = name;
+        prerequisite.extend_from_slice(&[0, 255, 0, 255, 0, 0, 0, 0, 0, 0]);
+        let expected = [prerequisite, delete, add].concat();
+        assert!(request.windows(expected.len()).any(|val| val == expected.as_slice()));
+    }
+
+    #[test]
+    fn sign_should_match_known_mac() {
+        // An update of home.example.com to 192.0.2.7 in zone example.com, the MAC is computed independently with
+        // HMAC-SHA256 over the message and the TSIG variables of RFC 8945.
+        let hex = "123428000001000000010000076578616d706c6503636f6d000006000104686f6d65076578616d706c6503636f6d00\
+                   000100010000003c0004c0000207";
+        let mut message = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect::<Vec<_>>();
+        let unsigned_len = message.len();
+        let key = TsigKey {
+            name: String::from("dwd-key"),
+            algorithm: TsigAlgorithm::HmacSha256,
+            secret: b"secret-key-for-testing-only".to_vec(),
+        };
+
+        let mac = sign(&mut message, &key, 1700000000).unwrap();
+        assert_eq!(
+            signature::hex(&mac),
+            "7090c24624a514567af0e0b35f151871e4984547a801ee6bc2dd4118573b7549"
+        );
+        // The TSIG record is appended as the only additional record.
+        assert_eq!(&message[10..12], &[0, 1]);
+        let tsig = find_tsig(&message).unwrap().unwrap();
+        assert_eq!(tsig.offset, unsigned_len);
+        assert_eq!(tsig.time_signed, 1700000000);
+        assert_eq!(tsig.mac, mac);
+        assert_eq!(tsig.original_id, 0x1234);
+    }
+
+    #[tokio::test]
+    async fn update_set_should_skip_empty_set() {
+        let (addr, received) = start_server(0, false).await;
+
+        update_set(&conf(addr, None), &["2001:db8::1".parse().unwrap()])
+            .await
+            .unwrap();
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_should_retry_over_tcp_when_truncated() {
+        let (addr, received) = start_server(0, true).await;
+
+        update_set(
+            &conf(addr, None),
+            &["192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap()],
+        )
+        .await
+        .unwrap();
+
+        let received = received.lock().unwrap();
+        let transports = received.iter().map(|(val, _)| val.as_str()).collect::<Vec<_>>();
+        assert_eq!(transports, ["udp", "tcp"]);
+        // The delete and the two records.
+        assert_eq!(&received[1].1[8..10], &[0, 3]);
+    }
+
+    #[tokio::test]
+    async fn update_should_classify_rcode() {
+        let (addr, _) = start_server(5, false).await;
+        let err = update(&conf(addr, Some(Rfc2136Transport::Tcp)), &"192.0.2.7".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Auth);
+        assert_eq!(err.record.as_deref(), Some("home.example.com"));
+
+        let (addr, _) = start_server(8, false).await;
+        let err = update(&conf(addr, None), &"2001:db8::1".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,564 @@
+//! Update the records hosted on AWS Route 53, the requests are signed with Signature Version 4 by hand, so the AWS
+//! SDK is not needed.
+//!
+//! The records are replaced with `UPSERT` changes, and DWD waits until the change is `INSYNC` on all Route 53
+//! servers if `wait_for_sync` is turned on.
+
+use chrono::{DateTime, Utc};
+use log::trace;
+use openssl::hash::MessageDigest;
+use reqwest::{
+    header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+    Request, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs,
+    net::IpAddr,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tokio::time;
+
+use super::super::error::{DwdError, ErrorKind};
+use super::super::http::HttpClient;
+use super::super::util::error_style;
+use super::signature::{hex, hmac, host, sha256_hex, uri_encode};
+
+const DEFAULT_ENDPOINT: &str = "https://route53.amazonaws.com";
+/// Route 53 is a global service, its requests are signed for us-east-1 unless in the China regions.
+const DEFAULT_REGION: &str = "us-east-1";
+const SERVICE: &str = "route53";
+const API_VERSION: &str = "2013-04-01";
+const DEFAULT_TTL: u32 = 300;
+const DEFAULT_SYNC_TIMEOUT: u64 = 120;
+const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigRoute53 {
+    /// The ID of the hosted zone, e.g. `Z1D633PJN98FT9`.
+    pub hosted_zone_id: String,
+    /// The full name of the record, e.g. `home.example.com`.
+    pub record_name: String,
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// The access key, or set it through env variables AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or in the
+    /// shared credentials file.
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    /// The profile in the shared credentials file, it is read when the keys are not set in the config, or when the
+    /// profile is set explicitly. `AWS_PROFILE` or `default` by default.
+    pub profile: Option<String>,
+    /// The shared credentials file, `AWS_SHARED_CREDENTIALS_FILE` or `~/.aws/credentials` by default.
+    pub credentials_file: Option<String>,
+    /// The region which requests are signed for, `us-east-1` by default.
+    pub region: Option<String>,
+    /// The API endpoint, `https://route53.amazonaws.com` by default.
+    pub endpoint: Option<String>,
+    /// Wait until the change is propagated to all Route 53 servers.
+    pub wait_for_sync: Option<bool>,
+    /// Seconds to wait for the change to be in sync, 120 by default.
+    pub sync_timeout: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+/// Update the record on AWS Route 53
+///
+/// The document of Route 53 API: https://docs.aws.amazon.com/Route53/latest/APIReference/API_ChangeResourceRecordSets.html
+///
+/// # Example:
+/// ```rust
+/// route53::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigRoute53, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+    upsert(conf, client, record_type, &[*ip])
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+/// Replace the record set with the IPs of `record_type`, the IPs of the other family are ignored.
+pub async fn update_set(conf: &ConfigRoute53, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    update_set_inner(conf, client, ips)
+        .await
+        .map_err(|e| e.with_record(record_name(conf)))
+}
+
+async fn update_set_inner(conf: &ConfigRoute53, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    if record_type != "A" && record_type != "AAAA" {
+        return Err(DwdError::config(format!(
+            "Invalid config.route53.record_type {:?}, it should be A or AAAA.",
+            record_type
+        )));
+    }
+    let ips = ips
+        .iter()
+        .filter(|ip| ip.is_ipv4() == (record_type == "A"))
+        .cloned()
+        .collect::<Vec<_>>();
+    // An UPSERT needs at least one value, and deleting the record needs its current values.
+    if ips.is_empty() {
+        return Err(DwdError::config(format!(
+            "No {} record to publish, Route 53 could not hold an empty record set.",
+            record_type
+        )));
+    }
+    upsert(conf, client, record_type, &ips).await
+}
+
+pub fn record_name(conf: &ConfigRoute53) -> String {
+    conf.record_name.trim_end_matches('.').to_owned()
+}
+
+async fn upsert(conf: &ConfigRoute53, client: &HttpClient, record_type: &str, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let credentials = credentials(conf)?;
+    let zone_id = conf.hosted_zone_id.trim_start_matches("/hostedzone/");
+    let url = format!("{}/{}/hostedzone/{}/rrset/", endpoint(conf), API_VERSION, zone_id);
+    let body = change_batch(conf, record_type, ips);
+    trace!("Request body: {}", body);
+
+    let mut request = client
+        .post(&url)
+        .header(CONTENT_TYPE, "application/xml")
+        .body(body)
+        .build()?;
+    sign(&mut request, &credentials, region(conf), SERVICE, Utc::now())?;
+    let text = check_response(client.execute(request).await?).await?;
+
+    let (change_id, status) = match (xml_text(&text, "Id"), xml_text(&text, "Status")) {
+        (Some(id), Some(status)) => (id, status),
+        _ => return Err(DwdError::parse(format!("Unknown response: {}", error_style(text)))),
+    };
+    trace!("Change {} is {}", change_id, status);
+
+    if conf.wait_for_sync.unwrap_or(false) && status != "INSYNC" {
+        wait_for_sync(conf, client, &credentials, &change_id).await?;
+    }
+
+    trace!("Update {} to: {:?}", record_name(conf), ips);
+
+    Ok(())
+}
+
+/// Poll the change until it is `INSYNC`, a timeout is a transient error so the update is retried in the next round.
+async fn wait_for_sync(
+    conf: &ConfigRoute53,
+    client: &HttpClient,
+    credentials: &Credentials,
+    change_id: &str,
+) -> Result<(), DwdError> {
+    let timeout = Duration::from_secs(conf.sync_timeout.unwrap_or(DEFAULT_SYNC_TIMEOUT));
+    let url = format!(
+        "{}/{}/change/{}",
+        endpoint(conf),
+        API_VERSION,
+        change_id.trim_start_matches("/change/")
+    );
+    let started_at = Instant::now();
+    loop {
+        let mut request = client.get(&url).build()?;
+        sign(&mut request, credentials, region(conf), SERVICE, Utc::now())?;
+        let text = check_response(client.execute(request).await?).await?;
+        match xml_text(&text, "Status").as_deref() {
+            Some("INSYNC") => return Ok(()),
+            Some(status) => trace!("Change {} is {}", change_id, status),
+            None => return Err(DwdError::parse(format!("Unknown response: {}", error_style(text)))),
+        }
+
+        if started_at.elapsed() + SYNC_INTERVAL > timeout {
+            return Err(DwdError::new(
+                ErrorKind::Transport,
+                format!("The change {} is not in sync after {}s.", change_id, timeout.as_secs()),
+            ));
+        }
+        time::sleep(SYNC_INTERVAL).await;
+    }
+}
+
+fn change_batch(conf: &ConfigRoute53, record_type: &str, ips: &[IpAddr]) -> String {
+    let records = ips
+        .iter()
+        .map(|ip| format!("<ResourceRecord><Value>{}</Value></ResourceRecord>", ip))
+        .collect::<String>();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<ChangeResourceRecordSetsRequest xmlns="https://route53.amazonaws.com/doc/{}/"><ChangeBatch><Comment>Updated by DWD</Comment><Changes><Change><Action>UPSERT</Action><ResourceRecordSet><Name>{}</Name><Type>{}</Type><TTL>{}</TTL><ResourceRecords>{}</ResourceRecords></ResourceRecordSet></Change></Changes></ChangeBatch></ChangeResourceRecordSetsRequest>"#,
+        API_VERSION,
+        xml_escape(&conf.record_name),
+        record_type,
+        conf.record_ttl.unwrap_or(DEFAULT_TTL),
+        records
+    )
+}
+
+/// Return the body of a successful response, or classify the error by the status and the error code.
+async fn check_response(response: reqwest::Response) -> Result<String, DwdError> {
+    let status = response.status();
+    let text = response.text().await?;
+    trace!("Response: {} {}", status, text);
+    if status.is_success() {
+        return Ok(text);
+    }
+
+    let code = xml_text(&text, "Code").unwrap_or_default();
+    let message = format!(
+        "API response error: {} {}: {}",
+        error_style(status),
+        code,
+        xml_text(&text, "Message").unwrap_or_default()
+    );
+    let err = match code.as_str() {
+        "Throttling" | "PriorRequestNotComplete" => DwdError::new(ErrorKind::RateLimit, message),
+        "InvalidClientTokenId" | "SignatureDoesNotMatch" | "IncompleteSignature" | "ExpiredToken" | "AccessDenied" => {
+            DwdError::from_status(StatusCode::FORBIDDEN, message)
+        }
+        _ => DwdError::from_status(status, message),
+    };
+    Err(err)
+}
+
+/// Sign the request with AWS Signature Version 4, the host and all headers of the request are signed.
+///
+/// The document of the signature: https://docs.aws.amazon.com/IAM/latest/UserGuide/create-signed-request.html
+fn sign(
+    request: &mut Request,
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    now: DateTime<Utc>,
+) -> Result<(), DwdError> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = &amz_date[..8];
+    request
+        .headers_mut()
+        .insert("x-amz-date", HeaderValue::from_str(&amz_date)?);
+    if let Some(token) = credentials.session_token.as_ref() {
+        request
+            .headers_mut()
+            .insert("x-amz-security-token", HeaderValue::from_str(token)?);
+    }
+
+    let url = request.url();
+    let mut headers = vec![(String::from("host"), host(url))];
+    for (name, value) in request.headers() {
+        let value = value.to_str().map_err(|e| DwdError::config(e.to_string()))?;
+        headers.push((name.as_str().to_lowercase(), value.trim().to_owned()));
+    }
+    headers.sort();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let mut query = url
+        .query_pairs()
+        .map(|(key, val)| format!("{}={}", uri_encode(&key, false), uri_encode(&val, false)))
+        .collect::<Vec<_>>();
+    query.sort();
+    let payload = request.body().and_then(|body| body.as_bytes()).unwrap_or_default();
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method(),
+        // The path is encoded once more, as required by all services except S3.
+        uri_encode(url.path(), true),
+        query.join("&"),
+        headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect::<String>(),
+        signed_headers,
+        sha256_hex(payload)
+    );
+    trace!("Canonical request:\n{}", canonical_request);
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        sha256_hex(canonical_request.as_bytes())
+    );
+    let mut key = format!("AWS4{}", credentials.secret_access_key).into_bytes();
+    for part in [date, region, service, "aws4_request"] {
+        key = hmac(MessageDigest::sha256(), &key, part.as_bytes())?;
+    }
+    let signature = hex(&hmac(MessageDigest::sha256(), &key, string_to_sign.as_bytes())?);
+
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id, scope, signed_headers, signature
+    );
+    request
+        .headers_mut()
+        .insert(AUTHORIZATION, HeaderValue::from_str(&authorization)?);
+
+    Ok(())
+}
+
+/// Find the credentials in the config, the env variables and the shared credentials file in order.
+fn credentials(conf: &ConfigRoute53) -> Result<Credentials, DwdError> {
+    if let (Some(access_key_id), Some(secret_access_key)) =
+        (conf.access_key_id.as_ref(), conf.secret_access_key.as_ref())
+    {
+        return Ok(Credentials {
+            access_key_id: access_key_id.to_owned(),
+            secret_access_key: secret_access_key.to_owned(),
+            session_token: conf.session_token.clone(),
+        });
+    }
+
+    if conf.profile.is_none() {
+        if let (Ok(access_key_id), Ok(secret_access_key)) =
+            (env::var("AWS_ACCESS_KEY_ID"), env::var("AWS_SECRET_ACCESS_KEY"))
+        {
+            return Ok(Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            });
+        }
+    }
+
+    let profile = conf
+        .profile
+        .clone()
+        .or_else(|| env::var("AWS_PROFILE").ok())
+        .unwrap_or_else(|| String::from("default"));
+    let path = conf
+        .credentials_file
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(|| env::var("AWS_SHARED_CREDENTIALS_FILE").ok().map(PathBuf::from))
+        .or_else(|| {
+            env::var("HOME")
+                .or_else(|_| env::var("USERPROFILE"))
+                .ok()
+                .map(|home| PathBuf::from(home).join(".aws").join("credentials"))
+        });
+    let not_found = || {
+        DwdError::config(format!(
+            "Please set the AWS credentials in config.route53, env variables AWS_ACCESS_KEY_ID and \
+             AWS_SECRET_ACCESS_KEY, or profile {:?} of the shared credentials file.",
+            profile
+        ))
+    };
+    let text = path
+        .and_then(|path| fs::read_to_string(path).ok())
+        .ok_or_else(not_found)?;
+    parse_credentials_file(&text, &profile).ok_or_else(not_found)
+}
+
+/// Read a profile from the INI formatted shared credentials file.
+fn parse_credentials_file(text: &str, profile: &str) -> Option<Credentials> {
+    let mut in_profile = false;
+    let (mut access_key_id, mut secret_access_key, mut session_token) = (None, None, None);
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            let section = section.trim();
+            in_profile = section.strip_prefix("profile ").unwrap_or(section).trim() == profile;
+            continue;
+        }
+        if !in_profile {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let value = Some(value.trim().to_owned());
+            match key.trim() {
+                "aws_access_key_id" => access_key_id = value,
+                "aws_secret_access_key" => secret_access_key = value,
+                "aws_session_token" => session_token = value,
+                _ => {}
+            }
+        }
+    }
+
+    Some(Credentials {
+        access_key_id: access_key_id?,
+        secret_access_key: secret_access_key?,
+        session_token,
+    })
+}
+
+fn endpoint(conf: &ConfigRoute53) -> &str {
+    conf.endpoint
+        .as_deref()
+        .unwrap_or(DEFAULT_ENDPOINT)
+        .trim_end_matches('/')
+}
+
+fn region(conf: &ConfigRoute53) -> &str {
+    conf.region.as_deref().unwrap_or(DEFAULT_REGION)
+}
+
+/// The text of the first element with the tag, the responses are small and flat enough to skip an XML parser.
+fn xml_text(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(
+        xml[start..end]
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+    use chrono::TimeZone;
+    use reqwest::Method;
+
+    fn conf(server: &MockServer) -> ConfigRoute53 {
+        ConfigRoute53 {
+            hosted_zone_id: String::from("/hostedzone/Z1D633PJN98FT9"),
+            record_name: String::from("home.example.com"),
+            record_ttl: Some(60),
+            record_type: None,
+            access_key_id: Some(String::from("AKIDEXAMPLE")),
+            secret_access_key: Some(String::from("secret")),
+            session_token: Some(String::from("token")),
+            profile: None,
+            credentials_file: None,
+            region: None,
+            endpoint: Some(server.url.clone()),
+            wait_for_sync: Some(true),
+            sync_timeout: None,
+        }
+    }
+
+    #[test]
+    fn sign_should_match_aws_test_suite() {
+        // The `get-vanilla` case of the AWS Signature Version 4 test suite.
+        let mut request = Request::new(Method::GET, "https://example.amazonaws.com/".parse().unwrap());
+        let credentials = Credentials {
+            access_key_id: String::from("AKIDEXAMPLE"),
+            secret_access_key: String::from("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+            session_token: None,
+        };
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+        sign(&mut request, &credentials, "us-east-1", "service", now).unwrap();
+
+        assert_eq!(
+            request.headers()[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[tokio::test]
+    async fn update_should_upsert_and_wait_for_sync() {
+        let server = MockServer::start(|request| {
+            if request.method == "POST" {
+                MockResponse::new(
+                    200,
+                    "<ChangeResourceRecordSetsResponse><ChangeInfo><Id>/change/C2682N5HXP0BZ4</Id>\
+                     <Status>PENDING</Status></ChangeInfo></ChangeResourceRecordSetsResponse>",
+                )
+            } else {
+                MockResponse::new(
+                    200,
+                    "<GetChangeResponse><ChangeInfo><Id>/change/C2682N5HXP0BZ4</Id>\
+                     <Status>INSYNC</Status></ChangeInfo></GetChangeResponse>",
+                )
+            }
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update_set(
+            &conf(&server),
+            &client,
+            &[
+                "1.1.1.1".parse().unwrap(),
+                "2001:db8::1".parse().unwrap(),
+                "2.2.2.2".parse().unwrap(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].path, "/2013-04-01/hostedzone/Z1D633PJN98FT9/rrset/");
+        assert!(requests[0].body.contains(
+            "<Action>UPSERT</Action><ResourceRecordSet><Name>home.example.com</Name><Type>A</Type><TTL>60</TTL>\
+             <ResourceRecords><ResourceRecord><Value>1.1.1.1</Value></ResourceRecord>\
+             <ResourceRecord><Value>2.2.2.2</Value></ResourceRecord></ResourceRecords>"
+        ));
+        assert!(requests[0].headers["authorization"].starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/",));
+        assert!(requests[0].headers["authorization"]
+            .contains("SignedHeaders=content-type;host;x-amz-date;x-amz-security-token, Signature="));
+        assert_eq!(requests[0].headers["x-amz-security-token"], "token");
+        assert_eq!(requests[1].path, "/2013-04-01/change/C2682N5HXP0BZ4");
+    }
+
+    #[tokio::test]
+    async fn update_should_classify_errors() {
+        let server = MockServer::start(|request| {
+            let (status, code) = if request.body.contains("1.1.1.1") {
+                (403, "InvalidClientTokenId")
+            } else {
+                (400, "Throttling")
+            };
+            MockResponse::new(
+                status,
+                &format!(
+                    "<ErrorResponse><Error><Type>Sender</Type><Code>{}</Code><Message>Oops</Message></Error>\
+                     </ErrorResponse>",
+                    code
+                ),
+            )
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        let err = update(&conf(&server), &client, &"1.1.1.1".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Auth);
+        assert_eq!(err.record.as_deref(), Some("home.example.com"));
+
+        let err = update(&conf(&server), &client, &"2.2.2.2".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::RateLimit);
+        assert!(err.is_transient());
+    }
+
+    #[test]
+    fn parse_credentials_file_should_read_profile() {
+        let text = "[default]\naws_access_key_id = A\naws_secret_access_key = B\n\n\
+                    # comment\n[profile work]\naws_access_key_id=C\naws_secret_access_key=D\naws_session_token=E\n";
+        assert_eq!(
+            parse_credentials_file(text, "work"),
+            Some(Credentials {
+                access_key_id: String::from("C"),
+                secret_access_key: String::from("D"),
+                session_token: Some(String::from("E")),
+            })
+        );
+        assert_eq!(parse_credentials_file(text, "default").unwrap().access_key_id, "A");
+        assert_eq!(parse_credentials_file(text, "missing"), None);
+    }
+}
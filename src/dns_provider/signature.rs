@@ -0,0 +1,55 @@
+//! The helpers of request signatures, which are shared by the providers signing their API requests with HMAC.
+
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use reqwest::Url;
+
+use super::super::error::DwdError;
+
+pub fn hmac(digest: MessageDigest, key: &[u8], data: &[u8]) -> Result<Vec<u8>, DwdError> {
+    let sign = || {
+        let pkey = PKey::hmac(key)?;
+        let mut signer = Signer::new(digest, &pkey)?;
+        signer.update(data)?;
+        signer.sign_to_vec()
+    };
+    sign().map_err(|e| DwdError::config(format!("Sign the request failed: {}", e)))
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&openssl::sha::sha256(data))
+}
+
+/// Encode the bytes in lowercase hex.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Percent-encode everything except the unreserved characters of RFC 3986, and `/` if it is kept.
+pub fn uri_encode(input: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// The value of the `Host` header which is sent for the URL, the port is only included if it is not the default one.
+pub fn host(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    }
+}
+
+/// Encode the bytes in the URL safe base64 without padding, as used by JWT.
+pub fn base64url(bytes: &[u8]) -> String {
+    openssl::base64::encode_block(bytes)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
@@ -0,0 +1,264 @@
+use reqwest::{Method, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::net::IpAddr;
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::rest::{self, ApiFuture, RecordApi, RestRecord};
+
+const BASE_URL: &str = "https://api.vultr.com/v2/";
+const DEFAULT_TTL: u32 = 300;
+const MIN_TTL: u32 = 60;
+const PAGE_SIZE: u32 = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigVultr {
+    pub domain: String,
+    /// The host record, `@` for the domain itself.
+    pub record_host: Option<String>,
+    /// The TTL of records, at least 60.
+    pub record_ttl: Option<u32>,
+    /// The type of records which multi-value updates manage, `A` by default.
+    pub record_type: Option<String>,
+    /// The API key, or set it through env variable VULTR_API_KEY.
+    pub api_key: Option<String>,
+    /// The API base, `https://api.vultr.com/v2/` by default.
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordList {
+    records: Vec<Record>,
+    meta: Option<Meta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Meta {
+    links: Links,
+}
+
+#[derive(Debug, Deserialize)]
+struct Links {
+    /// The cursor of the next page, empty on the last page.
+    next: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Record {
+    id: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    name: String,
+    data: String,
+    ttl: Option<u32>,
+}
+
+struct Api<'a> {
+    conf: &'a ConfigVultr,
+    client: &'a HttpClient,
+    records_url: Url,
+    api_key: String,
+}
+
+/// Update DNS record on Vultr
+///
+/// The document of Vultr API: https://www.vultr.com/api/#tag/dns
+///
+/// # Example:
+/// ```rust
+/// vultr::update(conf, client, ip).await?;
+/// ```
+pub async fn update(conf: &ConfigVultr, client: &HttpClient, ip: &IpAddr) -> Result<(), DwdError> {
+    let update_inner = async { rest::update(&Api::new(conf, client)?, ip).await };
+    update_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+/// Make the records of the host contain exactly the given IPs, one record per IP.
+pub async fn update_set(conf: &ConfigVultr, client: &HttpClient, ips: &[IpAddr]) -> Result<(), DwdError> {
+    let record_type = conf.record_type.as_deref().unwrap_or("A");
+    let update_set_inner = async { rest::update_set(&Api::new(conf, client)?, record_type, ips).await };
+    update_set_inner.await.map_err(|e| e.with_record(record_name(conf)))
+}
+
+pub fn record_name(conf: &ConfigVultr) -> String {
+    rest::record_name(&conf.domain, conf.record_host.as_deref())
+}
+
+fn api_message(val: &Value) -> Option<&str> {
+    val["error"].as_str()
+}
+
+impl<'a> Api<'a> {
+    fn new(conf: &'a ConfigVultr, client: &'a HttpClient) -> Result<Self, DwdError> {
+        let api_key = match conf.api_key.as_ref() {
+            Some(val) => val.to_owned(),
+            None => {
+                env::var("VULTR_API_KEY").map_err(|_| DwdError::config("Please set env variable VULTR_API_KEY."))?
+            }
+        };
+        let records_url = Url::parse(conf.base_url.as_deref().unwrap_or(BASE_URL))?
+            .join(&format!("domains/{}/records/", conf.domain))?;
+        Ok(Api {
+            conf,
+            client,
+            records_url,
+            api_key,
+        })
+    }
+
+    /// The name of the domain itself is empty on Vultr.
+    fn host(&self) -> &str {
+        match rest::relative_host(self.conf.record_host.as_deref()) {
+            "@" => "",
+            host => host,
+        }
+    }
+}
+
+impl RecordApi for Api<'_> {
+    fn ttl(&self) -> u32 {
+        rest::ttl(self.conf.record_ttl, DEFAULT_TTL, MIN_TTL)
+    }
+
+    fn list<'a>(&'a self, record_type: &'a str) -> ApiFuture<'a, Vec<RestRecord>> {
+        Box::pin(async move {
+            let mut records = Vec::new();
+            let mut cursor = String::new();
+            loop {
+                let mut url = self.records_url.clone();
+                url.query_pairs_mut().append_pair("per_page", &PAGE_SIZE.to_string());
+                if !cursor.is_empty() {
+                    url.query_pairs_mut().append_pair("cursor", &cursor);
+                }
+                let builder = self.client.get(url).bearer_auth(&self.api_key);
+                let ret = rest::send(self.client, builder, api_message)
+                    .await?
+                    .json::<RecordList>()
+                    .await?;
+                records.extend(
+                    ret.records
+                        .into_iter()
+                        .filter(|record| record.name == self.host() && record.record_type == record_type)
+                        .map(|record| RestRecord {
+                            id: record.id,
+                            value: record.data,
+                            ttl: record.ttl,
+                        }),
+                );
+
+                match ret.meta {
+                    Some(meta) if !meta.links.next.is_empty() => cursor = meta.links.next,
+                    _ => break,
+                }
+            }
+            Ok(records)
+        })
+    }
+
+    fn create<'a>(&'a self, record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .post(self.records_url.clone())
+                .bearer_auth(&self.api_key)
+                .json(&json!({"type": record_type, "name": self.host(), "data": value, "ttl": self.ttl()}));
+            rest::send(self.client, builder, api_message).await?;
+            Ok(())
+        })
+    }
+
+    fn update<'a>(&'a self, record: &'a RestRecord, _record_type: &'a str, value: String) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .request(Method::PATCH, self.records_url.join(&record.id)?)
+                .bearer_auth(&self.api_key)
+                .json(&json!({"data": value, "ttl": self.ttl()}));
+            rest::send(self.client, builder, api_message).await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, record: &'a RestRecord) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .delete(self.records_url.join(&record.id)?)
+                .bearer_auth(&self.api_key);
+            rest::send(self.client, builder, api_message).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn conf(server: &MockServer) -> ConfigVultr {
+        ConfigVultr {
+            domain: String::from("example.com"),
+            record_host: Some(String::from("home")),
+            record_ttl: None,
+            record_type: None,
+            api_key: Some(String::from("k")),
+            base_url: Some(server.url.clone()),
+        }
+    }
+
+    #[tokio::test]
+    async fn update_should_follow_cursor_and_patch_record() {
+        let server = MockServer::start(|request| match request.method.as_str() {
+            "GET" if !request.path.contains("cursor=") => MockResponse::json(
+                200,
+                json!({
+                    "records": [{"id": "a", "type": "A", "name": "nas", "data": "1.1.1.1", "ttl": 300}],
+                    "meta": {"total": 2, "links": {"next": "c2", "prev": ""}}
+                }),
+            ),
+            "GET" => MockResponse::json(
+                200,
+                json!({
+                    "records": [{"id": "b", "type": "A", "name": "home", "data": "1.1.1.1", "ttl": 300}],
+                    "meta": {"total": 2, "links": {"next": "", "prev": "c1"}}
+                }),
+            ),
+            _ => MockResponse::new(204, ""),
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        update(&conf(&server), &client, &"2.2.2.2".parse().unwrap())
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].headers["authorization"], "Bearer k");
+        assert_eq!(requests[1].path, "/domains/example.com/records/?per_page=500&cursor=c2");
+        assert_eq!(requests[2].method, "PATCH");
+        assert_eq!(requests[2].path, "/domains/example.com/records/b");
+        assert_eq!(
+            serde_json::from_str::<Value>(&requests[2].body).unwrap(),
+            json!({"data": "2.2.2.2", "ttl": 300})
+        );
+    }
+
+    #[tokio::test]
+    async fn update_should_classify_errors() {
+        let server =
+            MockServer::start(|_| MockResponse::json(401, json!({"error": "Invalid API token.", "status": 401}))).await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        let err = update(&conf(&server), &client, &"1.1.1.1".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Auth);
+        assert!(err.message.contains("Invalid API token."));
+    }
+}
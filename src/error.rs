@@ -0,0 +1,221 @@
+//! The error model of DWD.
+//!
+//! Errors are classified by kind and carry the provider and record they happened on, so the callers could decide
+//! whether to retry, how to exit and what to notify, instead of matching the error messages.
+
+use reqwest::StatusCode;
+use std::{error::Error, fmt, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The config is missing or invalid.
+    Config,
+    /// Connection failures, timeouts and other errors before a response is received.
+    Transport,
+    /// The provider responded with an unexpected HTTP status.
+    HttpStatus,
+    /// The credentials are rejected by the provider.
+    Auth,
+    /// The provider is rate limited.
+    RateLimit,
+    /// The domain, zone or record does not exist.
+    NotFound,
+    /// The response could not be understood.
+    Parse,
+}
+
+impl ErrorKind {
+    /// The exit code of the process when it has to exit for this kind of error, as defined in sysexits.h.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::Config => 78,
+            ErrorKind::Auth => 77,
+            ErrorKind::Transport | ErrorKind::HttpStatus | ErrorKind::RateLimit => 69,
+            ErrorKind::NotFound => 68,
+            ErrorKind::Parse => 65,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Config => "config",
+            ErrorKind::Transport => "transport",
+            ErrorKind::HttpStatus => "http_status",
+            ErrorKind::Auth => "auth",
+            ErrorKind::RateLimit => "rate_limit",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Parse => "parse",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub struct DwdError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// The name of provider, e.g. `name.com`.
+    pub provider: Option<String>,
+    /// The record which is being updated, e.g. `home.example.com`.
+    pub record: Option<String>,
+    pub status: Option<StatusCode>,
+    /// How long to wait before the provider accepts requests again, only for `ErrorKind::RateLimit`.
+    pub retry_after: Option<Duration>,
+}
+
+impl DwdError {
+    pub fn new<T: Into<String>>(kind: ErrorKind, message: T) -> Self {
+        DwdError {
+            kind,
+            message: message.into(),
+            provider: None,
+            record: None,
+            status: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn config<T: Into<String>>(message: T) -> Self {
+        DwdError::new(ErrorKind::Config, message)
+    }
+
+    pub fn parse<T: Into<String>>(message: T) -> Self {
+        DwdError::new(ErrorKind::Parse, message)
+    }
+
+    pub fn rate_limited(retry_after: Duration) -> Self {
+        let mut err = DwdError::new(
+            ErrorKind::RateLimit,
+            format!("Rate limited, retry after {}s", retry_after.as_secs()),
+        );
+        err.retry_after = Some(retry_after);
+        err
+    }
+
+    /// Classify the error by the HTTP status of the response.
+    pub fn from_status<T: Into<String>>(status: StatusCode, message: T) -> Self {
+        let kind = match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ErrorKind::Auth,
+            StatusCode::NOT_FOUND => ErrorKind::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => ErrorKind::RateLimit,
+            _ => ErrorKind::HttpStatus,
+        };
+        let mut err = DwdError::new(kind, message);
+        err.status = Some(status);
+        err
+    }
+
+    /// Set the provider if it is not set yet, the innermost context wins.
+    pub fn with_provider<T: Into<String>>(mut self, provider: T) -> Self {
+        if self.provider.is_none() {
+            self.provider = Some(provider.into());
+        }
+        self
+    }
+
+    /// Set the record if it is not set yet, the innermost context wins.
+    pub fn with_record<T: Into<String>>(mut self, record: T) -> Self {
+        if self.record.is_none() {
+            self.record = Some(record.into());
+        }
+        self
+    }
+
+    /// Whether the error may go away by itself, so the operation is worth retrying later.
+    pub fn is_transient(&self) -> bool {
+        match self.kind {
+            ErrorKind::Transport | ErrorKind::RateLimit => true,
+            ErrorKind::HttpStatus => self.status.map(|val| val.is_server_error()).unwrap_or(true),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for DwdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(provider) = self.provider.as_ref() {
+            write!(f, "[{}] ", provider)?;
+        }
+        if let Some(record) = self.record.as_ref() {
+            write!(f, "{}: ", record)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for DwdError {}
+
+impl From<reqwest::Error> for DwdError {
+    fn from(err: reqwest::Error) -> Self {
+        let kind = if err.is_builder() {
+            ErrorKind::Config
+        } else if err.is_decode() {
+            ErrorKind::Parse
+        } else if let Some(status) = err.status() {
+            return DwdError::from_status(status, err.to_string());
+        } else {
+            ErrorKind::Transport
+        };
+        DwdError::new(kind, err.to_string())
+    }
+}
+
+impl From<reqwest::header::InvalidHeaderValue> for DwdError {
+    fn from(err: reqwest::header::InvalidHeaderValue) -> Self {
+        DwdError::config(err.to_string())
+    }
+}
+
+impl From<url::ParseError> for DwdError {
+    fn from(err: url::ParseError) -> Self {
+        DwdError::config(format!("Invalid URL: {}", err))
+    }
+}
+
+impl From<serde_json::Error> for DwdError {
+    fn from(err: serde_json::Error) -> Self {
+        DwdError::parse(err.to_string())
+    }
+}
+
+impl From<std::net::AddrParseError> for DwdError {
+    fn from(err: std::net::AddrParseError) -> Self {
+        DwdError::parse(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_status_should_classify_errors() {
+        assert_eq!(
+            DwdError::from_status(StatusCode::UNAUTHORIZED, "").kind,
+            ErrorKind::Auth
+        );
+        assert_eq!(DwdError::from_status(StatusCode::FORBIDDEN, "").kind, ErrorKind::Auth);
+        assert_eq!(
+            DwdError::from_status(StatusCode::NOT_FOUND, "").kind,
+            ErrorKind::NotFound
+        );
+
+        let err = DwdError::from_status(StatusCode::BAD_GATEWAY, "");
+        assert_eq!(err.kind, ErrorKind::HttpStatus);
+        assert!(err.is_transient());
+        assert!(!DwdError::from_status(StatusCode::BAD_REQUEST, "").is_transient());
+        assert!(!DwdError::from_status(StatusCode::UNAUTHORIZED, "").is_transient());
+        assert!(DwdError::rate_limited(Duration::from_secs(1)).is_transient());
+    }
+
+    #[test]
+    fn display_should_contain_context() {
+        let err = DwdError::from_status(StatusCode::UNAUTHORIZED, "Bad token")
+            .with_record("home.example.com")
+            .with_provider("name.com")
+            .with_provider("dynv6.com");
+        assert_eq!(err.to_string(), "[name.com] home.example.com: Bad token");
+    }
+}
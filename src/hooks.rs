@@ -0,0 +1,276 @@
+//! The hook commands which run when the IP changes or a DNS update finishes, e.g. to reload a WireGuard peer or the
+//! firewall rules.
+//!
+//! The commands run through the shell in background with the context in env variables, so a slow command never holds
+//! up the loops. The output is captured and logged, and a command is killed along with its children if it does not
+//! finish in time.
+
+use lazy_static::lazy_static;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, process::Stdio, sync::Mutex, time::Duration};
+use tokio::{process::Command, time};
+
+use super::notify::Event;
+
+const DEFAULT_TIMEOUT: u64 = 30;
+/// The output longer than this is truncated in logs.
+const MAX_OUTPUT: usize = 4096;
+
+lazy_static! {
+    static ref HOOKS: Mutex<ConfigHooks> = Mutex::new(ConfigHooks::default());
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigHooks {
+    /// Run after the changed IP is published.
+    pub on_ip_change: Option<String>,
+    /// Run after a DNS provider is updated successfully.
+    pub on_update_success: Option<String>,
+    /// Run after a DNS provider fails to update.
+    pub on_update_failure: Option<String>,
+    /// Seconds to wait before a command is killed, 30 by default.
+    pub timeout: Option<u64>,
+}
+
+impl ConfigHooks {
+    pub fn is_empty(&self) -> bool {
+        self.on_ip_change.is_none() && self.on_update_success.is_none() && self.on_update_failure.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    IpChange,
+    UpdateSuccess,
+    UpdateFailure,
+}
+
+impl Hook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::IpChange => "on_ip_change",
+            Hook::UpdateSuccess => "on_update_success",
+            Hook::UpdateFailure => "on_update_failure",
+        }
+    }
+
+    fn command(&self, conf: &ConfigHooks) -> Option<String> {
+        match self {
+            Hook::IpChange => conf.on_ip_change.clone(),
+            Hook::UpdateSuccess => conf.on_update_success.clone(),
+            Hook::UpdateFailure => conf.on_update_failure.clone(),
+        }
+    }
+}
+
+/// What happened, it is passed to the command as env variables like `DWD_NEW_IP`.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    pub wan: Option<String>,
+    pub old_ip: Option<IpAddr>,
+    pub new_ip: Option<IpAddr>,
+    pub provider: Option<String>,
+    pub record: Option<String>,
+    pub error: Option<String>,
+}
+
+impl Context {
+    /// The env variables of the command, the missing values are set to empty strings.
+    fn envs(&self, hook: Hook) -> Vec<(&'static str, String)> {
+        let ip = |val: Option<IpAddr>| val.map(|ip| ip.to_string()).unwrap_or_default();
+        vec![
+            ("DWD_HOOK", hook.name().to_owned()),
+            ("DWD_WAN", self.wan.clone().unwrap_or_default()),
+            ("DWD_OLD_IP", ip(self.old_ip)),
+            ("DWD_NEW_IP", ip(self.new_ip)),
+            ("DWD_PROVIDER", self.provider.clone().unwrap_or_default()),
+            ("DWD_RECORD", self.record.clone().unwrap_or_default()),
+            ("DWD_ERROR", self.error.clone().unwrap_or_default()),
+        ]
+    }
+}
+
+impl From<&Event> for Context {
+    fn from(event: &Event) -> Self {
+        Context {
+            wan: event.wan.clone(),
+            old_ip: event.old_ip,
+            new_ip: event.new_ip,
+            provider: event.provider.clone(),
+            record: event.record.clone(),
+            error: event.error.clone(),
+        }
+    }
+}
+
+pub fn init(conf: ConfigHooks) {
+    *HOOKS.lock().expect("The lock of hooks should not be poisoned.") = conf;
+}
+
+/// Run the command of the hook in background, it does nothing if the hook is not configured.
+pub fn run(hook: Hook, context: Context) {
+    let conf = HOOKS.lock().expect("The lock of hooks should not be poisoned.").clone();
+    let command = match hook.command(&conf) {
+        Some(val) if !val.trim().is_empty() => val,
+        _ => return,
+    };
+    let timeout = Duration::from_secs(conf.timeout.unwrap_or(DEFAULT_TIMEOUT));
+
+    tokio::spawn(async move {
+        debug!("Running hook {}: {}", hook.name(), command);
+        match execute(&command, &context.envs(hook), timeout).await {
+            Ok(output) => info!(
+                event = "hook_succeeded",
+                hook = hook.name();
+                "Hook {} finished{}",
+                hook.name(),
+                format_output(&output)
+            ),
+            Err(e) => error!(
+                target: "error",
+                event = "hook_failed",
+                hook = hook.name();
+                "Hook {} failed, error: {}",
+                hook.name(),
+                e
+            ),
+        }
+    });
+}
+
+/// Run the command through the shell, return the output if it exits with 0.
+async fn execute(command: &str, envs: &[(&'static str, String)], timeout: Duration) -> Result<String, String> {
+    #[cfg(unix)]
+    let mut cmd = Command::new("sh");
+    #[cfg(unix)]
+    cmd.arg("-c");
+    #[cfg(not(unix))]
+    let mut cmd = Command::new("cmd");
+    #[cfg(not(unix))]
+    cmd.arg("/C");
+
+    // The command runs in its own process group, so the processes it starts are killed with it.
+    #[cfg(unix)]
+    cmd.process_group(0);
+
+    let child = cmd
+        .arg(command)
+        .envs(envs.iter().map(|(name, val)| (*name, val)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // The command is killed when the timeout drops it.
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Start command {:?} failed: {}", command, e))?;
+    let pid = child.id();
+
+    let output = match time::timeout(timeout, child.wait_with_output()).await {
+        Ok(ret) => ret.map_err(|e| format!("Wait for command failed: {}", e))?,
+        Err(_) => {
+            kill_group(pid);
+            return Err(format!("Command timed out after {:?} and was killed.", timeout));
+        }
+    };
+
+    let text = [output.stdout, output.stderr]
+        .iter()
+        .map(|val| String::from_utf8_lossy(val).trim().to_owned())
+        .filter(|val| !val.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !output.status.success() {
+        let code = output
+            .status
+            .code()
+            .map(|code| code.to_string())
+            .unwrap_or_else(|| String::from("none"));
+        return Err(format!("Command exited with code {}{}", code, format_output(&text)));
+    }
+    Ok(text)
+}
+
+/// Kill the process group of the command, the shell itself is also killed when it is dropped.
+#[cfg(unix)]
+fn kill_group(pid: Option<u32>) {
+    if let Some(pid) = pid {
+        // SAFETY: killpg only sends a signal, the group ID is the PID of the shell which leads the group.
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_group(_pid: Option<u32>) {}
+
+fn format_output(output: &str) -> String {
+    if output.is_empty() {
+        return String::from(".");
+    }
+    let mut end = output.len().min(MAX_OUTPUT);
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    let ellipsis = if end < output.len() { " ..." } else { "" };
+    format!(", output:\n{}{}", &output[..end], ellipsis)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn context() -> Context {
+        Context {
+            old_ip: "1.1.1.1".parse().ok(),
+            new_ip: "2.2.2.2".parse().ok(),
+            provider: Some(String::from("name.com")),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn execute_should_pass_context_in_envs() {
+        let envs = context().envs(Hook::IpChange);
+        let output = execute(
+            "echo \"$DWD_HOOK $DWD_OLD_IP -> $DWD_NEW_IP on $DWD_PROVIDER [$DWD_ERROR]\"",
+            &envs,
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output, "on_ip_change 1.1.1.1 -> 2.2.2.2 on name.com []");
+    }
+
+    #[tokio::test]
+    async fn execute_should_report_exit_code_and_timeout() {
+        let envs = context().envs(Hook::UpdateFailure);
+        let err = execute("echo oops >&2; exit 3", &envs, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert_eq!(err, "Command exited with code 3, output:\noops");
+
+        let err = execute("sleep 5", &envs, Duration::from_millis(100)).await.unwrap_err();
+        assert_eq!(err, "Command timed out after 100ms and was killed.");
+    }
+
+    #[tokio::test]
+    async fn execute_should_kill_children_on_timeout() {
+        let path = std::env::temp_dir().join(format!("dwd-hook-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut envs = context().envs(Hook::IpChange);
+        envs.push(("DWD_TEST_FILE", path.to_string_lossy().into_owned()));
+        execute(
+            "(sleep 1; touch \"$DWD_TEST_FILE\") & sleep 5",
+            &envs,
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap_err();
+
+        time::sleep(Duration::from_millis(1500)).await;
+        assert!(!path.exists());
+    }
+}
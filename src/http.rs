@@ -0,0 +1,450 @@
+//! The shared HTTP client used by all IP providers and DNS providers.
+//!
+//! Clients are cached by their configs, so the jobs and providers with the same configs reuse the same connection
+//! pool. Transient errors like connection failures, timeouts and 5xx responses are retried with exponential backoff.
+//! Requests to a provider are throttled by the rate limiter of the provider, see rate_limit.rs.
+
+use lazy_static::lazy_static;
+use log::{debug, trace};
+use reqwest::{Client, IntoUrl, Method, Proxy, Request, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error, net::IpAddr, sync::Mutex, time::Duration};
+use tokio::time;
+
+use super::error::DwdError;
+use super::rate_limit;
+use super::util::warn_style;
+
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+const DEFAULT_TIMEOUT: u64 = 30;
+const DEFAULT_RETRIES: u32 = 2;
+const DEFAULT_RETRY_BACKOFF: u64 = 500;
+const DEFAULT_USER_AGENT: &str = concat!("dwd/", env!("CARGO_PKG_VERSION"));
+/// Wait for the rate limit in place if it is shorter than this, otherwise give up and let the caller retry later.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref CLIENTS: Mutex<HashMap<String, Client>> = Mutex::new(HashMap::new());
+}
+
+/// The local address or interface which the connections are bound to, it is used to choose an uplink.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigBind {
+    pub bind_address: Option<IpAddr>,
+    /// Bind to the interface with SO_BINDTODEVICE, only supported on Linux.
+    pub bind_interface: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigHttp {
+    /// The timeout of establishing connections in seconds, 10 by default.
+    pub connect_timeout: Option<u64>,
+    /// The timeout of the whole request in seconds, 30 by default.
+    pub timeout: Option<u64>,
+    /// How many times a request is retried for transient errors, 2 by default.
+    pub retries: Option<u32>,
+    /// The delay before the first retry in milliseconds, it doubles after each retry, 500 by default.
+    pub retry_backoff: Option<u64>,
+    /// The HTTP, HTTPS or SOCKS5 proxy, e.g. `socks5://127.0.0.1:1080`.
+    pub proxy: Option<String>,
+    pub user_agent: Option<String>,
+    /// The requests allowed per second for each provider, there is no limit by default.
+    pub rate_limit: Option<f64>,
+    /// How many requests could be sent at once before throttled by `rate_limit`, 1 by default.
+    pub rate_limit_burst: Option<u32>,
+    /// Override the configs above for some providers, the keys are the provider names like `name.com`.
+    #[serde(default)]
+    pub providers: HashMap<String, ConfigHttp>,
+}
+
+impl ConfigHttp {
+    /// Merge the override into the configs, the fields set in the override take precedence.
+    pub fn merge(&self, other: &ConfigHttp) -> ConfigHttp {
+        ConfigHttp {
+            connect_timeout: other.connect_timeout.or(self.connect_timeout),
+            timeout: other.timeout.or(self.timeout),
+            retries: other.retries.or(self.retries),
+            retry_backoff: other.retry_backoff.or(self.retry_backoff),
+            proxy: other.proxy.clone().or_else(|| self.proxy.clone()),
+            user_agent: other.user_agent.clone().or_else(|| self.user_agent.clone()),
+            rate_limit: other.rate_limit.or(self.rate_limit),
+            rate_limit_burst: other.rate_limit_burst.or(self.rate_limit_burst),
+            providers: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    retries: u32,
+    retry_backoff: Duration,
+    rate_limit: Option<f64>,
+    rate_limit_burst: u32,
+    /// The name of provider which the requests are sent to, it is used as the key of rate limiter.
+    provider: Option<String>,
+    providers: HashMap<String, HttpClient>,
+}
+
+impl HttpClient {
+    /// Build the client and the clients overridden for providers, or reuse them if they have been built.
+    pub fn new(conf: &ConfigHttp, bind: &ConfigBind) -> Result<Self, Box<dyn Error>> {
+        let mut providers = HashMap::new();
+        for (name, provider_conf) in conf.providers.iter() {
+            let client = HttpClient::new(&conf.merge(provider_conf), bind)
+                .map_err(|e| format!("[http] Invalid HTTP config for provider {}. ({})", name, e))?;
+            providers.insert(name.to_owned(), client);
+        }
+
+        Ok(HttpClient {
+            client: build_client(conf, bind)?,
+            retries: conf.retries.unwrap_or(DEFAULT_RETRIES),
+            retry_backoff: Duration::from_millis(conf.retry_backoff.unwrap_or(DEFAULT_RETRY_BACKOFF)),
+            rate_limit: conf.rate_limit,
+            rate_limit_burst: conf.rate_limit_burst.unwrap_or(1),
+            provider: None,
+            providers,
+        })
+    }
+
+    /// Get the client for the provider, it is the client itself if the provider has no override.
+    pub fn for_provider(&self, name: &str) -> HttpClient {
+        let mut client = match self.providers.get(name) {
+            Some(client) => client.to_owned(),
+            None => self.to_owned(),
+        };
+        client.provider = Some(name.to_owned());
+        client
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn put<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.put(url)
+    }
+
+    pub fn delete<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.delete(url)
+    }
+
+    pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    /// Build and send the request, retry it for transient errors.
+    pub async fn send(&self, builder: RequestBuilder) -> Result<Response, DwdError> {
+        let request = builder.build().map_err(|e| self.with_context(e.into()))?;
+        self.execute(request).await
+    }
+
+    /// Send the request, retry it for transient errors.
+    ///
+    /// Connection failures are always retried, while timeouts and 5xx responses are retried only for idempotent
+    /// methods, so a record will not be created twice. 429 responses are retried after the time told by
+    /// `Retry-After`, an `ErrorKind::RateLimit` error is returned if it is too long to wait.
+    pub async fn execute(&self, request: Request) -> Result<Response, DwdError> {
+        self.execute_with_retry(request).await.map_err(|e| self.with_context(e))
+    }
+
+    async fn execute_with_retry(&self, request: Request) -> Result<Response, DwdError> {
+        let mut request = request;
+        let mut backoff = self.retry_backoff;
+        let mut attempt = 0;
+        loop {
+            self.wait_for_rate_limit().await?;
+
+            let retry_request = if attempt < self.retries {
+                request.try_clone()
+            } else {
+                None
+            };
+            let idempotent = is_idempotent(request.method());
+
+            let ret = self.client.execute(request).await;
+            let mut delay = backoff;
+            let reason = match ret.as_ref() {
+                Err(err) if err.is_connect() => err.to_string(),
+                Err(err) if err.is_timeout() && idempotent => err.to_string(),
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    delay = rate_limit::retry_after(response.headers()).unwrap_or(backoff);
+                    self.block(delay);
+                    if delay > MAX_RATE_LIMIT_WAIT || retry_request.is_none() {
+                        return Err(self.rate_limited(delay));
+                    }
+                    response.status().to_string()
+                }
+                Ok(response) => {
+                    if let Some(reset) = rate_limit::quota_reset(response.headers()) {
+                        debug!(
+                            "The quota of {:?} is used up, reset in {}s.",
+                            self.provider,
+                            reset.as_secs()
+                        );
+                        self.block(reset);
+                    }
+                    if is_transient_status(response.status()) && idempotent {
+                        response.status().to_string()
+                    } else {
+                        return Ok(ret?);
+                    }
+                }
+                _ => return Ok(ret?),
+            };
+
+            request = match retry_request {
+                Some(val) => val,
+                None => return Ok(ret?),
+            };
+            attempt += 1;
+            debug!(
+                "{} {} failed: {}, retry {}/{} after {}ms ...",
+                request.method(),
+                request.url(),
+                warn_style(reason),
+                attempt,
+                self.retries,
+                delay.as_millis()
+            );
+            time::sleep(delay).await;
+            backoff *= 2;
+        }
+    }
+
+    /// Wait for a token of the rate limiter, give up if the provider is blocked for too long.
+    async fn wait_for_rate_limit(&self) -> Result<(), DwdError> {
+        let provider = match self.provider.as_ref() {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+
+        while let Some(wait) = rate_limit::acquire(provider, self.rate_limit, self.rate_limit_burst) {
+            if wait > MAX_RATE_LIMIT_WAIT {
+                return Err(self.rate_limited(wait));
+            }
+            trace!("Waiting {}ms for the rate limit of {} ...", wait.as_millis(), provider);
+            time::sleep(wait).await;
+        }
+
+        Ok(())
+    }
+
+    /// Block the provider for the duration, e.g. when its response asks not to retry for a while.
+    pub fn block(&self, duration: Duration) {
+        if let Some(provider) = self.provider.as_ref() {
+            rate_limit::block(provider, duration);
+        }
+    }
+
+    fn rate_limited(&self, retry_after: Duration) -> DwdError {
+        DwdError::rate_limited(retry_after)
+    }
+
+    fn with_context(&self, err: DwdError) -> DwdError {
+        match self.provider.as_ref() {
+            Some(provider) => err.with_provider(provider),
+            None => err,
+        }
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    )
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Build a reqwest client or reuse the one built with the same configs.
+fn build_client(conf: &ConfigHttp, bind: &ConfigBind) -> Result<Client, Box<dyn Error>> {
+    let key = format!("{:?}", (&conf.merge(&ConfigHttp::default()), bind));
+    let mut clients = CLIENTS
+        .lock()
+        .expect("The lock of HTTP clients should not be poisoned.");
+    if let Some(client) = clients.get(&key) {
+        return Ok(client.to_owned());
+    }
+
+    let mut builder = Client::builder()
+        .connect_timeout(Duration::from_secs(
+            conf.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+        ))
+        .timeout(Duration::from_secs(conf.timeout.unwrap_or(DEFAULT_TIMEOUT)))
+        .user_agent(conf.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT));
+    if let Some(proxy) = conf.proxy.as_ref() {
+        let proxy = Proxy::all(proxy).map_err(|e| format!("Invalid proxy {}. (error: {})", proxy, e))?;
+        builder = builder.proxy(proxy);
+    }
+    if let Some(address) = bind.bind_address {
+        builder = builder.local_address(address);
+    }
+    if let Some(interface) = bind.bind_interface.as_ref() {
+        #[cfg(target_os = "linux")]
+        {
+            builder = builder.interface(interface);
+        }
+        #[cfg(not(target_os = "linux"))]
+        return Err(format!("Binding to interface {} is only supported on Linux.", interface).into());
+    }
+
+    let client = builder
+        .build()
+        .map_err(|e| format!("[http::build_client] Build HTTP client failed. (error: {})", e))?;
+    trace!("Built a new HTTP client: {}", key);
+    clients.insert(key, client.to_owned());
+
+    Ok(client)
+}
+
+pub async fn get<T: IntoUrl>(client: &HttpClient, url: T) -> Result<String, DwdError> {
+    let response = client.send(client.get(url)).await?;
+
+    trace!("GET {} {}", response.url(), response.status());
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(client.with_context(DwdError::from_status(
+            status,
+            format!("Unexpected response status {}", status),
+        )));
+    }
+
+    response.text().await.map_err(|e| client.with_context(e.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::mock_server::{MockResponse, MockServer};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn merge_should_prefer_override() {
+        let conf = ConfigHttp {
+            timeout: Some(10),
+            proxy: Some(String::from("socks5://127.0.0.1:1080")),
+            ..Default::default()
+        };
+        let merged = conf.merge(&ConfigHttp {
+            timeout: Some(60),
+            retries: Some(5),
+            ..Default::default()
+        });
+
+        assert_eq!(merged.timeout, Some(60));
+        assert_eq!(merged.retries, Some(5));
+        assert_eq!(merged.proxy, Some(String::from("socks5://127.0.0.1:1080")));
+    }
+
+    #[test]
+    fn new_should_fail_for_invalid_provider_override() {
+        let mut conf = ConfigHttp::default();
+        conf.providers.insert(
+            String::from("name.com"),
+            ConfigHttp {
+                proxy: Some(String::from("not a proxy")),
+                ..Default::default()
+            },
+        );
+
+        assert!(HttpClient::new(&conf, &ConfigBind::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn send_should_retry_transient_errors() {
+        let count = Arc::new(AtomicU32::new(0));
+        let counter = count.clone();
+        let server = MockServer::start(move |_| match counter.fetch_add(1, Ordering::SeqCst) {
+            0 => MockResponse::new(503, "busy"),
+            _ => MockResponse::new(200, "1.1.1.1"),
+        })
+        .await;
+
+        let conf = ConfigHttp {
+            retry_backoff: Some(1),
+            ..Default::default()
+        };
+        let client = HttpClient::new(&conf, &ConfigBind::default()).unwrap();
+
+        let ret = get(&client, &server.url).await;
+        assert_eq!(ret.unwrap(), "1.1.1.1");
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn send_should_not_retry_non_idempotent_requests() {
+        let server = MockServer::start(|_| MockResponse::new(503, "busy")).await;
+
+        let conf = ConfigHttp {
+            retry_backoff: Some(1),
+            ..Default::default()
+        };
+        let client = HttpClient::new(&conf, &ConfigBind::default()).unwrap();
+
+        let response = client.send(client.post(&server.url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn send_should_honor_retry_after() {
+        let count = Arc::new(AtomicU32::new(0));
+        let counter = count.clone();
+        let server = MockServer::start(move |_| match counter.fetch_add(1, Ordering::SeqCst) {
+            0 => MockResponse::new(429, "slow down").with_header("Retry-After", "0"),
+            1 => MockResponse::new(200, "ok"),
+            _ => MockResponse::new(429, "slow down").with_header("Retry-After", "3600"),
+        })
+        .await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default())
+            .unwrap()
+            .for_provider("retry-after.test");
+
+        let response = client.send(client.post(&server.url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let err = client.send(client.get(&server.url)).await.unwrap_err();
+        assert_eq!(err.kind, ErrorKind::RateLimit);
+        assert_eq!(err.provider.as_deref(), Some("retry-after.test"));
+        // The provider is blocked, so no more request is sent.
+        let ret = client.send(client.get(&server.url)).await;
+        assert_eq!(ret.unwrap_err().kind, ErrorKind::RateLimit);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn send_should_use_configured_user_agent() {
+        let server = MockServer::start(|_| MockResponse::new(200, "")).await;
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        client.send(client.get(&server.url)).await.unwrap();
+        let conf = ConfigHttp {
+            user_agent: Some(String::from("my-agent")),
+            ..Default::default()
+        };
+        let client = HttpClient::new(&conf, &ConfigBind::default()).unwrap();
+        client.send(client.get(&server.url)).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].headers["user-agent"], DEFAULT_USER_AGENT);
+        assert_eq!(requests[1].headers["user-agent"], "my-agent");
+    }
+}
@@ -0,0 +1,194 @@
+//! A minimal HTTP/1.1 server for the metrics endpoint and the control API, one request per connection.
+
+use log::{trace, warn};
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    time,
+};
+
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+const MAX_BODY_SIZE: usize = 64 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// Wait a while after accepting failed, e.g. too many open files, so the loop does not spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub method: String,
+    /// The path without query string.
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// The header names are in lowercase.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn text(status: u16, body: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.to_owned(),
+        }
+    }
+
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+}
+
+/// Accept connections and answer them with the handler until the listener fails.
+pub async fn serve<F, Fut>(listener: TcpListener, handler: F)
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    let handler = Arc::new(handler);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(val) => val,
+            Err(err) => {
+                warn!("Accept connection failed: {}", err);
+                time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            let response = match time::timeout(READ_TIMEOUT, read_request(&mut reader)).await {
+                Ok(Ok(request)) => {
+                    trace!("{} {} from {}", request.method, request.path, peer);
+                    handler(request).await
+                }
+                Ok(Err(reason)) => Response::text(400, reason),
+                Err(_) => Response::text(408, "Request timeout"),
+            };
+
+            let head = format!(
+                "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                response.status,
+                reason_phrase(response.status),
+                response.content_type,
+                response.body.len()
+            );
+            let _ = writer.write_all(head.as_bytes()).await;
+            let _ = writer.write_all(response.body.as_bytes()).await;
+            let _ = writer.shutdown().await;
+        });
+    }
+}
+
+async fn read_request<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Result<Request, &'static str> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.map_err(|_| "Bad request")?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or("Bad request")?.to_owned();
+    let target = parts.next().ok_or("Bad request")?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_owned(), parse_query(query)),
+        None => (target.to_owned(), HashMap::new()),
+    };
+
+    let mut headers = HashMap::new();
+    let mut header_size = 0;
+    loop {
+        let mut line = String::new();
+        let size = reader.read_line(&mut line).await.map_err(|_| "Bad request")?;
+        header_size += size;
+        if size == 0 || header_size > MAX_HEADER_SIZE {
+            return Err("Bad request");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_owned());
+        }
+    }
+
+    let length = headers
+        .get("content-length")
+        .and_then(|val| val.parse::<usize>().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY_SIZE {
+        return Err("Payload too large");
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await.map_err(|_| "Bad request")?;
+
+    Ok(Request {
+        method,
+        path,
+        query,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    url::form_urlencoded::parse(query.as_bytes()).into_owned().collect()
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serve_should_answer_with_handler() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, |request: Request| async move {
+            Response::text(
+                200,
+                &format!(
+                    "{} {} {:?} {}",
+                    request.method,
+                    request.path,
+                    request.query.get("force"),
+                    request.body
+                ),
+            )
+        }));
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/update?force=true", url))
+            .body("hello")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "POST /update Some(\"true\") hello");
+    }
+}
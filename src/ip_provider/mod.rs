@@ -3,9 +3,14 @@
 //! At last don't forget to add it to the `fn get_ip` below.
 use log::{debug, error};
 use simple_error::SimpleError;
-use std::convert::TryFrom;
+use std::{convert::TryFrom, time::Instant};
 
-use super::util::{error_style, info_style};
+use super::control;
+use super::error::DwdError;
+use super::http::HttpClient;
+use super::metrics;
+use super::supervisor;
+use super::util::{error_style, info_style, is_ip};
 
 mod myip_ipip_net;
 mod myip_la;
@@ -33,7 +38,7 @@ impl TryFrom<&str> for IpProvider {
 }
 
 /// Get public IP from different provider.
-pub async fn get_ip_by_fallback(providers: &[String]) -> Option<(String, String)> {
+pub async fn get_ip_by_fallback(client: &HttpClient, providers: &[String]) -> Option<(String, String)> {
     debug!("Requesting {} for public IP ...", info_style(providers.join(", ")));
 
     let mut ret = None;
@@ -46,10 +51,37 @@ pub async fn get_ip_by_fallback(providers: &[String]) -> Option<(String, String)
             }
         };
 
-        match get_ip(provider).await {
-            Err(_) => continue,
-            Ok(ip) => {
-                ret = Some((name.to_owned(), ip));
+        let client = client.for_provider(name);
+        let started_at = Instant::now();
+        let queried = supervisor::catch_panic(&format!("Querying IP provider {}", name), async move {
+            get_ip(&client, provider).await
+        })
+        .await;
+        let succeeded = matches!(queried.as_ref(), Some(Ok(ip)) if is_ip(ip.trim()));
+        metrics::ip_detected(
+            name,
+            if succeeded { "success" } else { "failure" },
+            started_at.elapsed(),
+        );
+        match queried {
+            None => {
+                control::report_ip_provider(name, Some(String::from("The provider panicked.")));
+                continue;
+            }
+            Some(Err(err)) => {
+                let err = err.with_provider(name);
+                control::report_ip_provider(name, Some(err.to_string()));
+                error!(target: "error", event = "detection_failed", provider = name.as_str(); "{}", err);
+                continue;
+            }
+            Some(Ok(ip)) if !is_ip(ip.trim()) => {
+                control::report_ip_provider(name, Some(String::from("Got an invalid IP address.")));
+                error!(target: "error", event = "detection_failed", provider = name.as_str(); "[{}] Got an invalid IP address!", name);
+                continue;
+            }
+            Some(Ok(ip)) => {
+                control::report_ip_provider(name, None);
+                ret = Some((name.to_owned(), ip.trim().to_owned()));
                 break;
             }
         }
@@ -58,18 +90,10 @@ pub async fn get_ip_by_fallback(providers: &[String]) -> Option<(String, String)
     ret
 }
 
-async fn get_ip(provider: IpProvider) -> Result<String, ()> {
-    let ret = match provider {
-        IpProvider::IpipNet => myip_ipip_net::get_ip().await,
-        IpProvider::MyipLa => myip_la::get_ip().await,
-        IpProvider::VnetOne => vnet_one::get_ip().await,
-    };
-
-    match ret {
-        Err(err) => {
-            error!(target: "error", "{}", err);
-            Err(())
-        }
-        Ok(ip) => Ok(ip),
+async fn get_ip(client: &HttpClient, provider: IpProvider) -> Result<String, DwdError> {
+    match provider {
+        IpProvider::IpipNet => myip_ipip_net::get_ip(client).await,
+        IpProvider::MyipLa => myip_la::get_ip(client).await,
+        IpProvider::VnetOne => vnet_one::get_ip(client).await,
     }
 }
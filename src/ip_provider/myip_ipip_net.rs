@@ -1,9 +1,9 @@
 use lazy_static::lazy_static;
 use log::trace;
 use regex::Regex;
-use std::error::Error;
 
-use super::super::util::get;
+use super::super::error::DwdError;
+use super::super::http::{get, HttpClient};
 
 /// Get public IP from https://myip.ipip.net
 ///
@@ -13,18 +13,18 @@ use super::super::util::get;
 /// # Example:
 ///
 /// ```rust
-/// let ip = myip_ipip_net::get_ip().await?;
+/// let ip = myip_ipip_net::get_ip(&client).await?;
 /// ```
-pub async fn get_ip() -> Result<String, Box<dyn Error>> {
-    let response = get("https://myip.ipip.net").await?;
+pub async fn get_ip(client: &HttpClient) -> Result<String, DwdError> {
+    let response = get(client, "https://myip.ipip.net").await?;
 
     lazy_static! {
         static ref RE: Regex = Regex::new(r"IP：((?:\d{1,3}\.){3}\d{1,3})").unwrap();
     }
     let ret = RE.captures(&response);
     if ret.is_none() {
-        trace!("Response content: {}", &response.to_string().trim_end());
-        return Err("Can not capture IP from response".into());
+        trace!("Response content: {}", response.to_string().trim_end());
+        return Err(DwdError::parse("Can not capture IP from response"));
     }
 
     Ok(ret.unwrap().get(1).map_or("".into(), |m| m.as_str().into()))
@@ -37,7 +37,7 @@ mod tests {
 
     #[tokio::test]
     async fn get_ip_should_works() {
-        let ret = get_ip().await;
+        let ret = get_ip(&HttpClient::new(&Default::default(), &Default::default()).unwrap()).await;
         println!("ret = {:?}", ret);
         assert!(ret.is_ok());
         assert!(is_ip(ret.as_ref().unwrap()));
@@ -1,6 +1,5 @@
-use std::error::Error;
-
-use crate::util::get;
+use crate::error::DwdError;
+use crate::http::{get, HttpClient};
 
 /// Get public IP from https://api.myip.la
 ///
@@ -10,10 +9,10 @@ use crate::util::get;
 /// # Example:
 ///
 /// ```rust
-/// let ip = myip_la::get_ip().await?;
+/// let ip = myip_la::get_ip(&client).await?;
 /// ```
-pub async fn get_ip() -> Result<String, Box<dyn Error>> {
-    get("https://api.myip.la").await.map_err(|e| e.into())
+pub async fn get_ip(client: &HttpClient) -> Result<String, DwdError> {
+    get(client, "https://api.myip.la").await
 }
 
 #[cfg(test)]
@@ -23,7 +22,7 @@ mod tests {
 
     #[tokio::test]
     async fn get_ip_should_works() {
-        let ret = get_ip().await;
+        let ret = get_ip(&HttpClient::new(&Default::default(), &Default::default()).unwrap()).await;
         println!("ret = {:?}", ret);
         assert!(ret.is_ok());
         assert!(is_ip(ret.as_ref().unwrap()));
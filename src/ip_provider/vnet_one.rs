@@ -1,6 +1,5 @@
-use std::error::Error;
-
-use crate::util::get;
+use crate::error::DwdError;
+use crate::http::{get, HttpClient};
 
 /// Get public IP from https://ip.vnet.one/check.php
 ///
@@ -9,10 +8,10 @@ use crate::util::get;
 /// # Example:
 ///
 /// ```rust
-/// let ip = vnet_one::get_ip().await?;
+/// let ip = vnet_one::get_ip(&client).await?;
 /// ```
-pub async fn get_ip() -> Result<String, Box<dyn Error>> {
-    get("https://ip.vnet.one/check.php").await.map_err(|e| e.into())
+pub async fn get_ip(client: &HttpClient) -> Result<String, DwdError> {
+    get(client, "https://ip.vnet.one/check.php").await
 }
 
 #[cfg(test)]
@@ -22,7 +21,7 @@ mod tests {
 
     #[tokio::test]
     async fn get_ip_should_works() {
-        let ret = get_ip().await;
+        let ret = get_ip(&HttpClient::new(&Default::default(), &Default::default()).unwrap()).await;
         println!("ret = {:?}", ret);
         assert!(ret.is_ok());
         assert!(is_ip(ret.as_ref().unwrap()));
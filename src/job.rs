@@ -0,0 +1,315 @@
+//! A job detects the public IP through one uplink and publishes it to the DNS providers, the default job and each
+//! configured WAN runs as a job.
+
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{sync::watch, time};
+
+use super::control;
+use super::dns_provider::{self, ConfigDnsTarget};
+use super::hooks::{self, Context, Hook};
+use super::http::HttpClient;
+use super::ip_provider;
+use super::metrics;
+use super::mqtt;
+use super::notify::{self, Event, EventKind};
+use super::policy::{Decision, Policy};
+use super::round_robin::WanIps;
+use super::shutdown;
+use super::state;
+use super::systemd;
+use super::util::{self, emoji, error_style, info_style, success_style, warn_style};
+
+#[derive(Clone)]
+pub struct Job {
+    /// The name of WAN, it is `None` for the default job.
+    pub name: Option<String>,
+    pub ip_provider: Vec<String>,
+    pub interval: u32,
+    pub allow_non_global: bool,
+    pub policy: Policy,
+    /// The client which is bound to the uplink of this job.
+    pub client: HttpClient,
+    pub bind_address: Option<IpAddr>,
+    pub dns: ConfigDnsTarget,
+    /// Report the IP of the WAN for multi-value records.
+    pub wan_ips: Option<watch::Sender<WanIps>>,
+}
+
+impl Job {
+    fn label(&self) -> String {
+        match self.name.as_ref() {
+            Some(name) => format!("[{}] ", name),
+            None => String::new(),
+        }
+    }
+
+    /// The key of the job in the state file.
+    fn state_key(&self) -> &str {
+        self.name.as_deref().unwrap_or("default")
+    }
+
+    /// Report the current IP of the WAN, `None` means the WAN is unhealthy.
+    fn report(&self, ip: Option<IpAddr>) {
+        control::report_job(self.state_key(), ip);
+        if let Some(ip) = ip {
+            mqtt::report_ip(self.state_key(), ip);
+        }
+        if let (Some(name), Some(wan_ips)) = (self.name.as_ref(), self.wan_ips.as_ref()) {
+            wan_ips.send_if_modified(|wan_ips| wan_ips.insert(name.to_owned(), ip) != Some(ip));
+        }
+    }
+
+    /// Tell the world the changed IP is published, through metrics, notifications and the hook.
+    fn ip_changed(&self, previous: IpAddr, ip: IpAddr) {
+        metrics::ip_changed(self.state_key());
+        let event = Event::new(EventKind::IpChanged)
+            .with_wan(self.state_key())
+            .with_record(Some(&dns_provider::record_names(&self.dns).join(", ")))
+            .with_ips(Some(previous), Some(ip));
+        hooks::run(Hook::IpChange, Context::from(&event));
+        notify::send(event);
+    }
+}
+
+pub async fn run(mut job: Job) {
+    let targets = dns_provider::target_keys(&job.dns);
+    let mut last_updated_ip: Option<IpAddr> = state::published(job.state_key(), &targets);
+    let mut last_updated_at: Option<SystemTime> = None;
+    let mut last_nat_warning: Option<String> = None;
+
+    let mut timer = time::interval(Duration::from_secs(job.interval as u64));
+    let mut trigger = control::subscribe();
+    let mut cycled = false;
+    systemd::loop_started(&format!("job {}", job.state_key()), job.interval);
+    loop {
+        if cycled {
+            systemd::cycle_done(&format!("job {}", job.state_key()), job.interval);
+        }
+        cycled = true;
+
+        // Stop between rounds, so an in-flight update is never interrupted.
+        let force = tokio::select! {
+            _ = timer.tick() => false,
+            Some(force) = trigger.changed() => force,
+            _ = shutdown::wait() => return,
+        };
+        if control::is_paused() {
+            debug!("{}Updates are paused, skip.", job.label());
+            continue;
+        }
+
+        let started_at = SystemTime::now();
+        let ret = ip_provider::get_ip_by_fallback(&job.client, &job.ip_provider).await;
+        let duration = SystemTime::now()
+            .duration_since(started_at)
+            .expect("Clock may have gone backwards");
+
+        let (provider_name, ip) = match ret {
+            None => {
+                notify::send(
+                    Event::new(EventKind::DetectionFailed)
+                        .with_wan(job.state_key())
+                        .with_error("All IP providers failed."),
+                );
+                job.report(None);
+                continue;
+            }
+            Some(val) => val,
+        };
+
+        let ip = match ip.parse::<IpAddr>() {
+            Ok(val) => val,
+            Err(_) => {
+                error!(target: "error", event = "detection_failed", wan = job.state_key(); "{}Got an invalid IP address!", job.label());
+                notify::send(
+                    Event::new(EventKind::DetectionFailed)
+                        .with_wan(job.state_key())
+                        .with_provider(&provider_name)
+                        .with_error("Got an invalid IP address."),
+                );
+                job.report(None);
+                continue;
+            }
+        };
+
+        info!(
+            target: "success",
+            event = "ip_detected",
+            wan = job.state_key(),
+            provider = provider_name.as_str(),
+            ip:% = ip,
+            duration_ms = duration.as_millis() as u64;
+            "{}[{}] Successfully got current public IP: {} (in {}ms)",
+            job.label(),
+            provider_name,
+            success_style(&ip),
+            info_style(duration.as_millis())
+        );
+
+        metrics::current_ip(job.state_key(), &ip);
+
+        let scope = util::ip_scope(&ip);
+        if scope != util::IpScope::Global {
+            if job.allow_non_global {
+                warn!(
+                    "{}The IP {} is a {} address, publish it as allow_non_global is on.",
+                    job.label(),
+                    ip,
+                    scope
+                );
+            } else {
+                error!(
+                    target: "error",
+                    event = "ip_rejected",
+                    wan = job.state_key(),
+                    ip:% = ip;
+                    "{}[{}] The IP {} is a {} address, refuse to publish it. (set allow_non_global to override)",
+                    job.label(),
+                    provider_name,
+                    error_style(&ip),
+                    scope
+                );
+                job.report(None);
+                continue;
+            }
+        }
+
+        // The bind address of the other family tells nothing about the NAT of this IP.
+        let nat_warning = job
+            .bind_address
+            .filter(|local| local.is_ipv4() == ip.is_ipv4())
+            .or_else(|| util::local_ip_for(&ip))
+            .and_then(|local| util::detect_nat(&local, &ip));
+        if let Some(warning) = nat_warning.as_ref().filter(|_| nat_warning != last_nat_warning) {
+            warn!(
+                event = "nat_detected",
+                wan = job.state_key(),
+                ip:% = ip;
+                "{}{}{}",
+                job.label(),
+                emoji("⚠️  "),
+                warn_style(warning)
+            );
+        }
+        last_nat_warning = nat_warning;
+
+        // Let the policy decide whether the IP should be published, it also skips the IP which is the same as
+        // the last updated one.
+        match job.policy.check(&job.client, &ip, last_updated_ip.as_ref()).await {
+            Decision::Publish => (),
+            Decision::Unchanged | Decision::Pending { .. } if force => {
+                info!(
+                    event = "ip_forced",
+                    wan = job.state_key(),
+                    ip:% = ip;
+                    "{}The update is forced, publish {} anyway.",
+                    job.label(),
+                    info_style(&ip)
+                );
+            }
+            Decision::Unchanged => {
+                job.report(Some(ip));
+                let since_last_updated = last_updated_at
+                    .map(|val| {
+                        SystemTime::now()
+                            .duration_since(val)
+                            .expect("Clock may have gone backwards")
+                    })
+                    .unwrap_or_default();
+                info!(
+                    event = "ip_unchanged",
+                    wan = job.state_key(),
+                    ip:% = ip;
+                    "{}No need to update the DNS record, skip.(since_last_updated: {}s)",
+                    job.label(),
+                    since_last_updated.as_secs()
+                );
+                continue;
+            }
+            Decision::Pending { observations, required } => {
+                info!(
+                    event = "ip_pending",
+                    wan = job.state_key(),
+                    ip:% = ip;
+                    "{}The IP {} has been observed {}/{} times, wait for more confirmations.",
+                    job.label(),
+                    info_style(&ip),
+                    observations,
+                    required
+                );
+                continue;
+            }
+            Decision::Rejected(reason) => {
+                warn!(
+                    event = "ip_rejected",
+                    wan = job.state_key(),
+                    ip:% = ip;
+                    "{}[{}] The IP {} is rejected by policy: {}",
+                    job.label(),
+                    provider_name,
+                    warn_style(&ip),
+                    reason
+                );
+                // Keep the last reported IP, a rejected reading does not mean the WAN is down.
+                continue;
+            }
+        }
+
+        job.report(Some(ip));
+        if job.dns.dns_provider.is_empty() {
+            systemd::status(&format!("{}Current IP: {}", job.label(), ip));
+            job.policy.published(&ip, Instant::now());
+            state::set_published(job.state_key(), ip, targets.clone());
+            mqtt::report_published(job.state_key(), Utc::now());
+            if let Some(previous) = last_updated_ip.replace(ip).filter(|prev| *prev != ip) {
+                job.ip_changed(previous, ip);
+            }
+            last_updated_at = Some(SystemTime::now());
+            continue;
+        }
+
+        let started_at = SystemTime::now();
+        let settled = dns_provider::update_dns_for_all(&job.dns, &job.client, &ip).await;
+        let duration = SystemTime::now()
+            .duration_since(started_at)
+            .expect("Clock may have gone backwards");
+
+        if !settled {
+            warn!(
+                event = "update_incomplete",
+                wan = job.state_key(),
+                ip:% = ip;
+                "{}Some DNS providers failed with transient errors, will retry in the next round.",
+                job.label()
+            );
+            continue;
+        }
+
+        // Save IP and SystemTime when DNS update succeeds.
+        systemd::status(&format!("{}Published IP: {}", job.label(), ip));
+        job.policy.published(&ip, Instant::now());
+        // A target which failed with a permanent error is updated again after restart.
+        state::set_published(job.state_key(), ip, dns_provider::updated_target_keys(&job.dns));
+        mqtt::report_published(job.state_key(), Utc::now());
+        if let Some(previous) = last_updated_ip.replace(ip).filter(|prev| *prev != ip) {
+            job.ip_changed(previous, ip);
+        }
+        last_updated_at = Some(SystemTime::now());
+
+        info!(
+            event = "ip_published",
+            wan = job.state_key(),
+            ip:% = ip,
+            duration_ms = duration.as_millis() as u64;
+            "{}The DNS record updated in {}ms {}",
+            job.label(),
+            info_style(duration.as_millis()),
+            emoji("🕐")
+        );
+    }
+}
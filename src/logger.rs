@@ -0,0 +1,105 @@
+//! The output formats of logs.
+//!
+//! The text format is for humans, while the JSON format emits one object per line for log collectors like Loki and
+//! Elasticsearch. The key-values of a record, e.g. `event`, `provider`, `record`, `ip` and `duration_ms`, become the
+//! fields of the object.
+
+use chrono::{DateTime, Local};
+use clap::ArgEnum;
+use log::{kv, Record};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ArgEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Switch the format, it could be changed after the logger is initialized, e.g. by the config file.
+pub fn set_format(format: LogFormat) {
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+pub fn format() -> LogFormat {
+    if JSON.load(Ordering::Relaxed) {
+        LogFormat::Json
+    } else {
+        LogFormat::Text
+    }
+}
+
+/// Format the record as a JSON object in one line.
+pub fn json_line(record: &Record, now: DateTime<Local>) -> String {
+    let mut object = Map::new();
+    object.insert(String::from("timestamp"), Value::from(now.to_rfc3339()));
+    object.insert(String::from("level"), Value::from(record.level().as_str()));
+    object.insert(String::from("target"), Value::from(record.target()));
+    object.insert(
+        String::from("message"),
+        Value::from(console::strip_ansi_codes(&record.args().to_string())),
+    );
+
+    struct Fields<'a>(&'a mut Map<String, Value>);
+    impl<'kvs> kv::VisitSource<'kvs> for Fields<'_> {
+        fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+            let value = if let Some(val) = value.to_u64() {
+                Value::from(val)
+            } else if let Some(val) = value.to_i64() {
+                Value::from(val)
+            } else if let Some(val) = value.to_bool() {
+                Value::from(val)
+            } else {
+                Value::from(console::strip_ansi_codes(&value.to_string()))
+            };
+            self.0.insert(key.as_str().to_owned(), value);
+            Ok(())
+        }
+    }
+    let _ = record.key_values().visit(&mut Fields(&mut object));
+
+    Value::Object(object).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn json_line_should_contain_fields() {
+        let kvs: &[(&str, &dyn kv::ToValue)] =
+            &[("event", &"ip_detected"), ("ip", &"1.1.1.1"), ("duration_ms", &12u64)];
+        let now = Local::now();
+        let line = json_line(
+            &Record::builder()
+                .level(Level::Info)
+                .target("success")
+                .key_values(&kvs)
+                .args(format_args!(
+                    "Got IP {}",
+                    console::style("1.1.1.1").red().force_styling(true)
+                ))
+                .build(),
+            now,
+        );
+
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "timestamp": now.to_rfc3339(),
+                "level": "INFO",
+                "target": "success",
+                "message": "Got IP 1.1.1.1",
+                "event": "ip_detected",
+                "ip": "1.1.1.1",
+                "duration_ms": 12,
+            })
+        );
+    }
+}
@@ -1,22 +1,47 @@
 use chrono::Local;
 use clap::Parser;
-use console::Emoji;
 use dotenv::dotenv;
-use log::{debug, error, info, trace, Level, LevelFilter};
-use std::{
-    io::Write,
-    process,
-    time::{Duration, SystemTime},
-};
-use tokio::{task, time};
+use log::{debug, error, info, trace, warn, Level, LevelFilter};
+use std::{env, io::Write, process, time::Duration};
+use tokio::{net, sync::watch, task, time};
 
-use util::{debug_style, error_style, info_style, success_style, warn_style};
+use config::Config;
+use dns_provider::ConfigDnsTarget;
+use error::ErrorKind;
+use http::{ConfigBind, HttpClient};
+use job::Job;
+use logger::LogFormat;
+use policy::Policy;
+use round_robin::WanIps;
+use util::{debug_style, emoji, error_style, info_style, success_style, warn_style};
 
 mod config;
+mod control;
 mod dns_provider;
+mod error;
+mod hooks;
+mod http;
+mod http_server;
 mod ip_provider;
+mod job;
+mod logger;
+mod metrics;
+#[cfg(test)]
+mod mock_server;
+mod mqtt;
+mod notify;
+mod policy;
+mod prefix_delegation;
+mod rate_limit;
+mod round_robin;
+mod shutdown;
+mod state;
+mod supervisor;
+mod systemd;
 mod util;
 
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 10;
+
 #[derive(Parser, Debug)]
 #[clap(author, version)]
 struct Options {
@@ -34,6 +59,12 @@ struct Options {
         help = "The level of log verbosity."
     )]
     verbose: u32,
+    #[clap(
+        long = "log-format",
+        arg_enum,
+        help = "The format of logs, it overrides log_format in the config file. [default: text]"
+    )]
+    log_format: Option<LogFormat>,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -49,83 +80,230 @@ async fn main() {
         Ok(val) => val,
         Err(err) => {
             error!(target: "error", "{}", err);
-            process::exit(1);
+            process::exit(ErrorKind::Config.exit_code());
         }
     };
+    if let (None, Some(log_format)) = (options.log_format, conf.log_format) {
+        set_log_format(log_format);
+    }
 
-    info!("DDNS with DNS has started {}", Emoji("✨", ""));
-    debug!(
-        "Will request public IP from [{}] every {} seconds and update to [{}].",
-        info_style(&conf.ip_provider.join(", ")),
-        info_style(&conf.interval),
-        info_style(&conf.dns_provider.join(", "))
-    );
+    if let Some(state_file) = conf.state_file.as_ref() {
+        if let Err(err) = state::load(state_file) {
+            error!(target: "error", "{}", err);
+            process::exit(ErrorKind::Config.exit_code());
+        }
+    }
 
-    let handle = task::spawn(async move {
-        let mut last_updated_ip: Option<String> = None;
-        let mut last_updated_at: Option<SystemTime> = None;
+    if let Some(metrics_conf) = conf.metrics.as_ref() {
+        let listener = net::TcpListener::bind(&metrics_conf.listen)
+            .await
+            .unwrap_or_else(|err| {
+                error!(target: "error", "Listen on {} for metrics failed: {}", metrics_conf.listen, err);
+                process::exit(ErrorKind::Config.exit_code());
+            });
+        debug!("Serving metrics on http://{}/metrics", info_style(&metrics_conf.listen));
+        task::spawn(metrics::serve(listener));
+    }
 
-        let mut timer = time::interval(Duration::from_secs(conf.interval as u64));
-        loop {
-            timer.tick().await;
+    if let Some(control_conf) = conf.control.as_ref() {
+        let token = control_conf.token().unwrap_or_else(|err| {
+            error!(target: "error", "{}", err);
+            process::exit(ErrorKind::Config.exit_code());
+        });
+        let listener = net::TcpListener::bind(&control_conf.listen)
+            .await
+            .unwrap_or_else(|err| {
+                error!(target: "error", "Listen on {} for control API failed: {}", control_conf.listen, err);
+                process::exit(ErrorKind::Config.exit_code());
+            });
+        debug!("Serving control API on http://{}", info_style(&control_conf.listen));
+        task::spawn(control::serve(listener, token));
+    }
 
-            let started_at = SystemTime::now();
-            let ret = ip_provider::get_ip_by_fallback(&conf.ip_provider).await;
-            let duration = SystemTime::now()
-                .duration_since(started_at)
-                .expect("Clock may have gone backwards");
+    let mut digest = None;
+    if !conf.notify.notifier.is_empty() {
+        let client = HttpClient::new(&conf.http, &conf.bind).unwrap_or_else(|err| {
+            error!(target: "error", "{}", err);
+            process::exit(ErrorKind::Config.exit_code());
+        });
+        debug!(
+            "Will send notifications to [{}].",
+            info_style(conf.notify.notifier.join(", "))
+        );
+        digest = notify::init(conf.notify.clone(), client).unwrap_or_else(|err| {
+            error!(target: "error", "{}", err);
+            process::exit(ErrorKind::Config.exit_code());
+        });
+    }
 
-            let (provider_name, ip) = match ret {
-                None => continue,
-                Some(val) => val,
-            };
+    let mqtt_eventloop = conf.mqtt.as_ref().map(|mqtt_conf| {
+        debug!("Will publish the state to MQTT broker {}.", info_style(&mqtt_conf.host));
+        mqtt::init(mqtt_conf).unwrap_or_else(|err| {
+            error!(target: "error", "{}", err);
+            process::exit(ErrorKind::Config.exit_code());
+        })
+    });
 
-            if !util::is_ip(&ip) {
-                error!(target: "error", "Got an invalid IP address!");
-                continue;
-            } else {
-                info!(
-                    target: "success",
-                    "[{}] Successfully got current public IP: {} (in {}ms)",
-                    provider_name,
-                    success_style(&ip),
-                    info_style(duration.as_millis())
-                );
-            }
+    if !conf.hooks.is_empty() {
+        debug!("Will run hook commands after IP changes and DNS updates.");
+        hooks::init(conf.hooks.clone());
+    }
 
-            // If the last IP update is the same as the current IP and the update cycle has not yet been reached,
-            // then skip.
-            if last_updated_ip.is_some() && last_updated_at.is_some() {
-                let since_last_updated = SystemTime::now()
-                    .duration_since(last_updated_at.to_owned().unwrap())
-                    .expect("Clock may have gone backwards");
-                if last_updated_ip.as_ref().unwrap() == &ip {
-                    info!(
-                        "No need to update the DNS record, skip.(since_last_updated: {}s)",
-                        since_last_updated.as_secs()
-                    );
-                    continue;
-                }
-            }
+    info!(event = "started"; "DDNS with DNS has started {}", emoji("✨"));
+
+    let mut handles = Vec::new();
+    if let Some(pd_conf) = conf.prefix_delegation.clone() {
+        debug!(
+            "Will publish addresses of {} hosts under the delegated IPv6 prefix.",
+            info_style(pd_conf.hosts.len())
+        );
+        let client = HttpClient::new(&conf.http, &conf.bind).unwrap_or_else(|err| {
+            error!(target: "error", "{}", err);
+            process::exit(ErrorKind::Config.exit_code());
+        });
+        let (ip_provider, interval) = (conf.ip_provider.clone(), conf.interval);
+        handles.push(task::spawn(supervisor::supervise(
+            String::from("The prefix delegation task"),
+            move || prefix_delegation::run(pd_conf.clone(), client.clone(), ip_provider.clone(), interval),
+        )));
+    }
 
-            let started_at = SystemTime::now();
-            dns_provider::update_dns_for_all(&conf, &ip).await;
-            let duration = SystemTime::now()
-                .duration_since(started_at)
-                .expect("Clock may have gone backwards");
+    // The WANs report their IPs through this channel, then the round-robin tasks publish them as multi-value records.
+    let (wan_ips, _) = watch::channel(WanIps::new());
+    for rr_conf in conf.round_robin.iter() {
+        debug!(
+            "Will publish IPs of WANs [{}] as multi-value records to [{}].",
+            info_style(rr_conf.wan.join(", ")),
+            info_style(rr_conf.dns.dns_provider.join(", "))
+        );
+        let client = HttpClient::new(&conf.http, &conf.bind).unwrap_or_else(|err| {
+            error!(target: "error", "{}", err);
+            process::exit(ErrorKind::Config.exit_code());
+        });
+        let (rr_conf, wan_ips, interval) = (rr_conf.to_owned(), wan_ips.clone(), conf.interval);
+        handles.push(task::spawn(supervisor::supervise(
+            String::from("The round-robin task"),
+            move || round_robin::run(rr_conf.clone(), client.clone(), wan_ips.subscribe(), interval),
+        )));
+    }
+
+    for wan in conf.wan.iter() {
+        debug!(
+            "Will request public IP of WAN {} from [{}] and update to [{}].",
+            info_style(&wan.name),
+            info_style(&wan.ip_provider.join(", ")),
+            info_style(&wan.dns.dns_provider.join(", "))
+        );
+        let mut job = new_job(&conf, Some(wan.name.to_owned()), &wan.ip_provider, &wan.bind, &wan.dns);
+        job.wan_ips = Some(wan_ips.clone());
+        handles.push(task::spawn(supervisor::supervise(
+            format!("The job of WAN {}", wan.name),
+            move || job::run(job.clone()),
+        )));
+    }
 
-            // Save IP and SystemTime when DNS update succeeds.
-            last_updated_ip = Some(ip);
-            last_updated_at = Some(SystemTime::now());
+    if !conf.dns.dns_provider.is_empty() {
+        debug!(
+            "Will request public IP from [{}] every {} seconds and update to [{}].",
+            info_style(&conf.ip_provider.join(", ")),
+            info_style(&conf.interval),
+            info_style(&conf.dns.dns_provider.join(", "))
+        );
+        let job = new_job(&conf, None, &conf.ip_provider, &conf.bind, &conf.dns);
+        handles.push(task::spawn(supervisor::supervise(
+            String::from("The main job"),
+            move || job::run(job.clone()),
+        )));
+    }
+
+    if handles.is_empty() {
+        error!(target: "error", "Neither dns_provider, wan nor prefix_delegation is configured, nothing to do.");
+        process::exit(ErrorKind::Config.exit_code());
+    }
+    // It is waited on exit, so the offline status is published.
+    if let Some(eventloop) = mqtt_eventloop {
+        handles.push(task::spawn(mqtt::run(eventloop)));
+    }
+    // The pending events of the daily digest are sent on exit.
+    handles.extend(digest);
 
+    // Tell systemd DWD is ready once every job finishes its first cycle.
+    systemd::expect_first_cycles(
+        conf.wan.len() + !conf.dns.dns_provider.is_empty() as usize + conf.prefix_delegation.is_some() as usize,
+    );
+    if let Some(interval) = systemd::watchdog_interval() {
+        debug!("Will ping the systemd watchdog every {}ms.", interval.as_millis());
+        task::spawn(systemd::run_watchdog(interval));
+    }
+
+    // The tasks are supervised, they only return when there is nothing more to do or DWD is shutting down.
+    let tasks = async {
+        for handle in handles {
+            if let Err(err) = handle.await {
+                error!(target: "error", "DWD exits unexpectedly, sorry for that. 💔 ({})", err);
+            }
+        }
+    };
+    tokio::pin!(tasks);
+
+    tokio::select! {
+        _ = &mut tasks => (),
+        signal = shutdown::signal() => {
+            let timeout = conf.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
             info!(
-                "The DNS record updated in {}ms {}",
-                info_style(duration.as_millis()),
-                Emoji("🕐", "")
+                event = "shutdown_requested";
+                "Received {}, waiting up to {}s for in-flight updates, send it again to exit immediately ...",
+                signal, timeout
             );
+            shutdown::trigger();
+            systemd::notify("STOPPING=1");
+
+            tokio::select! {
+                _ = &mut tasks => debug!("All tasks have stopped."),
+                _ = time::sleep(Duration::from_secs(timeout)) => {
+                    warn!("{}", warn_style("Some tasks did not stop in time, exit anyway."))
+                }
+                signal = shutdown::signal() => {
+                    warn!("Received {} during shutdown, exit immediately.", signal);
+                    process::exit(130);
+                }
+            }
         }
-    });
-    handle.await.expect("DWD exits unexpectedly, sorry for that. 💔");
+    }
+
+    if let Some(state_file) = conf.state_file.as_ref() {
+        match state::flush(state_file) {
+            Ok(_) => debug!("Saved state to {}.", state_file),
+            Err(err) => error!(target: "error", "{}", err),
+        }
+    }
+    info!(event = "stopped"; "DDNS with DNS has stopped {}", emoji("👋"));
+}
+
+/// Build a job, exit if any config of it is invalid.
+fn new_job(
+    conf: &Config,
+    name: Option<String>,
+    ip_provider: &[String],
+    bind: &ConfigBind,
+    dns: &ConfigDnsTarget,
+) -> Job {
+    let exit = |err| -> ! {
+        error!(target: "error", "{}", err);
+        process::exit(ErrorKind::Config.exit_code());
+    };
+
+    Job {
+        name,
+        ip_provider: ip_provider.to_vec(),
+        interval: conf.interval,
+        allow_non_global: conf.allow_non_global,
+        policy: Policy::new(&conf.policy).unwrap_or_else(|err| exit(err)),
+        client: HttpClient::new(&conf.http, bind).unwrap_or_else(|err| exit(err)),
+        bind_address: bind.bind_address,
+        dns: dns.to_owned(),
+        wan_ips: None,
+    }
 }
 
 fn init_log(options: &Options) {
@@ -135,12 +313,33 @@ fn init_log(options: &Options) {
         _ => LevelFilter::Info,
     };
 
+    let is_term = console::Term::stderr().is_term();
+    if env::var_os("NO_COLOR").is_some() || !is_term {
+        util::disable_decorations();
+    }
+    if let Some(log_format) = options.log_format {
+        set_log_format(log_format);
+    }
+
+    // Log natively with structured fields when running under journald, the timestamp and level are kept by it.
+    if systemd::is_journald() && options.log_format != Some(LogFormat::Json) {
+        if let Ok(logger) = systemd::JournalLogger::new(level) {
+            log::set_boxed_logger(Box::new(logger)).expect("The logger should be only initialized once.");
+            log::set_max_level(level);
+            return;
+        }
+    }
+
     let mut builder = env_logger::Builder::new();
     builder
         .filter(Some("dwd"), level)
         .filter(Some("error"), level)
         .filter(Some("success"), level)
         .format(|buf, record| {
+            if logger::format() == LogFormat::Json {
+                return writeln!(buf, "{}", logger::json_line(record, Local::now()));
+            }
+
             let mut char = match record.target() {
                 "success" => success_style("✔ "),
                 "error" => error_style("✗ "),
@@ -163,14 +362,21 @@ fn init_log(options: &Options) {
                 Local::now().format("%Y-%m-%d %H:%M:%S"),
                 level,
                 char,
-                &record.args(),
+                record.args(),
             )
         })
         .init();
 
     match options.verbose {
-        2 => trace!("{}{}", Emoji("📃 ", ""), error_style("Log verbosity level: trace")),
-        1 => debug!("{}{}", Emoji("📃 ", ""), warn_style("Log verbosity level: debug")),
-        0 | _ => (),
+        2 => trace!("{}{}", emoji("📃 "), error_style("Log verbosity level: trace")),
+        1 => debug!("{}{}", emoji("📃 "), warn_style("Log verbosity level: debug")),
+        _ => (),
+    }
+}
+
+fn set_log_format(log_format: LogFormat) {
+    logger::set_format(log_format);
+    if log_format == LogFormat::Json {
+        util::disable_decorations();
     }
 }
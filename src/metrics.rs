@@ -0,0 +1,275 @@
+//! Prometheus metrics, they are collected in memory and exposed at `/metrics` in the text exposition format.
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+use tokio::net::TcpListener;
+
+use super::http_server::{self, Request, Response};
+
+/// The buckets of latency histograms in seconds.
+const BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+lazy_static! {
+    static ref REGISTRY: Mutex<BTreeMap<&'static str, Family>> = Mutex::new(BTreeMap::new());
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigMetrics {
+    /// The address to serve `/metrics` on, e.g. `127.0.0.1:9898`.
+    pub listen: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Debug)]
+struct Family {
+    help: &'static str,
+    kind: Kind,
+    series: BTreeMap<Labels, Series>,
+}
+
+#[derive(Debug)]
+enum Series {
+    Value(f64),
+    Histogram { buckets: Vec<u64>, sum: f64, count: u64 },
+}
+
+fn with_family<F>(name: &'static str, help: &'static str, kind: Kind, f: F)
+where
+    F: FnOnce(&mut Family),
+{
+    let mut registry = REGISTRY.lock().expect("The lock of metrics should not be poisoned.");
+    let family = registry.entry(name).or_insert_with(|| Family {
+        help,
+        kind,
+        series: BTreeMap::new(),
+    });
+    f(family);
+}
+
+fn labels(pairs: &[(&'static str, &str)]) -> Labels {
+    pairs.iter().map(|(name, value)| (*name, value.to_string())).collect()
+}
+
+fn inc_counter(name: &'static str, help: &'static str, pairs: &[(&'static str, &str)]) {
+    with_family(name, help, Kind::Counter, |family| {
+        if let Series::Value(val) = family.series.entry(labels(pairs)).or_insert(Series::Value(0.0)) {
+            *val += 1.0;
+        }
+    });
+}
+
+fn set_gauge(name: &'static str, help: &'static str, pairs: &[(&'static str, &str)], value: f64) {
+    with_family(name, help, Kind::Gauge, |family| {
+        family.series.insert(labels(pairs), Series::Value(value));
+    });
+}
+
+fn observe(name: &'static str, help: &'static str, pairs: &[(&'static str, &str)], duration: Duration) {
+    let seconds = duration.as_secs_f64();
+    with_family(name, help, Kind::Histogram, |family| {
+        let series = family.series.entry(labels(pairs)).or_insert(Series::Histogram {
+            buckets: vec![0; BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        });
+        if let Series::Histogram { buckets, sum, count } = series {
+            for (bucket, bound) in buckets.iter_mut().zip(BUCKETS.iter()) {
+                if seconds <= *bound {
+                    *bucket += 1;
+                }
+            }
+            *sum += seconds;
+            *count += 1;
+        }
+    });
+}
+
+fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Clock may have gone backwards")
+        .as_secs_f64()
+}
+
+/// Record an IP detection, the outcome is `success` or `failure`.
+pub fn ip_detected(provider: &str, outcome: &str, duration: Duration) {
+    inc_counter(
+        "dwd_ip_detections_total",
+        "The IP detections by provider and outcome.",
+        &[("provider", provider), ("outcome", outcome)],
+    );
+    observe(
+        "dwd_ip_detection_duration_seconds",
+        "The latency of IP detections.",
+        &[("provider", provider)],
+        duration,
+    );
+}
+
+/// Record a DNS update, the outcome is `success`, `failure` or `rate_limited`.
+pub fn dns_updated(provider: &str, outcome: &str, duration: Duration) {
+    inc_counter(
+        "dwd_dns_updates_total",
+        "The DNS updates by provider and outcome.",
+        &[("provider", provider), ("outcome", outcome)],
+    );
+    observe(
+        "dwd_dns_update_duration_seconds",
+        "The latency of DNS updates.",
+        &[("provider", provider)],
+        duration,
+    );
+    if outcome == "success" {
+        set_gauge(
+            "dwd_last_successful_update_timestamp_seconds",
+            "The unix time of the last successful DNS update.",
+            &[("provider", provider)],
+            unix_time(),
+        );
+    }
+}
+
+/// Record the current IP of a job, the series of the previous IP is removed.
+pub fn current_ip(wan: &str, ip: &IpAddr) {
+    with_family(
+        "dwd_current_ip_info",
+        "The current public IP of each job.",
+        Kind::Gauge,
+        |family| {
+            family
+                .series
+                .retain(|labels, _| !labels.iter().any(|(name, value)| *name == "wan" && value == wan));
+            family
+                .series
+                .insert(labels(&[("wan", wan), ("ip", &ip.to_string())]), Series::Value(1.0));
+        },
+    );
+}
+
+pub fn ip_changed(wan: &str) {
+    inc_counter(
+        "dwd_ip_changes_total",
+        "The times the published IP changed.",
+        &[("wan", wan)],
+    );
+}
+
+/// Render all metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let registry = REGISTRY.lock().expect("The lock of metrics should not be poisoned.");
+    let mut output = String::new();
+    for (name, family) in registry.iter() {
+        let _ = writeln!(output, "# HELP {} {}", name, family.help);
+        let _ = writeln!(output, "# TYPE {} {}", name, family.kind.as_str());
+        for (labels, series) in family.series.iter() {
+            match series {
+                Series::Value(val) => {
+                    let _ = writeln!(output, "{}{} {}", name, format_labels(labels, None), val);
+                }
+                Series::Histogram { buckets, sum, count } => {
+                    for (bucket, bound) in buckets.iter().zip(BUCKETS.iter()) {
+                        let le = bound.to_string();
+                        let _ = writeln!(output, "{}_bucket{} {}", name, format_labels(labels, Some(&le)), bucket);
+                    }
+                    let _ = writeln!(
+                        output,
+                        "{}_bucket{} {}",
+                        name,
+                        format_labels(labels, Some("+Inf")),
+                        count
+                    );
+                    let _ = writeln!(output, "{}_sum{} {}", name, format_labels(labels, None), sum);
+                    let _ = writeln!(output, "{}_count{} {}", name, format_labels(labels, None), count);
+                }
+            }
+        }
+    }
+    output
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<_>>();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Serve `/metrics` on the listener.
+pub async fn serve(listener: TcpListener) {
+    http_server::serve(listener, |request: Request| async move {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => Response {
+                status: 200,
+                content_type: "text/plain; version=0.0.4; charset=utf-8",
+                body: render(),
+            },
+            _ => Response::text(404, "Not found"),
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_should_expose_counters_histograms_and_info() {
+        ip_detected("render.test", "success", Duration::from_millis(300));
+        ip_detected("render.test", "success", Duration::from_secs(100));
+        current_ip("render-wan", &"1.1.1.1".parse().unwrap());
+        current_ip("render-wan", &"2.2.2.2".parse().unwrap());
+
+        let output = render();
+        assert!(output.contains("# TYPE dwd_ip_detections_total counter\n"));
+        assert!(output.contains("dwd_ip_detections_total{provider=\"render.test\",outcome=\"success\"} 2\n"));
+        assert!(output.contains("dwd_ip_detection_duration_seconds_bucket{provider=\"render.test\",le=\"0.25\"} 0\n"));
+        assert!(output.contains("dwd_ip_detection_duration_seconds_bucket{provider=\"render.test\",le=\"0.5\"} 1\n"));
+        assert!(output.contains("dwd_ip_detection_duration_seconds_bucket{provider=\"render.test\",le=\"+Inf\"} 2\n"));
+        assert!(output.contains("dwd_ip_detection_duration_seconds_count{provider=\"render.test\"} 2\n"));
+        assert!(output.contains("dwd_current_ip_info{wan=\"render-wan\",ip=\"2.2.2.2\"} 1\n"));
+        assert!(!output.contains("ip=\"1.1.1.1\""));
+    }
+
+    #[test]
+    fn escape_should_escape_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,141 @@
+//! A tiny HTTP server for testing providers and notifiers offline, it records every request and answers with the
+//! response returned by the handler.
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    /// The path with query string, e.g. `/nic/update?hostname=example.com`.
+    pub path: String,
+    /// The header names are in lowercase.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl MockResponse {
+    pub fn new(status: u16, body: &str) -> Self {
+        MockResponse {
+            status,
+            headers: Vec::new(),
+            body: body.to_owned(),
+        }
+    }
+
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        MockResponse::new(status, &body.to_string()).with_header("Content-Type", "application/json")
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+pub struct MockServer {
+    /// The base URL of the server, like `http://127.0.0.1:12345/`.
+    pub url: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockServer {
+    pub async fn start<F>(handler: F) -> MockServer
+    where
+        F: Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        let handler = Arc::new(handler);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(val) => val,
+                    Err(_) => return,
+                };
+                let recorded = recorded.clone();
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let mut reader = BufReader::new(stream);
+                    let request = match read_request(&mut reader).await {
+                        Some(val) => val,
+                        None => return,
+                    };
+                    let response = handler(&request);
+                    recorded.lock().unwrap().push(request);
+
+                    let mut raw = format!(
+                        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+                        response.status,
+                        response.body.len()
+                    );
+                    for (name, value) in response.headers.iter() {
+                        raw.push_str(&format!("{}: {}\r\n", name, value));
+                    }
+                    raw.push_str("\r\n");
+                    raw.push_str(&response.body);
+                    let _ = reader.get_mut().write_all(raw.as_bytes()).await;
+                    let _ = reader.get_mut().shutdown().await;
+                });
+            }
+        });
+
+        MockServer { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn read_request<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<MockRequest> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let path = parts.next()?.to_owned();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_owned());
+        }
+    }
+
+    let length = headers
+        .get("content-length")
+        .and_then(|val| val.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await.ok()?;
+
+    Some(MockRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
@@ -0,0 +1,505 @@
+//! Publish the state of DWD to an MQTT broker, so home automation like Home Assistant could watch it.
+//!
+//! The state is published as retained messages under `topic_prefix`, and republished after each reconnection in case
+//! the broker lost them. With discovery enabled, Home Assistant creates the sensors and the button automatically:
+//!
+//! - `<prefix>/status`, `online` or `offline`, the broker sends `offline` as the last will if DWD disappears.
+//! - `<prefix>/<wan>/ip` and `<prefix>/<wan>/updated_at`, the current IP and when it was published. `/`, `+` and `#`
+//!   in the WAN name are replaced with `_`.
+//! - `<prefix>/provider/<name>`, the health of the DNS provider as JSON.
+//! - `<prefix>/event`, the last event as JSON, it has the same fields as the webhook.
+//! - `<prefix>/force_update`, publish anything to it to publish the IP immediately.
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use rumqttc::{
+    AsyncClient, Event as MqttEvent, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS, TlsConfiguration,
+    Transport,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::BTreeMap, env, fs, net::IpAddr, sync::Mutex, time::Duration};
+use tokio::time;
+
+use super::control;
+use super::error::DwdError;
+use super::notify::Event;
+use super::shutdown;
+
+const DEFAULT_TOPIC_PREFIX: &str = "dwd";
+const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How many messages could be queued while the connection is busy.
+const CAPACITY: usize = 100;
+
+lazy_static! {
+    static ref MQTT: Mutex<Option<Mqtt>> = Mutex::new(None);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigMqtt {
+    pub host: String,
+    /// 1883 by default, or 8883 with TLS.
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: bool,
+    /// The PEM file of the CA which signs the certificate of the broker, the system CAs are used by default.
+    pub ca_file: Option<String>,
+    pub username: Option<String>,
+    /// The password, or set it through env variable MQTT_PASSWORD.
+    pub password: Option<String>,
+    /// `dwd` by default, it should be unique on the broker.
+    pub client_id: Option<String>,
+    /// The prefix of all topics, `dwd` by default.
+    pub topic_prefix: Option<String>,
+    /// Publish Home Assistant discovery configs, it is on by default.
+    pub discovery: Option<bool>,
+    /// `homeassistant` by default.
+    pub discovery_prefix: Option<String>,
+}
+
+struct Mqtt {
+    client: AsyncClient,
+    prefix: String,
+    /// It is `None` if discovery is disabled.
+    discovery_prefix: Option<String>,
+    /// The retained messages, they are republished after reconnection.
+    retained: BTreeMap<String, String>,
+    connected: bool,
+}
+
+impl Mqtt {
+    fn new(client: AsyncClient, conf: &ConfigMqtt) -> Self {
+        let mut mqtt = Mqtt {
+            client,
+            prefix: conf
+                .topic_prefix
+                .as_deref()
+                .unwrap_or(DEFAULT_TOPIC_PREFIX)
+                .trim_end_matches('/')
+                .to_owned(),
+            discovery_prefix: match conf.discovery {
+                Some(false) => None,
+                _ => Some(
+                    conf.discovery_prefix
+                        .as_deref()
+                        .unwrap_or(DEFAULT_DISCOVERY_PREFIX)
+                        .to_owned(),
+                ),
+            },
+            retained: BTreeMap::new(),
+            connected: false,
+        };
+        mqtt.publish(mqtt.topic("status"), String::from("online"));
+        mqtt.announce(
+            "button",
+            "force_update",
+            json!({
+                "name": "Force update",
+                "command_topic": mqtt.topic("force_update"),
+                "icon": "mdi:refresh",
+            }),
+        );
+        mqtt.announce(
+            "sensor",
+            "last_event",
+            json!({
+                "name": "Last event",
+                "state_topic": mqtt.topic("event"),
+                "value_template": "{{ value_json.event }}",
+                "json_attributes_topic": mqtt.topic("event"),
+                "icon": "mdi:bell",
+            }),
+        );
+        mqtt
+    }
+
+    fn topic(&self, path: &str) -> String {
+        format!("{}/{}", self.prefix, path)
+    }
+
+    /// Publish a retained message, it is only kept if not connected and sent after the connection is established.
+    fn publish(&mut self, topic: String, payload: String) {
+        if self.connected {
+            if let Err(e) = self
+                .client
+                .try_publish(topic.as_str(), QoS::AtLeastOnce, true, payload.as_bytes())
+            {
+                warn!("Publish to MQTT topic {} failed: {}", topic, e);
+            }
+        }
+        self.retained.insert(topic, payload);
+    }
+
+    /// Publish the Home Assistant discovery config of an entity once.
+    fn announce(&mut self, component: &str, object: &str, mut config: serde_json::Value) {
+        let discovery_prefix = match self.discovery_prefix.as_ref() {
+            Some(val) => val,
+            None => return,
+        };
+        let node = object_id(&self.prefix);
+        let object = object_id(object);
+        let topic = format!("{}/{}/{}/{}/config", discovery_prefix, component, node, object);
+        if self.retained.contains_key(&topic) {
+            return;
+        }
+
+        config["unique_id"] = json!(format!("{}_{}", node, object));
+        config["availability_topic"] = json!(self.topic("status"));
+        config["device"] = json!({
+            "identifiers": [node],
+            "name": if self.prefix == DEFAULT_TOPIC_PREFIX { String::from("DWD") } else { format!("DWD {}", self.prefix) },
+            "model": "DDNS with DNS",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        self.publish(topic, config.to_string());
+    }
+
+    fn on_connected(&mut self) {
+        self.connected = true;
+        let command_topic = self.topic("force_update");
+        if let Err(e) = self.client.try_subscribe(command_topic.as_str(), QoS::AtLeastOnce) {
+            warn!("Subscribe to MQTT topic {} failed: {}", command_topic, e);
+        }
+        for (topic, payload) in self.retained.clone() {
+            self.publish(topic, payload);
+        }
+    }
+}
+
+/// Only letters, digits, `_` and `-` are allowed in the IDs of Home Assistant.
+fn object_id(val: &str) -> String {
+    val.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The separator `/` and the wildcards `+` and `#` are not allowed in a level of the topics, e.g. in a WAN name.
+fn topic_level(val: &str) -> String {
+    val.replace(['/', '+', '#'], "_")
+}
+
+fn with_mqtt<F: FnOnce(&mut Mqtt)>(f: F) {
+    if let Some(mqtt) = MQTT.lock().expect("The lock of MQTT should not be poisoned.").as_mut() {
+        f(mqtt);
+    }
+}
+
+/// Set up the client, the connection is established by `run` with the returned event loop.
+pub fn init(conf: &ConfigMqtt) -> Result<EventLoop, DwdError> {
+    let port = conf.port.unwrap_or(if conf.tls { 8883 } else { 1883 });
+    let mut options = MqttOptions::new(conf.client_id.as_deref().unwrap_or("dwd"), &conf.host, port);
+    options.set_keep_alive(KEEP_ALIVE);
+
+    let prefix = conf.topic_prefix.as_deref().unwrap_or(DEFAULT_TOPIC_PREFIX);
+    options.set_last_will(LastWill::new(
+        format!("{}/status", prefix.trim_end_matches('/')),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+
+    if let Some(username) = conf.username.as_ref() {
+        let password = match conf.password.as_ref() {
+            Some(val) => val.to_owned(),
+            None => env::var("MQTT_PASSWORD")
+                .map_err(|_| DwdError::config("Please set mqtt.password or env variable MQTT_PASSWORD."))?,
+        };
+        options.set_credentials(username, password);
+    }
+
+    if conf.tls {
+        let tls = match conf.ca_file.as_ref() {
+            Some(path) => TlsConfiguration::SimpleNative {
+                ca: fs::read(path)
+                    .map_err(|e| DwdError::config(format!("Read mqtt.ca_file {:?} failed: {}", path, e)))?,
+                client_auth: None,
+            },
+            None => TlsConfiguration::Native,
+        };
+        options.set_transport(Transport::tls_with_config(tls));
+    }
+
+    let (client, eventloop) = AsyncClient::new(options, CAPACITY);
+    *MQTT.lock().expect("The lock of MQTT should not be poisoned.") = Some(Mqtt::new(client, conf));
+    Ok(eventloop)
+}
+
+/// Keep the connection alive and handle the commands until DWD is shutting down.
+pub async fn run(mut eventloop: EventLoop) {
+    loop {
+        let event = tokio::select! {
+            event = eventloop.poll() => event,
+            _ = shutdown::wait() => break,
+        };
+        match event {
+            Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                info!(event = "mqtt_connected"; "Connected to the MQTT broker.");
+                with_mqtt(|mqtt| mqtt.on_connected());
+            }
+            Ok(MqttEvent::Incoming(Packet::Publish(publish))) => handle_command(&publish.topic),
+            Ok(_) => (),
+            Err(e) => {
+                with_mqtt(|mqtt| mqtt.connected = false);
+                warn!(
+                    event = "mqtt_disconnected";
+                    "Disconnected from the MQTT broker, reconnect in {}s: {}",
+                    RECONNECT_DELAY.as_secs(),
+                    e
+                );
+                tokio::select! {
+                    _ = time::sleep(RECONNECT_DELAY) => (),
+                    _ = shutdown::wait() => break,
+                }
+            }
+        }
+    }
+
+    // The last will is only sent on unexpected disconnection, so tell the subscribers DWD is going away.
+    let mut connected = false;
+    with_mqtt(|mqtt| {
+        connected = mqtt.connected;
+        let status = mqtt.topic("status");
+        let _ = mqtt.client.try_publish(status, QoS::AtLeastOnce, true, "offline");
+        let _ = mqtt.client.try_disconnect();
+    });
+    if connected {
+        let flush = async {
+            while let Ok(event) = eventloop.poll().await {
+                if let MqttEvent::Outgoing(Outgoing::Disconnect) = event {
+                    break;
+                }
+            }
+        };
+        let _ = time::timeout(Duration::from_secs(2), flush).await;
+    }
+    debug!("Disconnected from the MQTT broker.");
+}
+
+fn handle_command(topic: &str) {
+    let mut expected = String::new();
+    with_mqtt(|mqtt| expected = mqtt.topic("force_update"));
+    if topic != expected {
+        return;
+    }
+
+    if control::is_paused() {
+        warn!("Updates are paused, ignore the force_update command from MQTT.");
+        return;
+    }
+    info!(event = "update_triggered", force = true; "An update is triggered through MQTT.");
+    control::trigger(true);
+}
+
+/// Publish the current IP of the WAN.
+pub fn report_ip(wan: &str, ip: IpAddr) {
+    with_mqtt(|mqtt| {
+        let topic = mqtt.topic(&format!("{}/ip", topic_level(wan)));
+        mqtt.announce(
+            "sensor",
+            &format!("{}_ip", wan),
+            json!({
+                "name": format!("{} IP", wan),
+                "state_topic": topic,
+                "icon": "mdi:ip-network",
+            }),
+        );
+        mqtt.publish(topic, ip.to_string());
+    });
+}
+
+/// Publish when the IP of the WAN is published to DNS.
+pub fn report_published(wan: &str, at: DateTime<Utc>) {
+    with_mqtt(|mqtt| {
+        let topic = mqtt.topic(&format!("{}/updated_at", topic_level(wan)));
+        mqtt.announce(
+            "sensor",
+            &format!("{}_updated_at", wan),
+            json!({
+                "name": format!("{} last update", wan),
+                "state_topic": topic,
+                "device_class": "timestamp",
+            }),
+        );
+        mqtt.publish(topic, at.to_rfc3339());
+    });
+}
+
+/// Publish the health of the DNS provider, `None` means the last update succeeded.
+pub fn report_dns_provider(name: &str, error: Option<&str>) {
+    with_mqtt(|mqtt| {
+        let topic = mqtt.topic(&format!("provider/{}", topic_level(name)));
+        mqtt.announce(
+            "sensor",
+            &format!("provider_{}", name),
+            json!({
+                "name": format!("{} status", name),
+                "state_topic": topic,
+                "value_template": "{{ value_json.state }}",
+                "json_attributes_topic": topic,
+                "icon": "mdi:dns",
+            }),
+        );
+        let payload = json!({
+            "state": if error.is_some() { "failing" } else { "healthy" },
+            "error": error,
+            "checked_at": Utc::now().to_rfc3339(),
+        });
+        mqtt.publish(topic, payload.to_string());
+    });
+}
+
+/// Publish the event, the last one is retained.
+pub fn publish_event(event: &Event) {
+    with_mqtt(|mqtt| {
+        let mut payload = serde_json::to_value(event).unwrap_or_default();
+        payload["message"] = json!(event.message());
+        mqtt.publish(mqtt.topic("event"), payload.to_string());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    /// Read an MQTT packet, return the first byte of the fixed header and the rest after the remaining length.
+    async fn read_packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+        let header = stream.read_u8().await.ok()?;
+        let (mut len, mut shift) = (0usize, 0);
+        loop {
+            let byte = stream.read_u8().await.ok()?;
+            len += ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await.ok()?;
+        Some((header, body))
+    }
+
+    /// A local broker which accepts one client and records the packets it sent, publishes are recorded as
+    /// `PUBLISH <topic> <payload>`. It sends a `force_update` command after the subscription.
+    async fn start_broker() -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = received.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Some((header, body)) = read_packet(&mut stream).await {
+                let reply = match header >> 4 {
+                    1 => {
+                        recorded
+                            .lock()
+                            .unwrap()
+                            .push(format!("CONNECT {}", String::from_utf8_lossy(&body)));
+                        vec![0x20, 0x02, 0x00, 0x00]
+                    }
+                    3 => {
+                        let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                        let topic = String::from_utf8_lossy(&body[2..2 + topic_len]).into_owned();
+                        let qos = (header >> 1) & 0x03;
+                        let start = 2 + topic_len + if qos > 0 { 2 } else { 0 };
+                        let payload = String::from_utf8_lossy(&body[start..]).into_owned();
+                        recorded.lock().unwrap().push(format!("PUBLISH {} {}", topic, payload));
+                        if qos == 0 {
+                            continue;
+                        }
+                        vec![0x40, 0x02, body[2 + topic_len], body[3 + topic_len]]
+                    }
+                    8 => {
+                        let topic_len = u16::from_be_bytes([body[2], body[3]]) as usize;
+                        let topic = String::from_utf8_lossy(&body[4..4 + topic_len]).into_owned();
+                        recorded.lock().unwrap().push(format!("SUBSCRIBE {}", topic));
+                        let mut reply = vec![0x90, 0x03, body[0], body[1], 0x01];
+                        let command = b"dwd/force_update";
+                        reply.extend([0x30, (command.len() + 2 + 5) as u8, 0x00, command.len() as u8]);
+                        reply.extend(command);
+                        reply.extend(b"PRESS");
+                        reply
+                    }
+                    12 => vec![0xd0, 0x00],
+                    _ => return,
+                };
+                stream.write_all(&reply).await.unwrap();
+            }
+        });
+
+        (port, received)
+    }
+
+    #[tokio::test]
+    async fn run_should_publish_state_and_discovery() {
+        let _serial = control::TEST_LOCK.lock().await;
+        let (port, received) = start_broker().await;
+        let conf = ConfigMqtt {
+            host: String::from("127.0.0.1"),
+            port: Some(port),
+            tls: false,
+            ca_file: None,
+            username: Some(String::from("dwd")),
+            password: Some(String::from("secret")),
+            client_id: None,
+            topic_prefix: None,
+            discovery: None,
+            discovery_prefix: None,
+        };
+        let eventloop = init(&conf).unwrap();
+        let mut trigger = control::subscribe();
+        // Reported before connected, it is sent after the connection is established.
+        report_ip("default", "1.1.1.1".parse().unwrap());
+        report_ip("lan/1+#", "2.2.2.2".parse().unwrap());
+        tokio::spawn(run(eventloop));
+
+        let expected = [
+            "SUBSCRIBE dwd/force_update",
+            "PUBLISH dwd/status online",
+            "PUBLISH dwd/default/ip 1.1.1.1",
+            "PUBLISH dwd/lan_1__/ip 2.2.2.2",
+            "PUBLISH homeassistant/button/dwd/force_update/config",
+            "PUBLISH homeassistant/sensor/dwd/default_ip/config",
+        ];
+        for _ in 0..50 {
+            let received = received.lock().unwrap().join("\n");
+            if expected.iter().all(|val| received.contains(val)) {
+                break;
+            }
+            time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let received = received.lock().unwrap().join("\n");
+        for val in expected {
+            assert!(received.contains(val), "{} is not in:\n{}", val, received);
+        }
+        // The last will and the credentials are in the CONNECT packet.
+        assert!(received.contains("dwd/status") && received.contains("offline") && received.contains("secret"));
+        assert!(received.contains(r#""unique_id":"dwd_default_ip""#));
+        // The force_update command sent by the broker triggers a forced update.
+        assert_eq!(
+            time::timeout(Duration::from_secs(5), trigger.changed()).await.unwrap(),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn object_id_should_be_sanitized() {
+        assert_eq!(object_id("provider_name.com"), "provider_name_com");
+        assert_eq!(object_id("WAN-1"), "wan-1");
+    }
+}
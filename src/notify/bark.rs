@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::{api_url, redact, response_text, secret, Event, EventKind};
+
+const BASE_URL: &str = "https://api.day.app";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigBark {
+    /// The key of the device, or set it through env variable BARK_DEVICE_KEY.
+    pub device_key: Option<String>,
+    /// The base URL of the server, e.g. a self-hosted bark-server.
+    pub base_url: Option<String>,
+    /// The group of notifications, `DWD` by default.
+    pub group: Option<String>,
+    pub sound: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse {
+    code: i64,
+    message: Option<String>,
+}
+
+/// Push the event to an iOS device through Bark.
+///
+/// The document of Bark API: https://bark.day.app/#/tutorial
+///
+/// # Example:
+/// ```rust
+/// bark::send(conf, client, &Event::new(EventKind::IpChanged)).await?;
+/// ```
+pub async fn send(conf: &ConfigBark, client: &HttpClient, event: &Event) -> Result<(), DwdError> {
+    let device_key = secret(conf.device_key.as_ref(), "BARK_DEVICE_KEY", "bark.device_key")?;
+    // Failures break through focus modes.
+    let level = match event.event {
+        EventKind::UpdateFailed | EventKind::DetectionFailed => "timeSensitive",
+        EventKind::IpChanged | EventKind::UpdateRecovered => "active",
+    };
+    let mut body = json!({
+        "device_key": device_key,
+        "title": event.title(),
+        "body": event.text(),
+        "group": conf.group.as_deref().unwrap_or("DWD"),
+        "level": level,
+    });
+    if let Some(sound) = conf.sound.as_ref() {
+        body["sound"] = json!(sound);
+    }
+
+    let url = api_url(conf.base_url.as_deref().unwrap_or(BASE_URL), "push");
+    let ret = async {
+        let text = response_text(client.send(client.post(&url).json(&body)).await?).await?;
+        let ret: ApiResponse = serde_json::from_str(&text)?;
+        if ret.code != 200 {
+            return Err(DwdError::parse(format!(
+                "Bark rejected the message: {}",
+                ret.message.unwrap_or_default()
+            )));
+        }
+        Ok(())
+    };
+    ret.await.map_err(|e| redact(e, &device_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock;
+    use super::*;
+    use crate::mock_server::MockResponse;
+
+    #[tokio::test]
+    async fn send_should_push_to_device() {
+        let (server, client) = mock(|_| MockResponse::json(200, json!({ "code": 200, "message": "success" }))).await;
+        let conf = ConfigBark {
+            device_key: Some(String::from("device")),
+            base_url: Some(server.url.clone()),
+            group: None,
+            sound: Some(String::from("alarm")),
+        };
+        let event = Event::new(EventKind::IpChanged)
+            .with_wan("default")
+            .with_ips(None, "2.2.2.2".parse().ok());
+        send(&conf, &client, &event).await.unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/push");
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["device_key"], "device");
+        assert_eq!(body["group"], "DWD");
+        assert_eq!(body["sound"], "alarm");
+        assert_eq!(
+            body["body"],
+            "The public IP of default changed from unknown to 2.2.2.2.\nWAN: default\nNew IP: 2.2.2.2"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::{redact, response_text, secret, truncate, Event, EventKind};
+
+/// The longest description of an embed.
+const MAX_DESCRIPTION: usize = 4096;
+/// The longest value of an embed field.
+const MAX_FIELD_VALUE: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigDiscord {
+    /// The URL of the channel webhook, or set it through env variable DISCORD_WEBHOOK_URL.
+    pub webhook_url: Option<String>,
+    /// The name which the message is sent as, `DWD` by default.
+    pub username: Option<String>,
+}
+
+/// Send the event to a Discord channel as an embed.
+///
+/// The document of Discord webhooks: https://discord.com/developers/docs/resources/webhook#execute-webhook
+///
+/// # Example:
+/// ```rust
+/// discord::send(conf, client, &Event::new(EventKind::IpChanged)).await?;
+/// ```
+pub async fn send(conf: &ConfigDiscord, client: &HttpClient, event: &Event) -> Result<(), DwdError> {
+    let url = secret(conf.webhook_url.as_ref(), "DISCORD_WEBHOOK_URL", "discord.webhook_url")?;
+    let fields = event
+        .fields()
+        .into_iter()
+        .map(|(name, value)| {
+            json!({
+                "name": name,
+                "value": truncate(&value, MAX_FIELD_VALUE),
+                "inline": name != "Error" && name != "Records",
+            })
+        })
+        .collect::<Vec<_>>();
+    let body = json!({
+        "username": conf.username.as_deref().unwrap_or("DWD"),
+        "embeds": [{
+            "title": event.title(),
+            "description": truncate(&event.message(), MAX_DESCRIPTION),
+            "color": color(event.event),
+            "fields": fields,
+            "timestamp": event.timestamp.to_rfc3339(),
+        }],
+    });
+
+    let ret = match client.send(client.post(&url).json(&body)).await {
+        Ok(response) => response_text(response).await,
+        Err(e) => Err(e),
+    };
+    ret.map(|_| ()).map_err(|e| redact(e, &url))
+}
+
+fn color(kind: EventKind) -> u32 {
+    match kind {
+        EventKind::IpChanged => 0x3498db,
+        EventKind::UpdateFailed => 0xe74c3c,
+        EventKind::UpdateRecovered => 0x2ecc71,
+        EventKind::DetectionFailed => 0xe67e22,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock;
+    use super::*;
+    use crate::mock_server::MockResponse;
+
+    #[tokio::test]
+    async fn send_should_post_embed() {
+        let (server, client) = mock(|_| MockResponse::new(204, "")).await;
+        let conf = ConfigDiscord {
+            webhook_url: Some(format!("{}api/webhooks/1/token", server.url)),
+            username: None,
+        };
+        let event = Event::new(EventKind::UpdateFailed)
+            .with_provider("name.com")
+            .with_error("Bad token");
+        send(&conf, &client, &event).await.unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/api/webhooks/1/token");
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        let embed = &body["embeds"][0];
+        assert_eq!(body["username"], "DWD");
+        assert_eq!(embed["title"], "DNS update failed");
+        assert_eq!(embed["color"], 0xe74c3c);
+        assert_eq!(embed["fields"][0]["name"], "Provider");
+        assert_eq!(embed["fields"][1]["value"], "Bad token");
+        assert_eq!(embed["fields"][1]["inline"], false);
+    }
+
+    #[tokio::test]
+    async fn send_should_truncate_long_field() {
+        let (server, client) = mock(|_| MockResponse::new(204, "")).await;
+        let conf = ConfigDiscord {
+            webhook_url: Some(format!("{}api/webhooks/1/token", server.url)),
+            username: None,
+        };
+        let event = Event::new(EventKind::UpdateFailed)
+            .with_provider("name.com")
+            .with_error(&"x".repeat(2000));
+        send(&conf, &client, &event).await.unwrap();
+
+        let body: serde_json::Value = serde_json::from_str(&server.requests()[0].body).unwrap();
+        let value = body["embeds"][0]["fields"][1]["value"].as_str().unwrap();
+        assert_eq!(value.chars().count(), MAX_FIELD_VALUE);
+    }
+}
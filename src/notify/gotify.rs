@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::{api_url, response_text, secret, Event, EventKind};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigGotify {
+    /// The base URL of the Gotify server, e.g. `https://gotify.example.com`.
+    pub base_url: String,
+    /// The token of the application, or set it through env variable GOTIFY_TOKEN.
+    pub token: Option<String>,
+    /// Failures are sent with 8 and others with 5 by default.
+    pub priority: Option<u8>,
+}
+
+/// Push the event to a Gotify server as a Markdown message.
+///
+/// The document of Gotify API: https://gotify.net/api-docs#/message/createMessage
+///
+/// # Example:
+/// ```rust
+/// gotify::send(conf, client, &Event::new(EventKind::IpChanged)).await?;
+/// ```
+pub async fn send(conf: &ConfigGotify, client: &HttpClient, event: &Event) -> Result<(), DwdError> {
+    let token = secret(conf.token.as_ref(), "GOTIFY_TOKEN", "gotify.token")?;
+    let priority = match event.event {
+        EventKind::UpdateFailed | EventKind::DetectionFailed => 8,
+        EventKind::IpChanged | EventKind::UpdateRecovered => 5,
+    };
+    let body = json!({
+        "title": event.title(),
+        "message": event.markdown(),
+        "priority": conf.priority.unwrap_or(priority),
+        "extras": { "client::display": { "contentType": "text/markdown" } },
+    });
+
+    let builder = client
+        .post(api_url(&conf.base_url, "message"))
+        .header("X-Gotify-Key", token)
+        .json(&body);
+    response_text(client.send(builder).await?).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock;
+    use super::*;
+    use crate::mock_server::MockResponse;
+
+    #[tokio::test]
+    async fn send_should_push_markdown_message() {
+        let (server, client) = mock(|_| MockResponse::json(200, json!({ "id": 1 }))).await;
+        let conf = ConfigGotify {
+            base_url: server.url.clone(),
+            token: Some(String::from("app-token")),
+            priority: None,
+        };
+        let event = Event::new(EventKind::UpdateFailed).with_error("boom");
+        send(&conf, &client, &event).await.unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/message");
+        assert_eq!(request.headers["x-gotify-key"], "app-token");
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["priority"], 8);
+        assert_eq!(body["extras"]["client::display"]["contentType"], "text/markdown");
+    }
+}
@@ -0,0 +1,664 @@
+//! This is notification module, it tells people when the public IP changes or a provider keeps failing. Take a look
+//! at webhook.rs for how to add your own notifier, and don't forget to add it to the `fn notify_one` below.
+//!
+//! The events are delivered in background so the loops are never held up. The same event is sent only once within
+//! `dedup_window`, and each notifier sends at most `max_per_hour` messages, so a flapping provider does not flood
+//! the inbox.
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use log::{debug, error, warn};
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use simple_error::SimpleError;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    env,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+
+use super::error::DwdError;
+use super::http::HttpClient;
+use super::mqtt;
+use super::rate_limit;
+use super::util::error_style;
+
+pub mod bark;
+pub mod discord;
+pub mod gotify;
+pub mod ntfy;
+pub mod serverchan;
+pub mod slack;
+pub mod smtp;
+pub mod telegram;
+pub mod webhook;
+
+const DEFAULT_DEDUP_WINDOW: u64 = 3600;
+const DEFAULT_MAX_PER_HOUR: u32 = 20;
+
+lazy_static! {
+    static ref NOTIFY: Mutex<Option<(ConfigNotify, HttpClient)>> = Mutex::new(None);
+    /// When each event was sent the last time, keyed by `Event::dedup_key`.
+    static ref SENT: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+}
+
+/// The notifiers which events are sent to, and the configs of them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigNotify {
+    #[serde(default)]
+    pub notifier: Vec<String>,
+    /// The events to send, all events by default.
+    pub events: Option<Vec<EventKind>>,
+    /// Seconds within which the same event is sent only once, 3600 by default.
+    pub dedup_window: Option<u64>,
+    /// The messages each notifier sends at most in an hour, 20 by default, it should be at least 1.
+    pub max_per_hour: Option<u32>,
+
+    pub webhook: Option<webhook::ConfigWebhook>,
+    pub smtp: Option<smtp::ConfigSmtp>,
+    pub telegram: Option<telegram::ConfigTelegram>,
+    pub discord: Option<discord::ConfigDiscord>,
+    pub slack: Option<slack::ConfigSlack>,
+    pub ntfy: Option<ntfy::ConfigNtfy>,
+    pub gotify: Option<gotify::ConfigGotify>,
+    pub bark: Option<bark::ConfigBark>,
+    pub serverchan: Option<serverchan::ConfigServerChan>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    IpChanged,
+    UpdateFailed,
+    UpdateRecovered,
+    DetectionFailed,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::IpChanged => "ip_changed",
+            EventKind::UpdateFailed => "update_failed",
+            EventKind::UpdateRecovered => "update_recovered",
+            EventKind::DetectionFailed => "detection_failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub event: EventKind,
+    /// The job which the event happens in, it is `default` for the default job.
+    pub wan: Option<String>,
+    pub old_ip: Option<IpAddr>,
+    pub new_ip: Option<IpAddr>,
+    pub provider: Option<String>,
+    pub record: Option<String>,
+    pub error: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Event {
+    pub fn new(event: EventKind) -> Self {
+        Event {
+            event,
+            wan: None,
+            old_ip: None,
+            new_ip: None,
+            provider: None,
+            record: None,
+            error: None,
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn with_wan(mut self, wan: &str) -> Self {
+        self.wan = Some(wan.to_owned());
+        self
+    }
+
+    pub fn with_ips(mut self, old_ip: Option<IpAddr>, new_ip: Option<IpAddr>) -> Self {
+        self.old_ip = old_ip;
+        self.new_ip = new_ip;
+        self
+    }
+
+    pub fn with_provider(mut self, provider: &str) -> Self {
+        self.provider = Some(provider.to_owned());
+        self
+    }
+
+    pub fn with_record(mut self, record: Option<&str>) -> Self {
+        self.record = record.map(String::from);
+        self
+    }
+
+    pub fn with_error(mut self, error: &str) -> Self {
+        self.error = Some(error.to_owned());
+        self
+    }
+
+    /// A short title of the event, e.g. for the title of push notifications.
+    pub fn title(&self) -> &'static str {
+        match self.event {
+            EventKind::IpChanged => "IP changed",
+            EventKind::UpdateFailed => "DNS update failed",
+            EventKind::UpdateRecovered => "DNS update recovered",
+            EventKind::DetectionFailed => "IP detection failed",
+        }
+    }
+
+    /// The details of the event which are present, as pairs of label and value.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let fields = [
+            ("WAN", self.wan.clone()),
+            ("Old IP", self.old_ip.map(|val| val.to_string())),
+            ("New IP", self.new_ip.map(|val| val.to_string())),
+            ("Provider", self.provider.clone()),
+            ("Records", self.record.clone().filter(|val| !val.is_empty())),
+            ("Error", self.error.clone()),
+        ];
+        fields
+            .iter()
+            .filter_map(|(label, val)| val.clone().map(|val| (*label, val)))
+            .collect()
+    }
+
+    /// The message followed by the details, one in a line.
+    pub fn text(&self) -> String {
+        let mut text = self.message();
+        for (label, val) in self.fields() {
+            text.push_str(&format!("\n{}: {}", label, val));
+        }
+        text
+    }
+
+    /// The message followed by the details as a Markdown list.
+    pub fn markdown(&self) -> String {
+        let mut text = self.message();
+        if !self.fields().is_empty() {
+            text.push('\n');
+        }
+        for (label, val) in self.fields() {
+            text.push_str(&format!("\n- **{}**: {}", label, val));
+        }
+        text
+    }
+
+    /// A one-line summary of the event.
+    pub fn message(&self) -> String {
+        let ip = |ip: Option<IpAddr>| ip.map(|val| val.to_string()).unwrap_or_else(|| String::from("unknown"));
+        let target = match (self.provider.as_ref(), self.record.as_ref()) {
+            (Some(provider), Some(record)) => format!("{} on {}", record, provider),
+            (Some(provider), None) => provider.to_owned(),
+            _ => String::from("DNS"),
+        };
+        match self.event {
+            EventKind::IpChanged => format!(
+                "The public IP of {} changed from {} to {}.",
+                self.wan.as_deref().unwrap_or("default"),
+                ip(self.old_ip),
+                ip(self.new_ip)
+            ),
+            EventKind::UpdateFailed => format!(
+                "Update {} to {} failed: {}",
+                target,
+                ip(self.new_ip),
+                self.error.as_deref().unwrap_or("unknown error")
+            ),
+            EventKind::UpdateRecovered => format!("Update {} to {} succeeded again.", target, ip(self.new_ip)),
+            EventKind::DetectionFailed => format!(
+                "Detect the public IP of {} failed: {}",
+                self.wan.as_deref().unwrap_or("default"),
+                self.error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+
+    /// The value of a template variable, the missing fields are empty.
+    pub fn var(&self, name: &str) -> Option<String> {
+        let ip = |ip: Option<IpAddr>| ip.map(|val| val.to_string()).unwrap_or_default();
+        let val = match name {
+            "event" => self.event.name().to_owned(),
+            "message" => self.message(),
+            "wan" => self.wan.clone().unwrap_or_default(),
+            "old_ip" => ip(self.old_ip),
+            "new_ip" => ip(self.new_ip),
+            "provider" => self.provider.clone().unwrap_or_default(),
+            "record" => self.record.clone().unwrap_or_default(),
+            "error" => self.error.clone().unwrap_or_default(),
+            "timestamp" => self.timestamp.to_rfc3339(),
+            _ => return None,
+        };
+        Some(val)
+    }
+
+    /// The events with the same key are duplicates, the timestamp and the error are not a part of it, because error
+    /// messages often contain request IDs or times.
+    fn dedup_key(&self) -> String {
+        format!(
+            "{}/{}/{}/{}/{:?}",
+            self.event.name(),
+            self.provider.as_deref().unwrap_or_default(),
+            self.record.as_deref().unwrap_or_default(),
+            self.wan.as_deref().unwrap_or_default(),
+            self.new_ip
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Notifier {
+    Webhook,
+    Smtp,
+    Telegram,
+    Discord,
+    Slack,
+    Ntfy,
+    Gotify,
+    Bark,
+    ServerChan,
+}
+
+impl Notifier {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Notifier::Webhook => "webhook",
+            Notifier::Smtp => "smtp",
+            Notifier::Telegram => "telegram",
+            Notifier::Discord => "discord",
+            Notifier::Slack => "slack",
+            Notifier::Ntfy => "ntfy",
+            Notifier::Gotify => "gotify",
+            Notifier::Bark => "bark",
+            Notifier::ServerChan => "serverchan",
+        }
+    }
+}
+
+impl TryFrom<&str> for Notifier {
+    type Error = SimpleError;
+
+    fn try_from(input: &str) -> Result<Notifier, SimpleError> {
+        let notifier = match input {
+            "webhook" => Notifier::Webhook,
+            "smtp" => Notifier::Smtp,
+            "telegram" => Notifier::Telegram,
+            "discord" => Notifier::Discord,
+            "slack" => Notifier::Slack,
+            "ntfy" => Notifier::Ntfy,
+            "gotify" => Notifier::Gotify,
+            "bark" => Notifier::Bark,
+            "serverchan" => Notifier::ServerChan,
+            _ => return Err(SimpleError::new("Unknown notifier")),
+        };
+        Ok(notifier)
+    }
+}
+
+/// Set up the notifiers and start the daily digest, the events are dropped if it is not called.
+///
+/// The handle of the daily digest is returned, it should be awaited on shutdown to send the pending events.
+pub fn init(conf: ConfigNotify, client: HttpClient) -> Result<Option<JoinHandle<()>>, DwdError> {
+    if conf.max_per_hour == Some(0) {
+        return Err(DwdError::config(
+            "Invalid notify.max_per_hour 0, remove the notifiers to disable notifications.",
+        ));
+    }
+    let mut digest = None;
+    if let Some(smtp_conf) = conf
+        .smtp
+        .as_ref()
+        .filter(|_| conf.notifier.iter().any(|name| name == "smtp"))
+    {
+        smtp::check_security(smtp_conf)?;
+        if smtp::digest_time(smtp_conf)?.is_some() {
+            digest = Some(tokio::spawn(smtp::run_digest(smtp_conf.to_owned())));
+        }
+    }
+
+    *NOTIFY.lock().expect("The lock of notify should not be poisoned.") = Some((conf, client));
+    Ok(digest)
+}
+
+/// Send the event to all notifiers in background, it is also published to MQTT regardless of the filters.
+pub fn send(event: Event) {
+    mqtt::publish_event(&event);
+    let (conf, client) = match NOTIFY
+        .lock()
+        .expect("The lock of notify should not be poisoned.")
+        .clone()
+    {
+        Some(val) => val,
+        None => return,
+    };
+    let unwanted = conf
+        .events
+        .as_ref()
+        .is_some_and(|events| !events.contains(&event.event));
+    if conf.notifier.is_empty() || unwanted {
+        return;
+    }
+
+    let window = Duration::from_secs(conf.dedup_window.unwrap_or(DEFAULT_DEDUP_WINDOW));
+    if is_duplicate(&event, window, Instant::now()) {
+        debug!("The event {} has been sent recently, skip.", event.dedup_key());
+        return;
+    }
+
+    // The digest collects every distinct event of the day, so it is queued before rate limiting.
+    let digest = conf.notifier.iter().any(|name| name == "smtp")
+        && conf
+            .smtp
+            .as_ref()
+            .is_some_and(|smtp_conf| smtp_conf.digest_at.is_some());
+    if digest {
+        smtp::queue(&event);
+    }
+
+    tokio::spawn(async move {
+        for name in conf.notifier.iter() {
+            let notifier = match Notifier::try_from(name.as_str()) {
+                Ok(val) => val,
+                Err(_) => {
+                    error!(target: "error", "Notifier {} does not supported.", error_style(name));
+                    continue;
+                }
+            };
+            if digest && matches!(notifier, Notifier::Smtp) {
+                continue;
+            }
+
+            let max_per_hour = conf.max_per_hour.unwrap_or(DEFAULT_MAX_PER_HOUR);
+            let key = format!("notify/{}", notifier.name());
+            if rate_limit::acquire(&key, Some(max_per_hour as f64 / 3600.0), max_per_hour).is_some() {
+                warn!(
+                    "Notifier {} has sent {} messages in the last hour, drop the event {}.",
+                    notifier.name(),
+                    max_per_hour,
+                    event.event.name()
+                );
+                continue;
+            }
+
+            let client = client.for_provider(notifier.name());
+            if let Err(e) = notify_one(&conf, &client, notifier, &event).await {
+                let e = e.with_provider(notifier.name());
+                error!(
+                    target: "error",
+                    event = "notification_failed",
+                    provider = notifier.name(),
+                    kind:% = e.kind;
+                    "Send notification through {} failed, error: {}",
+                    notifier.name(),
+                    e
+                );
+            }
+        }
+    });
+}
+
+async fn notify_one(
+    conf: &ConfigNotify,
+    client: &HttpClient,
+    notifier: Notifier,
+    event: &Event,
+) -> Result<(), DwdError> {
+    match notifier {
+        Notifier::Webhook => match conf.webhook.as_ref() {
+            Some(sub_conf) => webhook::send(sub_conf, client, event).await,
+            None => Err(DwdError::config("The config.notify.webhook is required.")),
+        },
+        Notifier::Smtp => match conf.smtp.as_ref() {
+            Some(sub_conf) => smtp::send(sub_conf, event).await,
+            None => Err(DwdError::config("The config.notify.smtp is required.")),
+        },
+        Notifier::Telegram => match conf.telegram.as_ref() {
+            Some(sub_conf) => telegram::send(sub_conf, client, event).await,
+            None => Err(DwdError::config("The config.notify.telegram is required.")),
+        },
+        Notifier::Discord => match conf.discord.as_ref() {
+            Some(sub_conf) => discord::send(sub_conf, client, event).await,
+            None => Err(DwdError::config("The config.notify.discord is required.")),
+        },
+        Notifier::Slack => match conf.slack.as_ref() {
+            Some(sub_conf) => slack::send(sub_conf, client, event).await,
+            None => Err(DwdError::config("The config.notify.slack is required.")),
+        },
+        Notifier::Ntfy => match conf.ntfy.as_ref() {
+            Some(sub_conf) => ntfy::send(sub_conf, client, event).await,
+            None => Err(DwdError::config("The config.notify.ntfy is required.")),
+        },
+        Notifier::Gotify => match conf.gotify.as_ref() {
+            Some(sub_conf) => gotify::send(sub_conf, client, event).await,
+            None => Err(DwdError::config("The config.notify.gotify is required.")),
+        },
+        Notifier::Bark => match conf.bark.as_ref() {
+            Some(sub_conf) => bark::send(sub_conf, client, event).await,
+            None => Err(DwdError::config("The config.notify.bark is required.")),
+        },
+        Notifier::ServerChan => match conf.serverchan.as_ref() {
+            Some(sub_conf) => serverchan::send(sub_conf, client, event).await,
+            None => Err(DwdError::config("The config.notify.serverchan is required.")),
+        },
+    }
+}
+
+/// Get the secret from the config, or the env variable if it is not configured.
+fn secret(val: Option<&String>, env_name: &str, field: &str) -> Result<String, DwdError> {
+    match val {
+        Some(val) => Ok(val.to_owned()),
+        None => env::var(env_name).map_err(|_| {
+            DwdError::config(format!(
+                "Please set config.notify.{} or env variable {}.",
+                field, env_name
+            ))
+        }),
+    }
+}
+
+/// Hide the secret which is a part of the URL from the error, reqwest errors contain the URL.
+fn redact(mut e: DwdError, secret: &str) -> DwdError {
+    if !secret.is_empty() {
+        e.message = e.message.replace(secret, "***");
+    }
+    e
+}
+
+/// Cut the text to at most `max` characters, an ellipsis marks that it is truncated.
+fn truncate(val: &str, max: usize) -> String {
+    if val.chars().count() <= max {
+        return val.to_owned();
+    }
+    let mut truncated = val.chars().take(max.saturating_sub(1)).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+/// Join the path to the base URL of an API, the base URL may or may not end with a slash.
+fn api_url(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+/// Check the status of the response, return its body if it succeeded.
+async fn response_text(response: Response) -> Result<String, DwdError> {
+    let status = response.status();
+    let text = response.text().await?;
+    if !status.is_success() {
+        return Err(DwdError::from_status(
+            status,
+            format!("Unexpected response status {}: {}", status, text),
+        ));
+    }
+    Ok(text)
+}
+
+/// Whether the same event has been sent within the window, otherwise it is recorded as sent now.
+///
+/// A failure and a recovery of the same record clear each other, so the next one is always sent.
+fn is_duplicate(event: &Event, window: Duration, now: Instant) -> bool {
+    let mut sent = SENT.lock().expect("The lock of sent events should not be poisoned.");
+    sent.retain(|_, sent_at| now.duration_since(*sent_at) < window);
+
+    let key = event.dedup_key();
+    if sent.contains_key(&key) {
+        return true;
+    }
+
+    let opposite = match event.event {
+        EventKind::UpdateFailed => Some(EventKind::UpdateRecovered),
+        EventKind::UpdateRecovered => Some(EventKind::UpdateFailed),
+        _ => None,
+    };
+    if let Some(opposite) = opposite {
+        let prefix = format!(
+            "{}/{}/{}/",
+            opposite.name(),
+            event.provider.as_deref().unwrap_or_default(),
+            event.record.as_deref().unwrap_or_default()
+        );
+        sent.retain(|key, _| !key.starts_with(&prefix));
+    }
+    sent.insert(key, now);
+    false
+}
+
+/// Replace the `{{name}}` in the template with the variables of the event, the values are escaped with `escape`,
+/// e.g. for JSON strings. Unknown variables are kept as they are.
+pub fn render<F>(template: &str, event: &Event, escape: F) -> String
+where
+    F: Fn(&str) -> String,
+{
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => {
+                let name = after[..end].trim();
+                match event.var(name) {
+                    Some(val) => output.push_str(&escape(&val)),
+                    None => output.push_str(&rest[start..start + 2 + end + 2]),
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                output.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockRequest, MockResponse, MockServer};
+
+    /// Start a mock server for a notifier, with a client to send requests to it.
+    pub(super) async fn mock<F>(handler: F) -> (MockServer, HttpClient)
+    where
+        F: Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    {
+        let server = MockServer::start(handler).await;
+        let client = HttpClient::new(&Default::default(), &Default::default()).unwrap();
+        (server, client)
+    }
+
+    fn failed(provider: &str, error: &str) -> Event {
+        Event::new(EventKind::UpdateFailed)
+            .with_provider(provider)
+            .with_error(error)
+    }
+
+    #[test]
+    fn render_should_replace_variables() {
+        let event = Event::new(EventKind::IpChanged)
+            .with_wan("wan1")
+            .with_ips("1.1.1.1".parse().ok(), "2.2.2.2".parse().ok());
+
+        assert_eq!(
+            render("{{ wan }}: {{old_ip}} -> {{new_ip}} {{unknown}} {{", &event, |val| val
+                .to_owned()),
+            "wan1: 1.1.1.1 -> 2.2.2.2 {{unknown}} {{"
+        );
+        assert_eq!(
+            render("{{message}}", &event, |val| val.to_owned()),
+            "The public IP of wan1 changed from 1.1.1.1 to 2.2.2.2."
+        );
+    }
+
+    #[test]
+    fn truncate_should_keep_at_most_max_chars() {
+        assert_eq!(truncate("héllo", 5), "héllo");
+        assert_eq!(truncate("héllo", 4), "hél…");
+    }
+
+    #[tokio::test]
+    async fn init_should_reject_zero_max_per_hour() {
+        let conf = ConfigNotify {
+            notifier: vec![String::from("webhook")],
+            max_per_hour: Some(0),
+            ..Default::default()
+        };
+        let client = HttpClient::new(&Default::default(), &Default::default()).unwrap();
+        assert_eq!(init(conf, client).unwrap_err().kind, crate::error::ErrorKind::Config);
+    }
+
+    #[test]
+    fn is_duplicate_should_suppress_the_same_event_within_window() {
+        let window = Duration::from_secs(60);
+        let now = Instant::now();
+
+        assert!(!is_duplicate(&failed("dedup.test", "boom"), window, now));
+        assert!(is_duplicate(
+            &failed("dedup.test", "boom"),
+            window,
+            now + Duration::from_secs(30)
+        ));
+        assert!(is_duplicate(
+            &failed("dedup.test", "bang"),
+            window,
+            now + Duration::from_secs(30)
+        ));
+        assert!(!is_duplicate(
+            &failed("dedup.test", "boom"),
+            window,
+            now + Duration::from_secs(61)
+        ));
+
+        let recovered = Event::new(EventKind::UpdateRecovered).with_provider("dedup.test");
+        assert!(!is_duplicate(&recovered, window, now + Duration::from_secs(62)));
+        assert!(!is_duplicate(
+            &failed("dedup.test", "boom"),
+            window,
+            now + Duration::from_secs(63)
+        ));
+        assert!(!is_duplicate(&recovered, window, now + Duration::from_secs(64)));
+    }
+
+    #[test]
+    fn is_duplicate_should_only_clear_the_same_record() {
+        let window = Duration::from_secs(60);
+        let now = Instant::now();
+        let failed = |record: &str| failed("record.test", "boom").with_record(Some(record));
+        let recovered = |record: &str| {
+            Event::new(EventKind::UpdateRecovered)
+                .with_provider("record.test")
+                .with_record(Some(record))
+        };
+
+        assert!(!is_duplicate(&failed("a.example.com"), window, now));
+        assert!(!is_duplicate(&failed("b.example.com"), window, now));
+        assert!(!is_duplicate(&recovered("a.example.com"), window, now));
+        assert!(!is_duplicate(&failed("a.example.com"), window, now));
+        assert!(is_duplicate(&failed("b.example.com"), window, now));
+    }
+}
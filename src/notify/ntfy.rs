@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::{response_text, secret, Event, EventKind};
+
+const BASE_URL: &str = "https://ntfy.sh";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigNtfy {
+    pub topic: String,
+    /// The base URL of the server, e.g. a self-hosted ntfy server.
+    pub base_url: Option<String>,
+    /// The access token for protected topics, or set it through env variable NTFY_TOKEN.
+    pub token: Option<String>,
+    /// The priority from 1 to 5, failures are sent with 4 and others with 3 by default.
+    pub priority: Option<u8>,
+}
+
+/// Publish the event to a ntfy topic.
+///
+/// The document of ntfy publishing: https://docs.ntfy.sh/publish/#publish-as-json
+///
+/// # Example:
+/// ```rust
+/// ntfy::send(conf, client, &Event::new(EventKind::IpChanged)).await?;
+/// ```
+pub async fn send(conf: &ConfigNtfy, client: &HttpClient, event: &Event) -> Result<(), DwdError> {
+    let (tag, priority) = match event.event {
+        EventKind::IpChanged => ("globe_with_meridians", 3),
+        EventKind::UpdateFailed => ("warning", 4),
+        EventKind::UpdateRecovered => ("white_check_mark", 3),
+        EventKind::DetectionFailed => ("x", 4),
+    };
+    let body = json!({
+        "topic": conf.topic,
+        "title": event.title(),
+        "message": event.markdown(),
+        "markdown": true,
+        "tags": [tag],
+        "priority": conf.priority.unwrap_or(priority),
+    });
+
+    let mut builder = client.post(conf.base_url.as_deref().unwrap_or(BASE_URL)).json(&body);
+    // The token is optional, public topics need none.
+    if let Ok(token) = secret(conf.token.as_ref(), "NTFY_TOKEN", "ntfy.token") {
+        builder = builder.bearer_auth(token);
+    }
+    response_text(client.send(builder).await?).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock;
+    use super::*;
+    use crate::mock_server::MockResponse;
+
+    #[tokio::test]
+    async fn send_should_publish_json() {
+        let (server, client) = mock(|_| MockResponse::json(200, json!({ "id": "abc" }))).await;
+        let conf = ConfigNtfy {
+            topic: String::from("home"),
+            base_url: Some(server.url.clone()),
+            token: Some(String::from("tk_secret")),
+            priority: None,
+        };
+        let event = Event::new(EventKind::UpdateRecovered).with_provider("dynv6.com");
+        send(&conf, &client, &event).await.unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.headers["authorization"], "Bearer tk_secret");
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["topic"], "home");
+        assert_eq!(body["title"], "DNS update recovered");
+        assert_eq!(body["priority"], 3);
+        assert_eq!(
+            body["message"],
+            "Update dynv6.com to unknown succeeded again.\n\n- **Provider**: dynv6.com"
+        );
+    }
+}
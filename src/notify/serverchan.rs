@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::{api_url, redact, response_text, secret, Event};
+
+const BASE_URL: &str = "https://sctapi.ftqq.com";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigServerChan {
+    /// The SendKey of ServerChan Turbo, or set it through env variable SERVERCHAN_SENDKEY.
+    pub sendkey: Option<String>,
+    /// The base URL of the API.
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse {
+    code: i64,
+    message: Option<String>,
+}
+
+/// Push the event through ServerChan, it is delivered to WeChat and other channels.
+///
+/// The document of ServerChan: https://sct.ftqq.com/sendkey
+///
+/// # Example:
+/// ```rust
+/// serverchan::send(conf, client, &Event::new(EventKind::IpChanged)).await?;
+/// ```
+pub async fn send(conf: &ConfigServerChan, client: &HttpClient, event: &Event) -> Result<(), DwdError> {
+    let sendkey = secret(conf.sendkey.as_ref(), "SERVERCHAN_SENDKEY", "serverchan.sendkey")?;
+    let url = api_url(
+        conf.base_url.as_deref().unwrap_or(BASE_URL),
+        &format!("{}.send", sendkey),
+    );
+    let form = [("title", event.title().to_owned()), ("desp", event.markdown())];
+
+    let ret = async {
+        let text = response_text(client.send(client.post(&url).form(&form)).await?).await?;
+        let ret: ApiResponse = serde_json::from_str(&text)?;
+        if ret.code != 0 {
+            return Err(DwdError::parse(format!(
+                "ServerChan rejected the message: {}",
+                ret.message.unwrap_or_default()
+            )));
+        }
+        Ok(())
+    };
+    ret.await.map_err(|e| redact(e, &sendkey))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock;
+    use super::super::EventKind;
+    use super::*;
+    use crate::mock_server::MockResponse;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn send_should_fail_when_rejected() {
+        let (server, client) = mock(|request| {
+            if request.body.contains("desp=") {
+                MockResponse::json(200, json!({ "code": 40001, "message": "bad sendkey" }))
+            } else {
+                MockResponse::new(400, "")
+            }
+        })
+        .await;
+        let conf = ConfigServerChan {
+            sendkey: Some(String::from("SCT123")),
+            base_url: Some(server.url.clone()),
+        };
+        let err = send(&conf, &client, &Event::new(EventKind::UpdateFailed))
+            .await
+            .unwrap_err();
+
+        assert_eq!(server.requests()[0].path, "/SCT123.send");
+        assert_eq!(
+            server.requests()[0].headers["content-type"],
+            "application/x-www-form-urlencoded"
+        );
+        assert!(err.to_string().contains("bad sendkey"));
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::{redact, response_text, secret, truncate, Event};
+
+/// The longest text of a header block.
+const MAX_HEADER: usize = 150;
+/// The longest text of a section block.
+const MAX_TEXT: usize = 3000;
+/// The longest text of a section field.
+const MAX_FIELD: usize = 2000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigSlack {
+    /// The URL of the incoming webhook, or set it through env variable SLACK_WEBHOOK_URL.
+    pub webhook_url: Option<String>,
+}
+
+/// Send the event to a Slack channel with Block Kit.
+///
+/// The document of Slack incoming webhooks: https://api.slack.com/messaging/webhooks
+///
+/// # Example:
+/// ```rust
+/// slack::send(conf, client, &Event::new(EventKind::IpChanged)).await?;
+/// ```
+pub async fn send(conf: &ConfigSlack, client: &HttpClient, event: &Event) -> Result<(), DwdError> {
+    let url = secret(conf.webhook_url.as_ref(), "SLACK_WEBHOOK_URL", "slack.webhook_url")?;
+    let message = truncate(&escape(&event.message()), MAX_TEXT);
+    let mut blocks = vec![
+        json!({ "type": "header", "text": { "type": "plain_text", "text": truncate(event.title(), MAX_HEADER) } }),
+        json!({ "type": "section", "text": { "type": "mrkdwn", "text": message } }),
+    ];
+    let fields = event
+        .fields()
+        .into_iter()
+        .map(|(label, val)| {
+            let text = truncate(&format!("*{}*\n{}", label, escape(&val)), MAX_FIELD);
+            json!({ "type": "mrkdwn", "text": text })
+        })
+        .collect::<Vec<_>>();
+    if !fields.is_empty() {
+        blocks.push(json!({ "type": "section", "fields": fields }));
+    }
+    // The text is shown in notifications where blocks are not supported.
+    let body = json!({ "text": truncate(&event.message(), MAX_TEXT), "blocks": blocks });
+
+    let ret = match client.send(client.post(&url).json(&body)).await {
+        Ok(response) => response_text(response).await,
+        Err(e) => Err(e),
+    };
+    ret.map(|_| ()).map_err(|e| redact(e, &url))
+}
+
+/// Escape the control characters of Slack mrkdwn.
+fn escape(val: &str) -> String {
+    val.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock;
+    use super::super::EventKind;
+    use super::*;
+    use crate::mock_server::MockResponse;
+
+    #[tokio::test]
+    async fn send_should_post_blocks() {
+        let (server, client) = mock(|_| MockResponse::new(200, "ok")).await;
+        let conf = ConfigSlack {
+            webhook_url: Some(format!("{}services/T000/B000/XXX", server.url)),
+        };
+        let event = Event::new(EventKind::DetectionFailed)
+            .with_wan("default")
+            .with_error("<timeout>");
+        send(&conf, &client, &event).await.unwrap();
+
+        let body: serde_json::Value = serde_json::from_str(&server.requests()[0].body).unwrap();
+        assert_eq!(body["text"], "Detect the public IP of default failed: <timeout>");
+        assert_eq!(body["blocks"][0]["text"]["text"], "IP detection failed");
+        assert_eq!(body["blocks"][2]["fields"][1]["text"], "*Error*\n&lt;timeout&gt;");
+    }
+
+    #[tokio::test]
+    async fn send_should_truncate_long_blocks() {
+        let (server, client) = mock(|_| MockResponse::new(200, "ok")).await;
+        let conf = ConfigSlack {
+            webhook_url: Some(format!("{}services/T000/B000/XXX", server.url)),
+        };
+        let event = Event::new(EventKind::UpdateFailed)
+            .with_provider("name.com")
+            .with_error(&"x".repeat(5000));
+        send(&conf, &client, &event).await.unwrap();
+
+        let body: serde_json::Value = serde_json::from_str(&server.requests()[0].body).unwrap();
+        let count = |val: &serde_json::Value| val.as_str().unwrap().chars().count();
+        assert!(count(&body["blocks"][0]["text"]["text"]) <= MAX_HEADER);
+        assert_eq!(count(&body["blocks"][1]["text"]["text"]), MAX_TEXT);
+        assert_eq!(count(&body["blocks"][2]["fields"][1]["text"]), MAX_FIELD);
+    }
+}
@@ -0,0 +1,383 @@
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+use lazy_static::lazy_static;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::{env, net::IpAddr, sync::Mutex, time::Duration};
+use tokio::time;
+
+use super::super::error::{DwdError, ErrorKind};
+use super::super::shutdown;
+use super::{render, Event};
+
+const DEFAULT_SUBJECT: &str = "[DWD] {{message}}";
+const DEFAULT_BODY: &str = "{{message}}
+
+Event: {{event}}
+WAN: {{wan}}
+Old IP: {{old_ip}}
+New IP: {{new_ip}}
+Provider: {{provider}}
+Records: {{record}}
+Error: {{error}}
+Time: {{timestamp}}
+";
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// The events waiting for the daily digest.
+    static ref DIGEST: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain text, it should only be used for a local relay, the credentials are only sent to a loopback host.
+    None,
+    /// Upgrade the connection with STARTTLS, the default port is 587.
+    Starttls,
+    /// Implicit TLS, the default port is 465.
+    Tls,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigSmtp {
+    pub host: String,
+    pub port: Option<u16>,
+    /// `starttls` by default.
+    pub security: Option<SmtpSecurity>,
+    pub username: Option<String>,
+    /// The password, or set it through env variable SMTP_PASSWORD.
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// The templates of subject and body, they have the same variables as the webhook body.
+    pub subject: Option<String>,
+    pub body: Option<String>,
+    /// Collect the events and send them in one email every day at this local time, e.g. `08:00`.
+    pub digest_at: Option<String>,
+}
+
+/// Send the event by email.
+///
+/// # Example:
+/// ```rust
+/// smtp::send(conf, &Event::new(EventKind::IpChanged)).await?;
+/// ```
+pub async fn send(conf: &ConfigSmtp, event: &Event) -> Result<(), DwdError> {
+    let subject = render(conf.subject.as_deref().unwrap_or(DEFAULT_SUBJECT), event, |val| {
+        val.replace(['\r', '\n'], " ")
+    });
+    let body = render(conf.body.as_deref().unwrap_or(DEFAULT_BODY), event, |val| {
+        val.to_owned()
+    });
+    send_mail(conf, &subject, body).await
+}
+
+/// Queue the event for the daily digest.
+pub fn queue(event: &Event) {
+    DIGEST
+        .lock()
+        .expect("The lock of digest should not be poisoned.")
+        .push(event.to_owned());
+    debug!("Queued the event {} for the daily digest.", event.event.name());
+}
+
+/// Send the queued events in one email every day at `digest_at`, the pending events are sent on shutdown.
+pub async fn run_digest(conf: ConfigSmtp) {
+    let at = match digest_time(&conf) {
+        Ok(Some(val)) => val,
+        Ok(None) => return,
+        Err(err) => {
+            error!(target: "error", "{}", err);
+            return;
+        }
+    };
+
+    loop {
+        let now = Local::now();
+        let wait = (next_run(now, at) - now).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = time::sleep(wait) => send_digest(&conf).await,
+            _ = shutdown::wait() => {
+                send_digest(&conf).await;
+                return;
+            }
+        }
+    }
+}
+
+async fn send_digest(conf: &ConfigSmtp) {
+    let events = std::mem::take(&mut *DIGEST.lock().expect("The lock of digest should not be poisoned."));
+    if events.is_empty() {
+        debug!("No event is queued, skip the digest.");
+        return;
+    }
+
+    let subject = format!("[DWD] Daily digest: {} events", events.len());
+    let body = events
+        .iter()
+        .map(|event| {
+            render(conf.body.as_deref().unwrap_or(DEFAULT_BODY), event, |val| {
+                val.to_owned()
+            })
+        })
+        .collect::<Vec<_>>()
+        .join("\n----\n\n");
+    match send_mail(conf, &subject, body).await {
+        Ok(_) => info!("Sent the daily digest of {} events.", events.len()),
+        Err(e) => {
+            error!(target: "error", "Send the daily digest failed, error: {}", e.with_provider("smtp"));
+            // Put the events back, so they are sent with the next digest.
+            DIGEST
+                .lock()
+                .expect("The lock of digest should not be poisoned.")
+                .splice(0..0, events);
+        }
+    }
+}
+
+/// Refuse to send the credentials in plain text, unless the server is a local relay.
+pub fn check_security(conf: &ConfigSmtp) -> Result<(), DwdError> {
+    let is_local = conf.host == "localhost" || conf.host.parse::<IpAddr>().map(|ip| ip.is_loopback()).unwrap_or(false);
+    if conf.security == Some(SmtpSecurity::None) && conf.username.is_some() && !is_local {
+        return Err(DwdError::config(format!(
+            "The credentials of notify.smtp would be sent to {} in plain text, set security to starttls or tls.",
+            conf.host
+        )));
+    }
+    Ok(())
+}
+
+/// Parse `digest_at`, it is validated on start so a typo is not found a day later.
+pub fn digest_time(conf: &ConfigSmtp) -> Result<Option<NaiveTime>, DwdError> {
+    conf.digest_at
+        .as_ref()
+        .map(|val| {
+            NaiveTime::parse_from_str(val, "%H:%M").map_err(|_| {
+                DwdError::config(format!(
+                    "Invalid notify.smtp.digest_at {:?}, it should be like 08:00.",
+                    val
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// The next time to send the digest, it is today if the time has not passed yet, otherwise tomorrow.
+fn next_run(now: DateTime<Local>, at: NaiveTime) -> DateTime<Local> {
+    let today = now.naive_local().date().and_time(at);
+    [today, today + chrono::Duration::days(1)]
+        .iter()
+        .filter_map(|val| Local.from_local_datetime(val).earliest())
+        .find(|val| *val > now)
+        .unwrap_or_else(|| now + chrono::Duration::days(1))
+}
+
+async fn send_mail(conf: &ConfigSmtp, subject: &str, body: String) -> Result<(), DwdError> {
+    check_security(conf)?;
+    let mut message = Message::builder().from(parse_mailbox(&conf.from)?).subject(subject);
+    if conf.to.is_empty() {
+        return Err(DwdError::config("The config.notify.smtp.to should not be empty."));
+    }
+    for to in conf.to.iter() {
+        message = message.to(parse_mailbox(to)?);
+    }
+    let message = message
+        .header(ContentType::TEXT_PLAIN)
+        .body(body)
+        .map_err(|e| DwdError::config(format!("Build email failed: {}", e)))?;
+
+    let security = conf.security.unwrap_or(SmtpSecurity::Starttls);
+    let builder = match security {
+        SmtpSecurity::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&conf.host)),
+        SmtpSecurity::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&conf.host),
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&conf.host),
+    }
+    .map_err(smtp_error)?;
+    let port = conf.port.unwrap_or(match security {
+        SmtpSecurity::None => 25,
+        SmtpSecurity::Starttls => 587,
+        SmtpSecurity::Tls => 465,
+    });
+
+    let mut builder = builder.port(port).timeout(Some(TIMEOUT));
+    if let Some(username) = conf.username.as_ref() {
+        builder = builder.credentials(Credentials::new(username.to_owned(), password(conf)?));
+    }
+
+    builder.build().send(message).await.map_err(smtp_error)?;
+    Ok(())
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, DwdError> {
+    address
+        .parse()
+        .map_err(|e| DwdError::config(format!("Invalid email address {:?}: {}", address, e)))
+}
+
+fn password(conf: &ConfigSmtp) -> Result<String, DwdError> {
+    match conf.password.as_ref() {
+        Some(val) => Ok(val.to_owned()),
+        None => env::var("SMTP_PASSWORD").map_err(|_| DwdError::config("Please set env variable SMTP_PASSWORD.")),
+    }
+}
+
+/// Tell the rejected credentials from other errors, the server replies 535 for them.
+fn smtp_error(e: lettre::transport::smtp::Error) -> DwdError {
+    let code = e.status().map(|code| code.to_string());
+    let kind = match code.as_deref() {
+        Some("535") | Some("534") | Some("530") => ErrorKind::Auth,
+        _ if e.is_permanent() => ErrorKind::HttpStatus,
+        _ => ErrorKind::Transport,
+    };
+    DwdError::new(kind, format!("SMTP error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::EventKind;
+    use super::*;
+    use chrono::Timelike;
+    use std::sync::Arc;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    /// A local SMTP sink which accepts any mail, it returns the port and the received commands and data.
+    async fn start_sink() -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = received.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).await.unwrap() == 0 {
+                    return;
+                }
+                recorded.lock().unwrap().push(line.trim_end().to_owned());
+                let reply: &[u8] = if in_data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-sink\r\n250 AUTH PLAIN LOGIN\r\n"
+                } else if line.starts_with("AUTH") {
+                    b"235 authenticated\r\n"
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                    return;
+                } else {
+                    b"250 ok\r\n"
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+        });
+
+        (port, received)
+    }
+
+    fn conf(port: u16) -> ConfigSmtp {
+        ConfigSmtp {
+            host: String::from("127.0.0.1"),
+            port: Some(port),
+            security: Some(SmtpSecurity::None),
+            username: Some(String::from("dwd")),
+            password: Some(String::from("secret")),
+            from: String::from("DWD <dwd@example.com>"),
+            to: vec![String::from("a@example.com"), String::from("b@example.com")],
+            subject: None,
+            body: None,
+            digest_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn send_should_deliver_to_all_recipients() {
+        let (port, received) = start_sink().await;
+        let conf = conf(port);
+        let event = Event::new(EventKind::IpChanged)
+            .with_wan("wan1")
+            .with_record(Some("home.example.com"))
+            .with_ips("1.1.1.1".parse().ok(), "2.2.2.2".parse().ok());
+
+        send(&conf, &event).await.unwrap();
+
+        let received = received.lock().unwrap().join("\n");
+        assert!(received.contains("AUTH PLAIN"));
+        assert!(received.contains("MAIL FROM:<dwd@example.com>"));
+        assert!(received.contains("RCPT TO:<a@example.com>"));
+        assert!(received.contains("RCPT TO:<b@example.com>"));
+        assert!(received.contains("Subject: [DWD] The public IP of wan1 changed from 1.1.1.1 to 2.2.2.2."));
+        assert!(received.contains("Records: home.example.com"));
+    }
+
+    #[tokio::test]
+    async fn send_digest_should_keep_events_until_sent() {
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        queue(&Event::new(EventKind::IpChanged).with_wan("wan1"));
+        queue(&Event::new(EventKind::DetectionFailed).with_wan("wan2"));
+        send_digest(&conf(closed_port)).await;
+        assert_eq!(DIGEST.lock().unwrap().len(), 2);
+
+        let (port, received) = start_sink().await;
+        send_digest(&conf(port)).await;
+        assert!(DIGEST.lock().unwrap().is_empty());
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.iter().filter(|line| line.as_str() == "DATA").count(), 1);
+        let received = received.join("\n");
+        assert!(received.contains("Subject: [DWD] Daily digest: 2 events"));
+        assert!(received.contains("WAN: wan1"));
+        assert!(received.contains("WAN: wan2"));
+    }
+
+    #[test]
+    fn check_security_should_refuse_plain_text_credentials_to_remote_host() {
+        let mut conf = conf(25);
+        assert!(check_security(&conf).is_ok());
+
+        conf.host = String::from("smtp.example.com");
+        assert!(check_security(&conf).is_err());
+
+        conf.security = Some(SmtpSecurity::Starttls);
+        assert!(check_security(&conf).is_ok());
+    }
+
+    #[test]
+    fn next_run_should_be_today_or_tomorrow() {
+        let at = NaiveTime::from_hms_opt(8, 0, 0).unwrap();
+        let morning = Local.with_ymd_and_hms(2024, 3, 1, 7, 0, 0).unwrap();
+        let evening = Local.with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap();
+
+        assert_eq!(
+            next_run(morning, at),
+            Local.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()
+        );
+        assert_eq!(
+            next_run(evening, at),
+            Local.with_ymd_and_hms(2024, 3, 2, 8, 0, 0).unwrap()
+        );
+        assert_eq!(next_run(evening, at).hour(), 8);
+    }
+}
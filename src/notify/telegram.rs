@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::{api_url, redact, response_text, secret, truncate, Event};
+
+const BASE_URL: &str = "https://api.telegram.org";
+/// The longest text of a message.
+const MAX_LENGTH: usize = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigTelegram {
+    /// The token of the bot, or set it through env variable TELEGRAM_BOT_TOKEN.
+    pub bot_token: Option<String>,
+    pub chat_id: String,
+    /// The base URL of Bot API, e.g. a self-hosted Bot API server.
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse {
+    ok: bool,
+    description: Option<String>,
+}
+
+/// Send the event to a Telegram chat as a MarkdownV2 message, or a truncated plain text message if it is too long.
+///
+/// The document of Telegram Bot API: https://core.telegram.org/bots/api#sendmessage
+///
+/// # Example:
+/// ```rust
+/// telegram::send(conf, client, &Event::new(EventKind::IpChanged)).await?;
+/// ```
+pub async fn send(conf: &ConfigTelegram, client: &HttpClient, event: &Event) -> Result<(), DwdError> {
+    let token = secret(conf.bot_token.as_ref(), "TELEGRAM_BOT_TOKEN", "telegram.bot_token")?;
+    send_inner(conf, client, event, &token)
+        .await
+        .map_err(|e| redact(e, &token))
+}
+
+async fn send_inner(conf: &ConfigTelegram, client: &HttpClient, event: &Event, token: &str) -> Result<(), DwdError> {
+    let url = api_url(
+        conf.base_url.as_deref().unwrap_or(BASE_URL),
+        &format!("bot{}/sendMessage", token),
+    );
+    let text = markdown(event);
+    // A truncated MarkdownV2 text may end within an entity, so the long one is sent as plain text.
+    let body = if text.chars().count() > MAX_LENGTH {
+        json!({
+            "chat_id": conf.chat_id,
+            "text": truncate(&format!("{}\n{}", event.title(), event.text()), MAX_LENGTH),
+            "disable_web_page_preview": true,
+        })
+    } else {
+        json!({
+            "chat_id": conf.chat_id,
+            "text": text,
+            "parse_mode": "MarkdownV2",
+            "disable_web_page_preview": true,
+        })
+    };
+
+    let response = client.send(client.post(&url).json(&body)).await?;
+    let ret: ApiResponse = serde_json::from_str(&response_text(response).await?)?;
+    if !ret.ok {
+        return Err(DwdError::parse(format!(
+            "Telegram rejected the message: {}",
+            ret.description.unwrap_or_default()
+        )));
+    }
+    Ok(())
+}
+
+fn markdown(event: &Event) -> String {
+    let mut text = format!("*{}*\n{}", escape(event.title()), escape(&event.message()));
+    for (label, val) in event.fields() {
+        text.push_str(&format!(
+            "\n*{}*: `{}`",
+            escape(label),
+            val.replace('\\', "\\\\").replace('`', "\\`")
+        ));
+    }
+    text
+}
+
+/// Escape the characters which are reserved by MarkdownV2.
+fn escape(val: &str) -> String {
+    let mut escaped = String::with_capacity(val.len());
+    for c in val.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock;
+    use super::super::EventKind;
+    use super::*;
+    use crate::mock_server::MockResponse;
+
+    #[tokio::test]
+    async fn send_should_post_markdown_message() {
+        let (server, client) = mock(|_| MockResponse::json(200, json!({ "ok": true }))).await;
+        let conf = ConfigTelegram {
+            bot_token: Some(String::from("123:abc")),
+            chat_id: String::from("42"),
+            base_url: Some(server.url.clone()),
+        };
+        let event = Event::new(EventKind::IpChanged)
+            .with_wan("wan1")
+            .with_ips("1.1.1.1".parse().ok(), "2.2.2.2".parse().ok());
+        send(&conf, &client, &event).await.unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/bot123:abc/sendMessage");
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["chat_id"], "42");
+        assert_eq!(body["parse_mode"], "MarkdownV2");
+        assert_eq!(
+            body["text"],
+            "*IP changed*\nThe public IP of wan1 changed from 1\\.1\\.1\\.1 to 2\\.2\\.2\\.2\\.\n*WAN*: `wan1`\n\
+             *Old IP*: `1.1.1.1`\n*New IP*: `2.2.2.2`"
+        );
+    }
+
+    #[tokio::test]
+    async fn send_should_truncate_long_message() {
+        let (server, client) = mock(|_| MockResponse::json(200, json!({ "ok": true }))).await;
+        let conf = ConfigTelegram {
+            bot_token: Some(String::from("123:abc")),
+            chat_id: String::from("42"),
+            base_url: Some(server.url.clone()),
+        };
+        let event = Event::new(EventKind::UpdateFailed)
+            .with_provider("name.com")
+            .with_error(&"x".repeat(5000));
+        send(&conf, &client, &event).await.unwrap();
+
+        let body: serde_json::Value = serde_json::from_str(&server.requests()[0].body).unwrap();
+        assert!(body.get("parse_mode").is_none());
+        let text = body["text"].as_str().unwrap();
+        assert_eq!(text.chars().count(), MAX_LENGTH);
+        assert!(text.starts_with("DNS update failed\n") && text.ends_with('…'));
+    }
+}
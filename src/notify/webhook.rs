@@ -0,0 +1,140 @@
+use log::trace;
+use reqwest::{header::HeaderName, header::HeaderValue, Method};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::super::error::DwdError;
+use super::super::http::HttpClient;
+use super::{render, response_text, Event};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigWebhook {
+    pub url: String,
+    /// The HTTP method, `POST` by default.
+    pub method: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// The template of body, the variables like `{{new_ip}}` are escaped as JSON strings. The event is sent as a
+    /// JSON object by default.
+    pub body: Option<String>,
+}
+
+/// Send the event to a generic webhook.
+///
+/// # Example:
+/// ```rust
+/// webhook::send(conf, client, &Event::new(EventKind::IpChanged)).await?;
+/// ```
+pub async fn send(conf: &ConfigWebhook, client: &HttpClient, event: &Event) -> Result<(), DwdError> {
+    let method = Method::from_bytes(conf.method.as_deref().unwrap_or("POST").to_uppercase().as_bytes())
+        .map_err(|_| DwdError::config(format!("Invalid webhook method {:?}.", conf.method)))?;
+
+    let body = match conf.body.as_ref() {
+        Some(template) => render(template, event, json_escape),
+        None => default_body(event)?,
+    };
+
+    let mut builder = client.request(method, &conf.url);
+    if !conf
+        .headers
+        .keys()
+        .any(|name| name.eq_ignore_ascii_case("content-type"))
+    {
+        builder = builder.header("Content-Type", "application/json");
+    }
+    for (name, value) in conf.headers.iter() {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| DwdError::config(format!("Invalid webhook header {:?}.", name)))?;
+        builder = builder.header(name, HeaderValue::from_str(value)?);
+    }
+
+    let response = client.send(builder.body(body)).await?;
+    trace!("Webhook responded with {}", response.status());
+
+    response_text(response).await?;
+    Ok(())
+}
+
+/// The event as a JSON object with a `message` field.
+fn default_body(event: &Event) -> Result<String, DwdError> {
+    let mut body = serde_json::to_value(event)?;
+    body["message"] = serde_json::Value::String(event.message());
+    Ok(body.to_string())
+}
+
+/// Escape the value for a JSON string, the quotes around it are not included.
+fn json_escape(val: &str) -> String {
+    let quoted = serde_json::Value::String(val.to_owned()).to_string();
+    quoted[1..quoted.len() - 1].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::EventKind;
+    use super::*;
+    use crate::http::{ConfigBind, ConfigHttp};
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn event() -> Event {
+        Event::new(EventKind::UpdateFailed)
+            .with_provider("name.com")
+            .with_record(Some("home.example.com"))
+            .with_ips(None, "1.1.1.1".parse().ok())
+            .with_error("Bad \"token\"")
+    }
+
+    #[tokio::test]
+    async fn send_should_render_template() {
+        let server = MockServer::start(|_| MockResponse::new(204, "")).await;
+        let mut headers = HashMap::new();
+        headers.insert(String::from("X-Token"), String::from("secret"));
+        let conf = ConfigWebhook {
+            url: format!("{}hooks/dwd", server.url),
+            method: Some(String::from("put")),
+            headers,
+            body: Some(String::from(r#"{"text": "{{record}}: {{error}}"}"#)),
+        };
+
+        let client = HttpClient::new(&ConfigHttp::default(), &ConfigBind::default()).unwrap();
+        send(&conf, &client, &event()).await.unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/hooks/dwd");
+        assert_eq!(request.headers["x-token"], "secret");
+        assert_eq!(request.headers["content-type"], "application/json");
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["text"], "home.example.com: Bad \"token\"");
+    }
+
+    #[tokio::test]
+    async fn send_should_post_event_by_default() {
+        let server = MockServer::start(|_| MockResponse::new(500, "oops")).await;
+        let conf = ConfigWebhook {
+            url: server.url.clone(),
+            method: None,
+            headers: HashMap::new(),
+            body: None,
+        };
+
+        let client = HttpClient::new(
+            &ConfigHttp {
+                retries: Some(0),
+                ..Default::default()
+            },
+            &ConfigBind::default(),
+        )
+        .unwrap();
+        assert!(send(&conf, &client, &event()).await.is_err());
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "POST");
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["event"], "update_failed");
+        assert_eq!(body["new_ip"], "1.1.1.1");
+        assert_eq!(
+            body["message"],
+            "Update home.example.com on name.com to 1.1.1.1 failed: Bad \"token\""
+        );
+    }
+}
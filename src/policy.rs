@@ -0,0 +1,324 @@
+//! This is the policy layer between IP detection and DNS update, it decides whether a detected IP deserves to be
+//! published, so a short failover of the ISP or a misbehaving IP provider will not flip the DNS records.
+
+use ipnet::IpNet;
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use super::http::{get, HttpClient};
+
+const ASN_LOOKUP_URL: &str = "https://api.iptoasn.com/v1/as/ip/{ip}";
+const FLIP_WINDOW: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigPolicy {
+    /// How many consecutive identical observations are required before a new IP is published.
+    #[serde(default = "default_confirmations")]
+    pub confirmations: u32,
+    /// The maximum times of IP changes could be published within an hour.
+    pub max_changes_per_hour: Option<u32>,
+    /// Only IPs in these CIDR ranges are allowed to be published, e.g. `["203.0.113.0/24"]`.
+    #[serde(default)]
+    pub allowed_cidrs: Vec<String>,
+    /// Only IPs announced by these ASNs are allowed to be published, e.g. `[4134]`.
+    #[serde(default)]
+    pub allowed_asns: Vec<u32>,
+    /// The API used to look up ASN of IP, `{ip}` will be replaced with the IP.
+    pub asn_lookup_url: Option<String>,
+}
+
+impl Default for ConfigPolicy {
+    fn default() -> Self {
+        ConfigPolicy {
+            confirmations: default_confirmations(),
+            max_changes_per_hour: None,
+            allowed_cidrs: Vec::new(),
+            allowed_asns: Vec::new(),
+            asn_lookup_url: None,
+        }
+    }
+}
+
+fn default_confirmations() -> u32 {
+    1
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// The IP is the same as the published one, nothing to do.
+    Unchanged,
+    /// The IP is new but has not been observed enough times.
+    Pending {
+        observations: u32,
+        required: u32,
+    },
+    /// The IP is refused, with the reason.
+    Rejected(String),
+    Publish,
+}
+
+#[derive(Debug, Deserialize)]
+struct AsnInfo {
+    as_number: u32,
+}
+
+#[derive(Clone)]
+pub struct Policy {
+    conf: ConfigPolicy,
+    allowed_nets: Vec<IpNet>,
+    candidate: Option<IpAddr>,
+    observations: u32,
+    /// The IP published last time since started.
+    last_published: Option<IpAddr>,
+    changes: VecDeque<Instant>,
+    asn_cache: HashMap<IpAddr, u32>,
+}
+
+impl Policy {
+    pub fn new(conf: &ConfigPolicy) -> Result<Self, Box<dyn Error>> {
+        let mut allowed_nets = Vec::new();
+        for cidr in conf.allowed_cidrs.iter() {
+            let net = cidr
+                .parse::<IpNet>()
+                .map_err(|e| format!("[policy] Invalid CIDR in allowed_cidrs. (cidr: {}, error: {})", cidr, e))?;
+            allowed_nets.push(net);
+        }
+
+        Ok(Policy {
+            conf: conf.to_owned(),
+            allowed_nets,
+            candidate: None,
+            observations: 0,
+            last_published: None,
+            changes: VecDeque::new(),
+            asn_cache: HashMap::new(),
+        })
+    }
+
+    /// Check the detected IP against the policy, `published` is the IP currently in the DNS records.
+    ///
+    /// `Decision::Publish` changes nothing, call `published` once the DNS records are updated.
+    pub async fn check(&mut self, client: &HttpClient, ip: &IpAddr, published: Option<&IpAddr>) -> Decision {
+        if !self.allowed_nets.is_empty() && !self.allowed_nets.iter().any(|net| net.contains(ip)) {
+            return Decision::Rejected(format!("{} is not in the allowed CIDR ranges", ip));
+        }
+
+        if !self.conf.allowed_asns.is_empty() {
+            match self.lookup_asn(client, ip).await {
+                Ok(asn) if self.conf.allowed_asns.contains(&asn) => (),
+                Ok(asn) => return Decision::Rejected(format!("{} is announced by AS{} which is not allowed", ip, asn)),
+                Err(err) => return Decision::Rejected(format!("looking up ASN of {} failed: {}", ip, err)),
+            }
+        }
+
+        self.decide(ip, published, Instant::now())
+    }
+
+    fn decide(&mut self, ip: &IpAddr, published: Option<&IpAddr>, now: Instant) -> Decision {
+        if published == Some(ip) {
+            self.candidate = None;
+            self.observations = 0;
+            return Decision::Unchanged;
+        }
+
+        if self.candidate.as_ref() == Some(ip) {
+            self.observations = self.observations.saturating_add(1);
+        } else {
+            self.candidate = Some(ip.to_owned());
+            self.observations = 1;
+        }
+
+        let required = self.conf.confirmations.max(1);
+        if self.observations < required {
+            return Decision::Pending {
+                observations: self.observations,
+                required,
+            };
+        }
+
+        while let Some(changed_at) = self.changes.front() {
+            if now.duration_since(*changed_at) >= FLIP_WINDOW {
+                self.changes.pop_front();
+            } else {
+                break;
+            }
+        }
+        if let Some(max) = self.conf.max_changes_per_hour {
+            if self.changes.len() as u32 >= max {
+                return Decision::Rejected(format!("{} IP changes have been published within an hour", max));
+            }
+        }
+
+        Decision::Publish
+    }
+
+    /// Record the IP is published, so the change is counted into the flips of the last hour.
+    pub fn published(&mut self, ip: &IpAddr, now: Instant) {
+        // The first publishing after started is not a flip.
+        if self.last_published.map(|val| val != *ip).unwrap_or(false) {
+            self.changes.push_back(now);
+        }
+        self.last_published = Some(ip.to_owned());
+        self.candidate = None;
+        self.observations = 0;
+    }
+
+    async fn lookup_asn(&mut self, client: &HttpClient, ip: &IpAddr) -> Result<u32, Box<dyn Error>> {
+        if let Some(asn) = self.asn_cache.get(ip) {
+            return Ok(*asn);
+        }
+
+        let url = self
+            .conf
+            .asn_lookup_url
+            .as_deref()
+            .unwrap_or(ASN_LOOKUP_URL)
+            .replace("{ip}", &ip.to_string());
+        let response = get(client, &url).await?;
+        trace!("Response content: {}", response.trim_end());
+
+        let info = serde_json::from_str::<AsnInfo>(&response)?;
+        debug!("The IP {} is announced by AS{}", ip, info.as_number);
+        self.asn_cache.insert(ip.to_owned(), info.as_number);
+
+        Ok(info.as_number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn decide_should_require_consecutive_observations() {
+        let conf = ConfigPolicy {
+            confirmations: 3,
+            ..Default::default()
+        };
+        let mut policy = Policy::new(&conf).unwrap();
+        let now = Instant::now();
+        let published = ip("1.1.1.1");
+
+        assert_eq!(
+            policy.decide(&ip("2.2.2.2"), Some(&published), now),
+            Decision::Pending {
+                observations: 1,
+                required: 3
+            }
+        );
+        // A different IP in between resets the counter.
+        assert!(matches!(
+            policy.decide(&ip("3.3.3.3"), Some(&published), now),
+            Decision::Pending { observations: 1, .. }
+        ));
+        assert!(matches!(
+            policy.decide(&ip("3.3.3.3"), Some(&published), now),
+            Decision::Pending { observations: 2, .. }
+        ));
+        assert_eq!(policy.decide(&ip("3.3.3.3"), Some(&published), now), Decision::Publish);
+        assert_eq!(policy.decide(&published, Some(&published), now), Decision::Unchanged);
+    }
+
+    #[test]
+    fn decide_should_limit_flips_per_hour() {
+        let conf = ConfigPolicy {
+            max_changes_per_hour: Some(2),
+            ..Default::default()
+        };
+        let mut policy = Policy::new(&conf).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(policy.decide(&ip("1.1.1.1"), None, now), Decision::Publish);
+        policy.published(&ip("1.1.1.1"), now);
+        assert_eq!(
+            policy.decide(&ip("2.2.2.2"), Some(&ip("1.1.1.1")), now),
+            Decision::Publish
+        );
+        policy.published(&ip("2.2.2.2"), now);
+        assert_eq!(
+            policy.decide(&ip("1.1.1.1"), Some(&ip("2.2.2.2")), now),
+            Decision::Publish
+        );
+        policy.published(&ip("1.1.1.1"), now);
+        assert!(matches!(
+            policy.decide(&ip("2.2.2.2"), Some(&ip("1.1.1.1")), now),
+            Decision::Rejected(_)
+        ));
+        assert_eq!(
+            policy.decide(&ip("2.2.2.2"), Some(&ip("1.1.1.1")), now + FLIP_WINDOW),
+            Decision::Publish
+        );
+    }
+
+    #[test]
+    fn decide_should_keep_state_until_published() {
+        let conf = ConfigPolicy {
+            confirmations: 2,
+            max_changes_per_hour: Some(1),
+            ..Default::default()
+        };
+        let mut policy = Policy::new(&conf).unwrap();
+        let now = Instant::now();
+        policy.published(&ip("1.1.1.1"), now);
+
+        assert!(matches!(
+            policy.decide(&ip("2.2.2.2"), Some(&ip("1.1.1.1")), now),
+            Decision::Pending { observations: 1, .. }
+        ));
+        assert_eq!(
+            policy.decide(&ip("2.2.2.2"), Some(&ip("1.1.1.1")), now),
+            Decision::Publish
+        );
+        // The update failed, the retry neither waits for confirmations again nor is limited by flips.
+        assert_eq!(
+            policy.decide(&ip("2.2.2.2"), Some(&ip("1.1.1.1")), now),
+            Decision::Publish
+        );
+
+        policy.published(&ip("2.2.2.2"), now);
+        assert!(matches!(
+            policy.decide(&ip("1.1.1.1"), Some(&ip("2.2.2.2")), now),
+            Decision::Pending { observations: 1, .. }
+        ));
+        assert!(matches!(
+            policy.decide(&ip("1.1.1.1"), Some(&ip("2.2.2.2")), now),
+            Decision::Rejected(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn check_should_reject_ip_out_of_allowed_cidrs() {
+        let conf = ConfigPolicy {
+            allowed_cidrs: vec![String::from("203.0.113.0/24"), String::from("2001:db8::/32")],
+            ..Default::default()
+        };
+        let mut policy = Policy::new(&conf).unwrap();
+        let client = HttpClient::new(&Default::default(), &Default::default()).unwrap();
+
+        assert_eq!(policy.check(&client, &ip("203.0.113.9"), None).await, Decision::Publish);
+        assert_eq!(policy.check(&client, &ip("2001:db8::1"), None).await, Decision::Publish);
+        assert!(matches!(
+            policy.check(&client, &ip("1.1.1.1"), None).await,
+            Decision::Rejected(_)
+        ));
+    }
+
+    #[test]
+    fn new_should_fail_for_invalid_cidr() {
+        let conf = ConfigPolicy {
+            allowed_cidrs: vec![String::from("203.0.113.0/33")],
+            ..Default::default()
+        };
+        assert!(Policy::new(&conf).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+//! With native IPv6 every LAN host owns a global address under the prefix delegated by the ISP, this module detects
+//! the current prefix and publishes the addresses of the configured hosts when the prefix changes.
+
+use ipnet::Ipv6Net;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs,
+    net::{IpAddr, Ipv6Addr},
+    time::Duration,
+};
+use tokio::time;
+
+use super::control;
+use super::dns_provider::{self, ConfigDnsTarget};
+use super::http::HttpClient;
+use super::ip_provider;
+use super::shutdown;
+use super::systemd;
+use super::util::{error_style, info_style, ip_scope, success_style, IpScope};
+
+const IF_INET6_PATH: &str = "/proc/net/if_inet6";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigPrefixDelegation {
+    /// Read the prefix from the global IPv6 address of this interface, if it is not set the IPv6 returned by
+    /// `ip_provider` is used.
+    pub interface: Option<String>,
+    #[serde(default = "default_prefix_len")]
+    pub prefix_len: u8,
+    pub hosts: Vec<ConfigPrefixHost>,
+}
+
+fn default_prefix_len() -> u8 {
+    64
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigPrefixHost {
+    pub name: String,
+    /// The static interface identifier of the host, e.g. `::1234`.
+    pub suffix: Option<String>,
+    /// The MAC address of the host, the interface identifier will be generated in modified EUI-64 format.
+    pub mac: Option<String>,
+
+    #[serde(flatten)]
+    pub dns: ConfigDnsTarget,
+}
+
+impl ConfigPrefixHost {
+    /// Get the interface identifier of the host, only the bits after the prefix are kept.
+    pub fn interface_id(&self, prefix_len: u8) -> Result<u128, Box<dyn Error>> {
+        if prefix_len > 128 {
+            return Err(format!("The prefix_len {} is invalid, it should be at most 128.", prefix_len).into());
+        }
+
+        let id = match (self.suffix.as_ref(), self.mac.as_ref()) {
+            (Some(suffix), None) => u128::from(
+                suffix
+                    .parse::<Ipv6Addr>()
+                    .map_err(|_| format!("The suffix of host {} is not a valid IPv6 suffix.", self.name))?,
+            ),
+            (None, Some(mac)) => eui64(mac).ok_or(format!("The mac of host {} is invalid.", self.name))?,
+            _ => return Err(format!("Either suffix or mac should be configured for host {}.", self.name).into()),
+        };
+
+        if prefix_len > 0 && id >> (128 - prefix_len as u32) != 0 {
+            return Err(format!(
+                "The suffix of host {} overlaps with the /{} prefix.",
+                self.name, prefix_len
+            )
+            .into());
+        }
+
+        Ok(id)
+    }
+}
+
+/// Generate the modified EUI-64 interface identifier from a MAC address, as defined in RFC 4291.
+pub fn eui64(mac: &str) -> Option<u128> {
+    let bytes = mac
+        .split([':', '-'])
+        .map(|part| u8::from_str_radix(part, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    if bytes.len() != 6 {
+        return None;
+    }
+
+    let id = [
+        bytes[0] ^ 0x02,
+        bytes[1],
+        bytes[2],
+        0xff,
+        0xfe,
+        bytes[3],
+        bytes[4],
+        bytes[5],
+    ];
+    Some(u64::from_be_bytes(id) as u128)
+}
+
+/// Combine the prefix and the interface identifier into an address.
+pub fn host_address(prefix: &Ipv6Net, interface_id: u128) -> Ipv6Addr {
+    Ipv6Addr::from(u128::from(prefix.network()) | interface_id)
+}
+
+/// Find the first global and permanent IPv6 address of the interface from the content of /proc/net/if_inet6.
+///
+/// Each line is like `20010db8000000000000000000000001 02 40 00 80 eth0`, the columns are address, index,
+/// prefix length, scope, flags and the interface name.
+fn parse_if_inet6(content: &str, interface: &str) -> Option<Ipv6Addr> {
+    const IFA_F_TEMPORARY: u32 = 0x01;
+    const IFA_F_DEPRECATED: u32 = 0x20;
+
+    content.lines().find_map(|line| {
+        let columns = line.split_whitespace().collect::<Vec<_>>();
+        if columns.len() != 6 || columns[5] != interface {
+            return None;
+        }
+
+        let flags = u32::from_str_radix(columns[4], 16).ok()?;
+        if flags & (IFA_F_TEMPORARY | IFA_F_DEPRECATED) != 0 {
+            return None;
+        }
+
+        let ip = Ipv6Addr::from(u128::from_str_radix(columns[0], 16).ok()?);
+        if ip_scope(&IpAddr::V6(ip)) != IpScope::Global {
+            return None;
+        }
+
+        Some(ip)
+    })
+}
+
+/// Detect the current delegated prefix from the interface or the IP providers.
+pub async fn detect_prefix(
+    conf: &ConfigPrefixDelegation,
+    client: &HttpClient,
+    ip_provider: &[String],
+) -> Result<Ipv6Net, Box<dyn Error>> {
+    let ip = match conf.interface.as_ref() {
+        Some(interface) => {
+            let content = fs::read_to_string(IF_INET6_PATH)
+                .map_err(|e| format!("Read {} failed. (error: {})", IF_INET6_PATH, e))?;
+            parse_if_inet6(&content, interface)
+                .ok_or(format!("No global IPv6 address found on interface {}.", interface))?
+        }
+        None => {
+            let (_, ip) = ip_provider::get_ip_by_fallback(client, ip_provider)
+                .await
+                .ok_or("All IP providers failed.")?;
+            match ip.parse::<IpAddr>()? {
+                IpAddr::V6(ip) if ip_scope(&IpAddr::V6(ip)) == IpScope::Global => ip,
+                ip => {
+                    return Err(format!(
+                        "The IP providers returned {} which is not a global IPv6, please configure an interface.",
+                        ip
+                    )
+                    .into())
+                }
+            }
+        }
+    };
+
+    Ok(Ipv6Net::new(ip, conf.prefix_len)?.trunc())
+}
+
+/// Keep publishing the addresses of hosts under the delegated prefix.
+pub async fn run(conf: ConfigPrefixDelegation, client: HttpClient, ip_provider: Vec<String>, interval: u32) {
+    let mut hosts = Vec::new();
+    for host in conf.hosts.iter() {
+        match host.interface_id(conf.prefix_len) {
+            Ok(id) => hosts.push((host, id)),
+            Err(err) => error!(target: "error", "[prefix_delegation] {}", err),
+        }
+    }
+    if hosts.is_empty() {
+        error!(target: "error", "[prefix_delegation] No valid host is configured, {}", error_style("exit."));
+        systemd::loop_exited("prefix delegation", "No valid host of prefix delegation is configured.");
+        return;
+    }
+
+    let mut last_prefix: Option<Ipv6Net> = None;
+    let mut timer = time::interval(Duration::from_secs(interval as u64));
+    let mut trigger = control::subscribe();
+    let mut cycled = false;
+    systemd::loop_started("prefix delegation", interval);
+    loop {
+        if cycled {
+            systemd::cycle_done("prefix delegation", interval);
+        }
+        cycled = true;
+
+        let force = tokio::select! {
+            _ = timer.tick() => false,
+            Some(force) = trigger.changed() => force,
+            _ = shutdown::wait() => return,
+        };
+        if control::is_paused() {
+            debug!("[prefix_delegation] Updates are paused, skip.");
+            continue;
+        }
+
+        let prefix = match detect_prefix(&conf, &client, &ip_provider).await {
+            Ok(val) => val,
+            Err(err) => {
+                error!(target: "error", event = "detection_failed"; "[prefix_delegation] Detect IPv6 prefix failed: {}", err);
+                continue;
+            }
+        };
+
+        if last_prefix == Some(prefix) && !force {
+            debug!("The IPv6 prefix {} is not changed, skip.", prefix);
+            continue;
+        }
+        info!(target: "success", event = "prefix_detected", prefix:% = prefix; "Successfully got current IPv6 prefix: {}", success_style(&prefix));
+
+        let mut settled = true;
+        for (host, id) in hosts.iter() {
+            let ip = IpAddr::V6(host_address(&prefix, *id));
+            info!(
+                event = "host_publishing",
+                record = host.name.as_str(),
+                ip:% = ip;
+                "Publishing {} for host {} ...",
+                info_style(&ip),
+                info_style(&host.name)
+            );
+            settled &= dns_provider::update_dns_for_all(&host.dns, &client, &ip).await;
+        }
+
+        // Keep the prefix unpublished if any host failed, so the hosts are retried in the next cycle.
+        if settled {
+            last_prefix = Some(prefix);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(suffix: Option<&str>, mac: Option<&str>) -> ConfigPrefixHost {
+        ConfigPrefixHost {
+            name: String::from("nas"),
+            suffix: suffix.map(String::from),
+            mac: mac.map(String::from),
+            dns: ConfigDnsTarget::default(),
+        }
+    }
+
+    #[test]
+    fn eui64_should_works() {
+        assert_eq!(eui64("00:1a:2b:3c:4d:5e"), Some(0x021a_2bff_fe3c_4d5e));
+        assert_eq!(eui64("02-1A-2B-3C-4D-5E"), Some(0x001a_2bff_fe3c_4d5e));
+        assert_eq!(eui64("00:1a:2b:3c:4d"), None);
+        assert_eq!(eui64("zz:1a:2b:3c:4d:5e"), None);
+    }
+
+    #[test]
+    fn host_address_should_combine_prefix_and_interface_id() {
+        let prefix = "2001:db8:1:2::/64".parse::<Ipv6Net>().unwrap();
+
+        let id = host(Some("::1234"), None).interface_id(64).unwrap();
+        assert_eq!(
+            host_address(&prefix, id),
+            "2001:db8:1:2::1234".parse::<Ipv6Addr>().unwrap()
+        );
+
+        let id = host(None, Some("00:1a:2b:3c:4d:5e")).interface_id(64).unwrap();
+        assert_eq!(
+            host_address(&prefix, id),
+            "2001:db8:1:2:21a:2bff:fe3c:4d5e".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn interface_id_should_fail_for_invalid_config() {
+        assert!(host(None, None).interface_id(64).is_err());
+        assert!(host(Some("::1"), Some("00:1a:2b:3c:4d:5e")).interface_id(64).is_err());
+        assert!(host(Some("1::1"), None).interface_id(64).is_err());
+        assert!(host(Some("::1:0:0:1"), None).interface_id(56).is_ok());
+        assert!(host(Some("::1"), None).interface_id(129).is_err());
+    }
+
+    #[test]
+    fn parse_if_inet6_should_find_permanent_global_address() {
+        let content = "\
+fe80000000000000021a2bfffe3c4d5e 02 40 20 80     eth0
+24000000000000010000000000000099 02 40 00 01     eth0
+24000000000000010000000000000001 02 40 00 80     eth0
+24000000000000020000000000000001 03 40 00 80     eth1
+00000000000000000000000000000001 01 80 10 80       lo
+";
+        assert_eq!(parse_if_inet6(content, "eth0"), "2400:0:0:1::1".parse().ok());
+        assert_eq!(parse_if_inet6(content, "eth1"), "2400:0:0:2::1".parse().ok());
+        assert_eq!(parse_if_inet6(content, "lo"), None);
+    }
+}
@@ -0,0 +1,216 @@
+//! Per-provider rate limiting, a token bucket throttles the requests sent to a provider, and the provider is blocked
+//! for a while when it responds with 429 or tells us the quota is used up through `X-RateLimit-*` headers.
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use reqwest::header::HeaderMap;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The longest wait, so a bogus header could not block a provider forever.
+const MAX_WAIT: Duration = Duration::from_secs(24 * 3600);
+
+lazy_static! {
+    static ref LIMITERS: Mutex<HashMap<String, Limiter>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug)]
+struct Limiter {
+    /// The requests allowed per second, `None` means no throttling.
+    rate: Option<f64>,
+    capacity: f64,
+    tokens: f64,
+    updated_at: Instant,
+    blocked_until: Option<Instant>,
+}
+
+impl Limiter {
+    fn new(rate: Option<f64>, burst: u32, now: Instant) -> Self {
+        let capacity = burst.max(1) as f64;
+        Limiter {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated_at: now,
+            blocked_until: None,
+        }
+    }
+
+    /// Take a token, return how long to wait if there is none or the provider is blocked.
+    fn acquire(&mut self, now: Instant) -> Option<Duration> {
+        if let Some(blocked_until) = self.blocked_until {
+            if blocked_until > now {
+                return Some(blocked_until - now);
+            }
+            self.blocked_until = None;
+        }
+
+        let rate = match self.rate {
+            Some(val) if val > 0.0 => val,
+            _ => return None,
+        };
+
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(self.capacity);
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            seconds((1.0 - self.tokens) / rate).or(Some(MAX_WAIT))
+        }
+    }
+}
+
+/// Take a token for the provider, return how long to wait before trying again if it should not send a request now.
+pub fn acquire(provider: &str, rate: Option<f64>, burst: u32) -> Option<Duration> {
+    let now = Instant::now();
+    let mut limiters = LIMITERS
+        .lock()
+        .expect("The lock of rate limiters should not be poisoned.");
+    limiters
+        .entry(provider.to_owned())
+        .or_insert_with(|| Limiter::new(rate, burst, now))
+        .acquire(now)
+}
+
+/// Block the provider for the duration, the longer one wins if it is already blocked.
+pub fn block(provider: &str, duration: Duration) {
+    let now = Instant::now();
+    let mut limiters = LIMITERS
+        .lock()
+        .expect("The lock of rate limiters should not be poisoned.");
+    let limiter = limiters
+        .entry(provider.to_owned())
+        .or_insert_with(|| Limiter::new(None, 1, now));
+    let until = now + duration.min(MAX_WAIT);
+    if limiter.blocked_until.map(|val| val < until).unwrap_or(true) {
+        limiter.blocked_until = Some(until);
+    }
+}
+
+/// Parse `Retry-After` header, it is either seconds or an HTTP date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get("retry-after")?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds).min(MAX_WAIT));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc);
+    let now: DateTime<Utc> = SystemTime::now().into();
+    Some((date - now).to_std().unwrap_or_default().min(MAX_WAIT))
+}
+
+/// Parse `X-RateLimit-Remaining` and `X-RateLimit-Reset` headers, return how long to wait when the quota is used up.
+///
+/// Some providers send the reset time as a unix timestamp while others send the seconds to wait, so values larger
+/// than a year are treated as timestamps.
+pub fn quota_reset(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(format!("x-ratelimit-{}", name))
+            .or_else(|| headers.get(format!("ratelimit-{}", name)))
+            .and_then(|val| val.to_str().ok())
+            .and_then(|val| val.trim().parse::<f64>().ok())
+    };
+
+    if header("remaining")? > 0.0 {
+        return None;
+    }
+
+    let reset = header("reset")?;
+    if reset > 365.0 * 24.0 * 3600.0 {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("Clock may have gone backwards")
+            .as_secs_f64();
+        seconds(reset - now)
+    } else {
+        seconds(reset)
+    }
+}
+
+/// Convert the seconds to a duration up to `MAX_WAIT`, negative values are zero and infinite values are ignored.
+fn seconds(val: f64) -> Option<Duration> {
+    if !val.is_finite() {
+        return None;
+    }
+    Some(Duration::try_from_secs_f64(val.max(0.0)).map_or(MAX_WAIT, |val| val.min(MAX_WAIT)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn limiter_should_throttle_by_rate_and_burst() {
+        let now = Instant::now();
+        let mut limiter = Limiter::new(Some(2.0), 2, now);
+
+        assert_eq!(limiter.acquire(now), None);
+        assert_eq!(limiter.acquire(now), None);
+        assert_eq!(limiter.acquire(now), Some(Duration::from_millis(500)));
+        assert_eq!(limiter.acquire(now + Duration::from_millis(500)), None);
+    }
+
+    #[test]
+    fn limiter_should_cap_wait_of_tiny_rate() {
+        let now = Instant::now();
+        let mut limiter = Limiter::new(Some(1e-300), 1, now);
+
+        assert_eq!(limiter.acquire(now), None);
+        assert_eq!(limiter.acquire(now), Some(MAX_WAIT));
+    }
+
+    #[test]
+    fn limiter_should_wait_until_unblocked() {
+        let now = Instant::now();
+        let mut limiter = Limiter::new(None, 1, now);
+        limiter.blocked_until = Some(now + Duration::from_secs(10));
+
+        assert_eq!(limiter.acquire(now), Some(Duration::from_secs(10)));
+        assert_eq!(limiter.acquire(now + Duration::from_secs(10)), None);
+    }
+
+    #[test]
+    fn retry_after_should_support_seconds_and_date() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert("retry-after", HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+
+        headers.insert("retry-after", HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn quota_reset_should_support_seconds_and_timestamp() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("3"));
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("30"));
+        assert_eq!(quota_reset(&headers), None);
+
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("0"));
+        assert_eq!(quota_reset(&headers), Some(Duration::from_secs(30)));
+
+        let reset = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60;
+        headers.insert("x-ratelimit-reset", HeaderValue::from_str(&reset.to_string()).unwrap());
+        let wait = quota_reset(&headers).unwrap();
+        assert!(wait > Duration::from_secs(55) && wait <= Duration::from_secs(60));
+
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("inf"));
+        assert_eq!(quota_reset(&headers), None);
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("1e300"));
+        assert_eq!(quota_reset(&headers), Some(MAX_WAIT));
+    }
+}
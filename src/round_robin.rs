@@ -0,0 +1,110 @@
+//! Publish the public IPs of all healthy WANs to one hostname as multi-value records, the IP of a WAN is dropped
+//! from the records when its detection fails.
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+use tokio::{sync::watch, time};
+
+use super::control;
+use super::dns_provider::{self, ConfigDnsTarget};
+use super::http::HttpClient;
+use super::shutdown;
+use super::util::{info_style, warn_style};
+
+/// The current public IP of each WAN, `None` means the detection of the WAN failed.
+pub type WanIps = HashMap<String, Option<IpAddr>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigRoundRobin {
+    /// The names of WANs whose IPs are published.
+    pub wan: Vec<String>,
+
+    #[serde(flatten)]
+    pub dns: ConfigDnsTarget,
+}
+
+/// Collect the IPs of the healthy WANs, the result is sorted so it could be compared.
+pub fn healthy_ips(conf: &ConfigRoundRobin, wan_ips: &WanIps) -> Vec<IpAddr> {
+    let mut ips = conf
+        .wan
+        .iter()
+        .filter_map(|name| wan_ips.get(name).cloned().flatten())
+        .collect::<Vec<_>>();
+    ips.sort();
+    ips.dedup();
+    ips
+}
+
+/// Keep the multi-value records in sync with the IPs of healthy WANs.
+///
+/// The records are updated whenever a WAN reports a change, failed updates are retried every `interval` seconds.
+pub async fn run(conf: ConfigRoundRobin, client: HttpClient, mut receiver: watch::Receiver<WanIps>, interval: u32) {
+    let mut last_published: Option<Vec<IpAddr>> = None;
+    let mut timer = time::interval(Duration::from_secs(interval as u64));
+    let mut trigger = control::subscribe();
+    loop {
+        let force = tokio::select! {
+            ret = receiver.changed() => {
+                if ret.is_err() {
+                    return;
+                }
+                false
+            }
+            _ = timer.tick() => false,
+            Some(force) = trigger.changed() => force,
+            _ = shutdown::wait() => return,
+        };
+        if control::is_paused() {
+            debug!("Updates are paused, skip the multi-value records.");
+            continue;
+        }
+
+        let ips = healthy_ips(&conf, &receiver.borrow_and_update());
+        if last_published.as_ref() == Some(&ips) && !force {
+            continue;
+        }
+        if ips.is_empty() {
+            if last_published.is_none() {
+                continue;
+            }
+            // Keep the records as they are, stale IPs are better than an unresolvable hostname.
+            warn!(
+                "{}",
+                warn_style("No WAN is healthy, keep the multi-value records unchanged.")
+            );
+            continue;
+        }
+
+        debug!(
+            "Publishing multi-value records [{}] ...",
+            info_style(ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(", "))
+        );
+        if dns_provider::update_dns_set_for_all(&conf.dns, &client, &ips).await {
+            last_published = Some(ips);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn healthy_ips_should_skip_failed_and_unknown_wans() {
+        let conf = ConfigRoundRobin {
+            wan: vec![String::from("wan1"), String::from("wan2"), String::from("wan3")],
+            dns: ConfigDnsTarget::default(),
+        };
+        let mut wan_ips = WanIps::new();
+        wan_ips.insert(String::from("wan2"), "2.2.2.2".parse().ok());
+        wan_ips.insert(String::from("wan1"), "1.1.1.1".parse().ok());
+        wan_ips.insert(String::from("wan3"), None);
+        wan_ips.insert(String::from("wan4"), "4.4.4.4".parse().ok());
+
+        assert_eq!(
+            healthy_ips(&conf, &wan_ips),
+            vec!["1.1.1.1".parse::<IpAddr>().unwrap(), "2.2.2.2".parse().unwrap()]
+        );
+    }
+}
@@ -0,0 +1,44 @@
+//! Graceful shutdown, the tasks stop at their next round once a shutdown is triggered, so in-flight updates are not
+//! interrupted between the requests of a provider.
+
+use lazy_static::lazy_static;
+use tokio::sync::watch;
+
+lazy_static! {
+    static ref SHUTDOWN: watch::Sender<bool> = watch::channel(false).0;
+}
+
+/// Ask all tasks to stop.
+pub fn trigger() {
+    SHUTDOWN.send_replace(true);
+}
+
+pub fn is_shutting_down() -> bool {
+    *SHUTDOWN.borrow()
+}
+
+/// Wait until a shutdown is triggered.
+pub async fn wait() {
+    let mut receiver = SHUTDOWN.subscribe();
+    // The sender lives in a static, so the channel is never closed.
+    let _ = receiver.wait_for(|val| *val).await;
+}
+
+/// Wait for SIGTERM or SIGINT, return the name of the signal.
+pub async fn signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Listening for SIGTERM should not fail.");
+        tokio::select! {
+            _ = terminate.recv() => "SIGTERM",
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
@@ -0,0 +1,139 @@
+//! The state which survives restarts, currently the last published IP of each job and the targets it is published
+//! to.
+//!
+//! It is kept in memory and written to `state_file` on exit, then the jobs skip publishing the same IP again after
+//! a restart, unless a target did not get it.
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error, fs, io::ErrorKind, net::IpAddr, path::Path, sync::Mutex};
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// The last published IP of each job, keyed by the name of WAN or `default`.
+    #[serde(default)]
+    published: HashMap<String, Published>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Published {
+    ip: IpAddr,
+    updated_at: DateTime<Utc>,
+    /// The keys of the targets which the IP is published to.
+    #[serde(default)]
+    targets: Vec<String>,
+}
+
+impl State {
+    /// Read the state from the file, it is empty if the file does not exist yet.
+    fn read(path: &str) -> Result<State, Box<dyn Error>> {
+        let content = match fs::read_to_string(path) {
+            Ok(val) => val,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(State::default()),
+            Err(err) => return Err(format!("Read state file {} failed. (error: {})", path, err).into()),
+        };
+        Ok(serde_json::from_str(&content).map_err(|e| format!("Parse state file {} failed. (error: {})", path, e))?)
+    }
+
+    /// Write the state to the file, a temporary file is renamed to it so the file is never half written.
+    fn write(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let content = serde_json::to_string_pretty(self)?;
+        let temp_path = Path::new(path).with_extension("tmp");
+        fs::write(&temp_path, content)
+            .map_err(|e| format!("Write state file {} failed. (error: {})", temp_path.display(), e))?;
+        fs::rename(&temp_path, path).map_err(|e| format!("Write state file {} failed. (error: {})", path, e))?;
+        Ok(())
+    }
+
+    /// The IP is only treated as published if every target has got it, so a newly added target is updated.
+    fn published(&self, key: &str, targets: &[String]) -> Option<IpAddr> {
+        self.published
+            .get(key)
+            .filter(|val| targets.iter().all(|target| val.targets.contains(target)))
+            .map(|val| val.ip)
+    }
+
+    fn set_published(&mut self, key: &str, ip: IpAddr, targets: Vec<String>) {
+        self.published.insert(
+            key.to_owned(),
+            Published {
+                ip,
+                updated_at: Utc::now(),
+                targets,
+            },
+        );
+    }
+}
+
+/// Load the state file, it is fine if the file does not exist yet.
+pub fn load(path: &str) -> Result<(), Box<dyn Error>> {
+    let state = State::read(path)?;
+    *STATE.lock().expect("The lock of state should not be poisoned.") = state;
+    Ok(())
+}
+
+/// Write the state to the file.
+pub fn flush(path: &str) -> Result<(), Box<dyn Error>> {
+    STATE
+        .lock()
+        .expect("The lock of state should not be poisoned.")
+        .write(path)
+}
+
+/// The last published IP of the job, `targets` are the keys of its targets.
+pub fn published(key: &str, targets: &[String]) -> Option<IpAddr> {
+    STATE
+        .lock()
+        .expect("The lock of state should not be poisoned.")
+        .published(key, targets)
+}
+
+/// The last published IP of the job and when it was published.
+pub fn published_at(key: &str) -> Option<(IpAddr, DateTime<Utc>)> {
+    STATE
+        .lock()
+        .expect("The lock of state should not be poisoned.")
+        .published
+        .get(key)
+        .map(|val| (val.ip, val.updated_at))
+}
+
+pub fn set_published(key: &str, ip: IpAddr, targets: Vec<String>) {
+    STATE
+        .lock()
+        .expect("The lock of state should not be poisoned.")
+        .set_published(key, ip, targets);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn state_should_survive_write_and_read() {
+        let path = env::temp_dir().join(format!("dwd-state-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert!(State::read(path).unwrap().published.is_empty());
+
+        let targets = vec![String::from("a")];
+        let mut state = State::default();
+        state.set_published("default", "1.1.1.1".parse().unwrap(), targets.clone());
+        state.write(path).unwrap();
+
+        let state = State::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(state.published("default", &targets), "1.1.1.1".parse().ok());
+        // A target added while it was down has not got the IP.
+        assert_eq!(
+            state.published("default", &[String::from("a"), String::from("b")]),
+            None
+        );
+        assert_eq!(state.published("wan1", &targets), None);
+    }
+}
@@ -0,0 +1,109 @@
+//! Run the work in supervised tasks, so a panic inside a provider only fails that provider instead of killing DWD.
+
+use log::{error, warn};
+use std::{any::Any, future::Future, time::Duration};
+use tokio::{task, time};
+
+use super::shutdown;
+use super::util::error_style;
+
+const INITIAL_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+/// The backoff is reset if the task has run longer than this before it dies.
+const HEALTHY_RUN: Duration = Duration::from_secs(600);
+
+/// Run the future in its own task, return `None` if it panics.
+pub async fn catch_panic<F, T>(name: &str, future: F) -> Option<T>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    match task::spawn(future).await {
+        Ok(val) => Some(val),
+        Err(err) if err.is_panic() => {
+            error!(
+                target: "error",
+                "{} panicked: {}",
+                name,
+                error_style(panic_message(&err.into_panic()))
+            );
+            None
+        }
+        Err(err) => {
+            error!(target: "error", "{} is cancelled: {}", name, err);
+            None
+        }
+    }
+}
+
+/// Keep the task running, it is restarted with exponential backoff whenever it panics.
+///
+/// A task which returns normally is considered finished, and no task is restarted during shutdown.
+pub async fn supervise<F, Fut>(name: String, factory: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    supervise_with_backoff(name, INITIAL_RESTART_BACKOFF, factory).await
+}
+
+async fn supervise_with_backoff<F, Fut>(name: String, initial_backoff: Duration, factory: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut backoff = initial_backoff;
+    loop {
+        let started_at = time::Instant::now();
+        if catch_panic(&name, factory()).await.is_some() || shutdown::is_shutting_down() {
+            return;
+        }
+
+        if started_at.elapsed() > HEALTHY_RUN {
+            backoff = initial_backoff;
+        }
+        warn!("Restarting {} in {}s ...", name, backoff.as_secs_f32());
+        time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+    }
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.to_owned()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn catch_panic_should_return_none_for_panic() {
+        assert_eq!(catch_panic("ok", async { 1 }).await, Some(1));
+        assert_eq!(catch_panic("panic", async { panic!("boom") }).await, None::<()>);
+    }
+
+    #[tokio::test]
+    async fn supervise_should_restart_panicked_task() {
+        let count = Arc::new(AtomicU32::new(0));
+        let counter = count.clone();
+        supervise_with_backoff(String::from("task"), Duration::from_millis(1), move || {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                    panic!("boom");
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+}
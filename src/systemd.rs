@@ -0,0 +1,337 @@
+//! The integration with systemd.
+//!
+//! With `Type=notify`, READY=1 is sent after all jobs finish their first cycle, STATUS= tells the current IP and
+//! WATCHDOG=1 is sent periodically as long as no loop hangs. When the output is connected to the journal, logs are
+//! sent to journald natively with structured fields instead of the colored text format.
+
+use lazy_static::lazy_static;
+use log::{kv, Level, LevelFilter, Log, Metadata, Record};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    io::{self, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::time;
+
+use super::util::warn_style;
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// A loop is considered hung if it does not finish a cycle in twice of its interval plus this.
+const HUNG_CYCLE_SLACK: Duration = Duration::from_secs(300);
+
+lazy_static! {
+    static ref READINESS: Mutex<Readiness> = Mutex::new(Readiness::default());
+    /// The last time each loop started or finished a cycle, and how long it may take before it is considered hung.
+    static ref HEARTBEATS: Mutex<HashMap<String, (Instant, Duration)>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Default)]
+struct Readiness {
+    expected: usize,
+    cycled: HashSet<String>,
+    notified: bool,
+}
+
+/// Send a state to the service manager, it does nothing if DWD is not started by systemd with `Type=notify`.
+pub fn notify(state: &str) {
+    if let Some(path) = env::var_os("NOTIFY_SOCKET") {
+        if let Err(err) = send_notify(&path, state) {
+            log::debug!("Notify systemd with {:?} failed: {}", state, err);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn send_notify(path: &std::ffi::OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        // The socket is in the abstract namespace.
+        Some(name) => {
+            #[cfg(target_os = "linux")]
+            {
+                use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+                socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+            }
+            #[cfg(not(target_os = "linux"))]
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Abstract socket {:?} is not supported.", name),
+            ));
+        }
+        None => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send_notify(_path: &std::ffi::OsStr, _state: &str) -> io::Result<()> {
+    Ok(())
+}
+
+pub fn status(status: &str) {
+    notify(&format!("STATUS={}", status));
+}
+
+/// Set how many loops should finish their first cycle before DWD is ready.
+pub fn expect_first_cycles(count: usize) {
+    READINESS
+        .lock()
+        .expect("The lock of readiness should not be poisoned.")
+        .expected = count;
+}
+
+/// Report that a loop starts, so it is considered hung if its first cycle does not finish in time either.
+pub fn loop_started(key: &str, interval: u32) {
+    heartbeat(key, interval);
+}
+
+/// Report that a loop finished a cycle, the interval is used to tell whether the loop hangs.
+pub fn cycle_done(key: &str, interval: u32) {
+    heartbeat(key, interval);
+    mark_cycled(key);
+}
+
+/// Report that a loop exits before its first cycle, so DWD does not wait for it to be ready.
+pub fn loop_exited(key: &str, reason: &str) {
+    HEARTBEATS
+        .lock()
+        .expect("The lock of heartbeats should not be poisoned.")
+        .remove(key);
+    status(reason);
+    mark_cycled(key);
+}
+
+fn heartbeat(key: &str, interval: u32) {
+    let allowance = Duration::from_secs(interval as u64 * 2) + HUNG_CYCLE_SLACK;
+    HEARTBEATS
+        .lock()
+        .expect("The lock of heartbeats should not be poisoned.")
+        .insert(key.to_owned(), (Instant::now(), allowance));
+}
+
+fn mark_cycled(key: &str) {
+    let mut readiness = READINESS.lock().expect("The lock of readiness should not be poisoned.");
+    readiness.cycled.insert(key.to_owned());
+    if !readiness.notified && readiness.cycled.len() >= readiness.expected {
+        readiness.notified = true;
+        notify("READY=1");
+    }
+}
+
+/// The interval to send WATCHDOG=1, it is half of the timeout configured by `WatchdogSec=`.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Send WATCHDOG=1 periodically, it stops once any loop hangs, then systemd restarts DWD.
+pub async fn run_watchdog(interval: Duration) {
+    let mut timer = time::interval(interval);
+    loop {
+        timer.tick().await;
+        match hung_loop(Instant::now()) {
+            Some(key) => log::warn!(
+                "{}",
+                warn_style(format!("{} seems hung, stop pinging the watchdog.", key))
+            ),
+            None => notify("WATCHDOG=1"),
+        }
+    }
+}
+
+fn hung_loop(now: Instant) -> Option<String> {
+    HEARTBEATS
+        .lock()
+        .expect("The lock of heartbeats should not be poisoned.")
+        .iter()
+        .find(|(_, (last, allowance))| now.duration_since(*last) > *allowance)
+        .map(|(key, _)| key.to_owned())
+}
+
+/// Whether the output is connected to the journal, systemd sets `JOURNAL_STREAM` in this case.
+pub fn is_journald() -> bool {
+    env::var_os("JOURNAL_STREAM").is_some() && std::path::Path::new(JOURNALD_SOCKET).exists()
+}
+
+/// Log to journald natively, the key-values of a record become the fields of the journal entry, e.g. `PROVIDER`,
+/// `RECORD` and `IP`.
+pub struct JournalLogger {
+    level: LevelFilter,
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+}
+
+impl JournalLogger {
+    #[cfg(unix)]
+    pub fn new(level: LevelFilter) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET)?;
+        Ok(JournalLogger { level, socket })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(_level: LevelFilter) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "journald is not supported."))
+    }
+}
+
+impl Log for JournalLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let target = metadata.target();
+        metadata.level() <= self.level && (target.starts_with("dwd") || target == "error" || target == "success")
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let payload = journal_payload(record);
+        #[cfg(unix)]
+        if self.socket.send(&payload).is_ok() {
+            return;
+        }
+        // Fallback to stderr, e.g. the entry is too large for a datagram.
+        let _ = writeln!(io::stderr(), "[{:<5}] {}", record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+fn journal_payload(record: &Record) -> Vec<u8> {
+    let priority = match record.level() {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    };
+
+    let mut payload = Vec::new();
+    append_field(&mut payload, "PRIORITY", priority);
+    append_field(&mut payload, "SYSLOG_IDENTIFIER", "dwd");
+    append_field(
+        &mut payload,
+        "MESSAGE",
+        &console::strip_ansi_codes(&record.args().to_string()),
+    );
+    if let Some(module) = record.module_path() {
+        append_field(&mut payload, "CODE_MODULE", module);
+    }
+    if record.target() == "success" || record.target() == "error" {
+        append_field(&mut payload, "DWD_RESULT", record.target());
+    }
+
+    struct Fields<'a>(&'a mut Vec<u8>);
+    impl<'kvs> kv::VisitSource<'kvs> for Fields<'_> {
+        fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+            let name = key
+                .as_str()
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>();
+            append_field(self.0, name.trim_start_matches('_'), &value.to_string());
+            Ok(())
+        }
+    }
+    let _ = record.key_values().visit(&mut Fields(&mut payload));
+
+    payload
+}
+
+/// Encode a field in the native journal protocol, the values with newlines are prefixed by their length.
+fn append_field(payload: &mut Vec<u8>, name: &str, value: &str) {
+    payload.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        payload.push(b'\n');
+        payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        payload.push(b'=');
+    }
+    payload.extend_from_slice(value.as_bytes());
+    payload.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_payload_should_contain_structured_fields() {
+        let kvs: &[(&str, &str)] = &[("provider", "name.com"), ("ip", "1.1.1.1")];
+        let payload = journal_payload(
+            &Record::builder()
+                .level(Level::Error)
+                .target("error")
+                .module_path(Some("dwd::job"))
+                .key_values(&kvs)
+                .args(format_args!("Update failed"))
+                .build(),
+        );
+
+        assert_eq!(
+            String::from_utf8(payload).unwrap(),
+            "PRIORITY=3\nSYSLOG_IDENTIFIER=dwd\nMESSAGE=Update failed\nCODE_MODULE=dwd::job\nDWD_RESULT=error\n\
+             PROVIDER=name.com\nIP=1.1.1.1\n"
+        );
+    }
+
+    #[test]
+    fn append_field_should_encode_multiline_value() {
+        let mut payload = Vec::new();
+        append_field(&mut payload, "MESSAGE", "a\nb");
+        assert_eq!(payload, b"MESSAGE\n\x03\0\0\0\0\0\0\0a\nb\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn send_notify_should_write_state_to_socket() {
+        use std::os::unix::net::UnixDatagram;
+
+        let path = env::temp_dir().join(format!("dwd-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        send_notify(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn hung_loop_should_find_stale_heartbeat() {
+        // A loop which hangs in its first cycle is found as well.
+        loop_started("hung-test", 1);
+        let now = Instant::now();
+        assert_eq!(hung_loop(now), None);
+        assert_eq!(
+            hung_loop(now + HUNG_CYCLE_SLACK + Duration::from_secs(3)),
+            Some(String::from("hung-test"))
+        );
+
+        cycle_done("hung-test", 1);
+        let now = Instant::now();
+        assert_eq!(hung_loop(now), None);
+        assert_eq!(
+            hung_loop(now + HUNG_CYCLE_SLACK + Duration::from_secs(3)),
+            Some(String::from("hung-test"))
+        );
+    }
+}
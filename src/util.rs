@@ -1,10 +1,9 @@
 use console::{Style, StyledObject};
 use lazy_static::lazy_static;
-use log::trace;
 use regex::Regex;
-use reqwest::IntoUrl;
-use std::error::Error;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 lazy_static! {
     static ref ERROR: Style = Style::new().red();
@@ -19,6 +18,24 @@ lazy_static! {
     static ref IPV6_RE: Regex = Regex::new(r"i^(?:[0-9a-f]{1,4}\:){7}[0-9a-f]{1,4}$").unwrap();
 }
 
+static DECORATED: AtomicBool = AtomicBool::new(true);
+
+/// Turn off colors and emoji, e.g. when the output is not a terminal or `NO_COLOR` is set.
+pub fn disable_decorations() {
+    console::set_colors_enabled(false);
+    console::set_colors_enabled_stderr(false);
+    DECORATED.store(false, Ordering::Relaxed);
+}
+
+/// Return the emoji, or nothing if decorations are turned off.
+pub fn emoji(emoji: &'static str) -> &'static str {
+    if DECORATED.load(Ordering::Relaxed) {
+        emoji
+    } else {
+        ""
+    }
+}
+
 pub fn error_style<T>(content: T) -> StyledObject<T> {
     ERROR.apply_to(content)
 }
@@ -39,16 +56,129 @@ pub fn success_style<T>(content: T) -> StyledObject<T> {
     SUCCESS.apply_to(content)
 }
 
-pub async fn get<T: IntoUrl>(url: T) -> Result<String, Box<dyn Error>> {
-    let response = reqwest::get(url).await?;
+pub fn is_ip(ip: &str) -> bool {
+    ip.parse::<Ipv4Addr>().is_ok() || ip.parse::<Ipv6Addr>().is_ok()
+}
+
+/// The scope of an IP address, only `Global` addresses are reachable from the internet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpScope {
+    Global,
+    /// RFC 1918 private networks.
+    Private,
+    /// RFC 6598 shared address space used by carrier-grade NAT, 100.64.0.0/10.
+    Cgnat,
+    Loopback,
+    LinkLocal,
+    /// RFC 5737 and RFC 3849 documentation ranges.
+    Documentation,
+    /// RFC 4193 unique local addresses, fc00::/7.
+    UniqueLocal,
+    Multicast,
+    Unspecified,
+    /// Everything else which should never be routed on the internet, like benchmarking or reserved ranges.
+    Bogon,
+}
 
-    trace!("GET {} {}", response.url(), response.status());
+impl fmt::Display for IpScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            IpScope::Global => "global",
+            IpScope::Private => "private",
+            IpScope::Cgnat => "CGNAT",
+            IpScope::Loopback => "loopback",
+            IpScope::LinkLocal => "link-local",
+            IpScope::Documentation => "documentation",
+            IpScope::UniqueLocal => "unique local",
+            IpScope::Multicast => "multicast",
+            IpScope::Unspecified => "unspecified",
+            IpScope::Bogon => "bogon",
+        };
+        write!(f, "{}", name)
+    }
+}
 
-    response.text().await.map_err(|e| e.into())
+/// Classify an IP address by the IANA special-purpose address registries.
+pub fn ip_scope(ip: &IpAddr) -> IpScope {
+    match ip {
+        IpAddr::V4(ip) => ipv4_scope(ip),
+        IpAddr::V6(ip) => ipv6_scope(ip),
+    }
 }
 
-pub fn is_ip(ip: &str) -> bool {
-    ip.parse::<Ipv4Addr>().is_ok() || ip.parse::<Ipv6Addr>().is_ok()
+fn ipv4_scope(ip: &Ipv4Addr) -> IpScope {
+    let [a, b, c, _] = ip.octets();
+    match (a, b, c) {
+        _ if ip.is_unspecified() => IpScope::Unspecified,
+        (0, _, _) => IpScope::Bogon,
+        (10, _, _) | (172, 16..=31, _) | (192, 168, _) => IpScope::Private,
+        (100, 64..=127, _) => IpScope::Cgnat,
+        (127, _, _) => IpScope::Loopback,
+        (169, 254, _) => IpScope::LinkLocal,
+        (192, 0, 2) | (198, 51, 100) | (203, 0, 113) => IpScope::Documentation,
+        (192, 0, 0) | (192, 88, 99) | (198, 18..=19, _) => IpScope::Bogon,
+        (224..=239, _, _) => IpScope::Multicast,
+        (240..=255, _, _) => IpScope::Bogon,
+        _ => IpScope::Global,
+    }
+}
+
+fn ipv6_scope(ip: &Ipv6Addr) -> IpScope {
+    if let Some(ipv4) = ip.to_ipv4_mapped() {
+        return ipv4_scope(&ipv4);
+    }
+
+    let segments = ip.segments();
+    match segments[0] {
+        _ if ip.is_unspecified() => IpScope::Unspecified,
+        _ if ip.is_loopback() => IpScope::Loopback,
+        0xfe80..=0xfebf => IpScope::LinkLocal,
+        0xfc00..=0xfdff => IpScope::UniqueLocal,
+        0xff00..=0xffff => IpScope::Multicast,
+        0x2001 if segments[1] == 0x0db8 => IpScope::Documentation,
+        // 2001::/23 is reserved for IETF protocol assignments like Teredo and benchmarking.
+        0x2001 if segments[1] < 0x0200 => IpScope::Bogon,
+        // Only 2000::/3 is allocated as global unicast for now.
+        0x2000..=0x3fff => IpScope::Global,
+        _ => IpScope::Bogon,
+    }
+}
+
+/// Get the address of the interface which is used to reach the internet.
+///
+/// No packet is sent here, connecting a UDP socket only makes the kernel choose a route and a source address.
+pub fn local_ip_for(ip: &IpAddr) -> Option<IpAddr> {
+    let (bind, target): (SocketAddr, SocketAddr) = match ip {
+        IpAddr::V4(_) => ("0.0.0.0:0".parse().unwrap(), "1.1.1.1:53".parse().unwrap()),
+        IpAddr::V6(_) => ("[::]:0".parse().unwrap(), "[2606:4700:4700::1111]:53".parse().unwrap()),
+    };
+
+    let socket = UdpSocket::bind(bind).ok()?;
+    socket.connect(target).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// Compare the interface address with the public address, and explain what kind of NAT is between them.
+///
+/// Return `None` when the host owns the public address directly or behind an ordinary home router.
+pub fn detect_nat(local: &IpAddr, public: &IpAddr) -> Option<String> {
+    if local == public {
+        return None;
+    }
+
+    match ip_scope(local) {
+        IpScope::Cgnat => Some(format!(
+            "The interface address {} is in the CGNAT shared address space, the ISP is translating it to {}, \
+             inbound connections to the public IP will probably not reach this host.",
+            local, public
+        )),
+        IpScope::Global => Some(format!(
+            "The interface address {} is public but the traffic leaves through {}, \
+             there is another NAT or a proxy in front of this host.",
+            local, public
+        )),
+        _ => None,
+    }
 }
 
 #[cfg(test)]
@@ -69,6 +199,51 @@ mod tests {
         assert!(is_ip("2001:DB8:2de::e13"));
     }
 
+    #[test]
+    fn ip_scope_should_classify_ipv4() {
+        let scope = |ip: &str| ip_scope(&ip.parse().unwrap());
+        assert_eq!(scope("1.1.1.1"), IpScope::Global);
+        assert_eq!(scope("10.0.0.1"), IpScope::Private);
+        assert_eq!(scope("172.31.255.255"), IpScope::Private);
+        assert_eq!(scope("172.32.0.1"), IpScope::Global);
+        assert_eq!(scope("192.168.1.1"), IpScope::Private);
+        assert_eq!(scope("100.64.0.1"), IpScope::Cgnat);
+        assert_eq!(scope("100.127.255.255"), IpScope::Cgnat);
+        assert_eq!(scope("100.128.0.1"), IpScope::Global);
+        assert_eq!(scope("127.0.0.1"), IpScope::Loopback);
+        assert_eq!(scope("169.254.1.1"), IpScope::LinkLocal);
+        assert_eq!(scope("203.0.113.1"), IpScope::Documentation);
+        assert_eq!(scope("198.18.0.1"), IpScope::Bogon);
+        assert_eq!(scope("0.1.2.3"), IpScope::Bogon);
+        assert_eq!(scope("0.0.0.0"), IpScope::Unspecified);
+        assert_eq!(scope("224.0.0.1"), IpScope::Multicast);
+        assert_eq!(scope("255.255.255.255"), IpScope::Bogon);
+    }
+
+    #[test]
+    fn ip_scope_should_classify_ipv6() {
+        let scope = |ip: &str| ip_scope(&ip.parse().unwrap());
+        assert_eq!(scope("2400:3200::1"), IpScope::Global);
+        assert_eq!(scope("::1"), IpScope::Loopback);
+        assert_eq!(scope("::"), IpScope::Unspecified);
+        assert_eq!(scope("fe80::1"), IpScope::LinkLocal);
+        assert_eq!(scope("fd00::1"), IpScope::UniqueLocal);
+        assert_eq!(scope("2001:db8::1"), IpScope::Documentation);
+        assert_eq!(scope("2001::1"), IpScope::Bogon);
+        assert_eq!(scope("ff02::1"), IpScope::Multicast);
+        assert_eq!(scope("::ffff:10.0.0.1"), IpScope::Private);
+        assert_eq!(scope("4000::1"), IpScope::Bogon);
+    }
+
+    #[test]
+    fn detect_nat_should_warn_for_cgnat_and_double_nat() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        assert!(detect_nat(&ip("1.1.1.1"), &ip("1.1.1.1")).is_none());
+        assert!(detect_nat(&ip("192.168.1.2"), &ip("1.1.1.1")).is_none());
+        assert!(detect_nat(&ip("100.64.1.2"), &ip("1.1.1.1")).unwrap().contains("CGNAT"));
+        assert!(detect_nat(&ip("8.8.8.8"), &ip("1.1.1.1")).is_some());
+    }
+
     #[test]
     fn is_ip_should_failed_for_invalid_ip_string() {
         assert!(!is_ip("hello world"));